@@ -0,0 +1,345 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use anyhow::Context;
+
+use crate::metadata::cluster::CorruptBatchPolicy;
+
+static CONFIG: OnceLock<ServerConfig> = OnceLock::new();
+
+// Defaults matching the layout the broker always used
+static DEFAULT_LOG_DIR: &str = "/tmp/kraft-combined-logs";
+static DEFAULT_LISTENERS: &str = "PLAINTEXT://127.0.0.1:9092";
+static DEFAULT_ADVERTISED_LISTENERS: &str = "PLAINTEXT://localhost:9092";
+static DEFAULT_NODE_ID: i32 = 1;
+// What clients are told to connect to when a listener binds every interface
+static DEFAULT_ADVERTISED_HOST: &str = "localhost";
+// Same defaults as Kafka's `max.incremental.fetch.session.cache.slots` and
+// `min.incremental.fetch.session.eviction.ms`
+static DEFAULT_FETCH_SESSION_CACHE_SLOTS: usize = 1000;
+static DEFAULT_FETCH_SESSION_EVICTION_MS: u64 = 120_000;
+// Same defaults as Kafka's `log.segment.bytes` and `log.roll.hours`
+static DEFAULT_LOG_SEGMENT_BYTES: usize = 1024 * 1024 * 1024;
+static DEFAULT_LOG_ROLL_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+// Same default as Kafka's `socket.request.max.bytes`
+static DEFAULT_SOCKET_REQUEST_MAX_BYTES: usize = 100 * 1024 * 1024;
+
+/// A `name://host:port` entry of `listeners` or `advertised.listeners`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Listener {
+    pub name: String,
+    // empty binds every interface
+    pub host: String,
+    pub port: u16,
+}
+
+impl Listener {
+    pub fn bind_address(&self) -> String {
+        match self.host.as_str() {
+            "" => format!("0.0.0.0:{}", self.port),
+            host if host.contains(':') => format!("[{}]:{}", host, self.port),
+            host => format!("{}:{}", host, self.port),
+        }
+    }
+}
+
+impl std::str::FromStr for Listener {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, address) = s
+            .split_once("://")
+            .ok_or_else(|| anyhow::anyhow!("expected name://host:port, got {s}"))?;
+        let (host, port) = address
+            .rsplit_once(':')
+            .ok_or_else(|| anyhow::anyhow!("missing port in {s}"))?;
+        Ok(Listener {
+            name: name.to_string(),
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port: port.parse().with_context(|| format!("bad port in {s}"))?,
+        })
+    }
+}
+
+/// The subset of Kafka's broker configuration this broker understands.
+/// Other keys of a `server.properties` file are ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerConfig {
+    pub node_id: i32,
+    // partitions are spread over the directories, see `storage::partition_dir`
+    pub log_dirs: Vec<PathBuf>,
+    // defaults to the first of `log_dirs`
+    pub metadata_log_dir: Option<PathBuf>,
+    pub listeners: Vec<Listener>,
+    // defaults to `listeners`
+    pub advertised_listeners: Option<Vec<Listener>>,
+    // most incremental fetch sessions cached at once
+    pub fetch_session_cache_slots: usize,
+    // how long a session is kept from eviction after its last use
+    pub fetch_session_eviction_ms: u64,
+    // segment size and age at which partition logs roll, unless the topic
+    // sets `segment.bytes` or `segment.ms`
+    pub log_segment_bytes: usize,
+    pub log_roll_ms: i64,
+    // largest request a client may send, bigger ones close the connection
+    pub socket_request_max_bytes: usize,
+    // `--corrupt-metadata-batches=skip|stop`, how a corrupt metadata log
+    // batch is handled
+    pub corrupt_metadata_batches: CorruptBatchPolicy,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            node_id: DEFAULT_NODE_ID,
+            log_dirs: vec![PathBuf::from(DEFAULT_LOG_DIR)],
+            metadata_log_dir: None,
+            listeners: parse_listeners(DEFAULT_LISTENERS).expect("valid default listeners"),
+            advertised_listeners: Some(
+                parse_listeners(DEFAULT_ADVERTISED_LISTENERS).expect("valid default listeners"),
+            ),
+            fetch_session_cache_slots: DEFAULT_FETCH_SESSION_CACHE_SLOTS,
+            fetch_session_eviction_ms: DEFAULT_FETCH_SESSION_EVICTION_MS,
+            log_segment_bytes: DEFAULT_LOG_SEGMENT_BYTES,
+            log_roll_ms: DEFAULT_LOG_ROLL_MS,
+            socket_request_max_bytes: DEFAULT_SOCKET_REQUEST_MAX_BYTES,
+            corrupt_metadata_batches: CorruptBatchPolicy::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Builds the config from the command line: an optional
+    /// `server.properties` path, then `--override key=value` pairs that win
+    /// over the file, like `kafka-server-start.sh`. Unknown `--` options are
+    /// rejected.
+    pub fn from_args(args: &[String]) -> anyhow::Result<ServerConfig> {
+        let mut properties = HashMap::new();
+        let mut overrides = Vec::new();
+        let mut corrupt_metadata_batches = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--override" {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("--override needs a key=value"))?;
+                overrides.push(value.clone());
+            } else if let Some(policy) = arg.strip_prefix("--corrupt-metadata-batches=") {
+                corrupt_metadata_batches = Some(
+                    policy
+                        .parse()
+                        .context("invalid --corrupt-metadata-batches")?,
+                );
+            } else if arg.starts_with("--") {
+                anyhow::bail!("unknown option {arg}");
+            } else {
+                let content = std::fs::read_to_string(arg)
+                    .with_context(|| format!("reading config file {arg}"))?;
+                properties.extend(parse_properties(&content));
+            }
+        }
+        for entry in overrides {
+            let (key, value) = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("expected key=value, got {entry}"))?;
+            properties.insert(key.trim().to_string(), value.trim().to_string());
+        }
+        let mut config = ServerConfig::from_properties(&properties)?;
+        if let Some(policy) = corrupt_metadata_batches {
+            config.corrupt_metadata_batches = policy;
+        }
+        Ok(config)
+    }
+
+    pub fn from_properties(properties: &HashMap<String, String>) -> anyhow::Result<ServerConfig> {
+        let mut config = ServerConfig::default();
+        if let Some(node_id) = properties.get("node.id") {
+            config.node_id = node_id
+                .parse()
+                .with_context(|| format!("bad node.id {node_id}"))?;
+        }
+        // `log.dir` is the single directory form, `log.dirs` wins over it
+        if let Some(dirs) = properties.get("log.dirs").or(properties.get("log.dir")) {
+            config.log_dirs = split_list(dirs).map(PathBuf::from).collect();
+            if config.log_dirs.is_empty() {
+                anyhow::bail!("log.dirs is empty");
+            }
+        }
+        if let Some(dir) = properties.get("metadata.log.dir") {
+            config.metadata_log_dir = Some(PathBuf::from(dir));
+        }
+        if let Some(listeners) = properties.get("listeners") {
+            config.listeners = parse_listeners(listeners)?;
+            // advertise the new listeners unless told otherwise
+            config.advertised_listeners = None;
+        }
+        if let Some(listeners) = properties.get("advertised.listeners") {
+            config.advertised_listeners = Some(parse_listeners(listeners)?);
+        }
+        if config.listeners.is_empty() {
+            anyhow::bail!("listeners is empty");
+        }
+        if let Some(slots) = properties.get("max.incremental.fetch.session.cache.slots") {
+            config.fetch_session_cache_slots = slots.parse().with_context(|| {
+                format!("bad max.incremental.fetch.session.cache.slots {slots}")
+            })?;
+        }
+        if let Some(ms) = properties.get("min.incremental.fetch.session.eviction.ms") {
+            config.fetch_session_eviction_ms = ms
+                .parse()
+                .with_context(|| format!("bad min.incremental.fetch.session.eviction.ms {ms}"))?;
+        }
+        if let Some(bytes) = properties.get("log.segment.bytes") {
+            config.log_segment_bytes = bytes
+                .parse()
+                .with_context(|| format!("bad log.segment.bytes {bytes}"))?;
+        }
+        // `log.roll.ms` wins over `log.roll.hours`
+        if let Some(ms) = properties.get("log.roll.ms") {
+            config.log_roll_ms = ms
+                .parse()
+                .with_context(|| format!("bad log.roll.ms {ms}"))?;
+        } else if let Some(hours) = properties.get("log.roll.hours") {
+            let hours: i64 = hours
+                .parse()
+                .with_context(|| format!("bad log.roll.hours {hours}"))?;
+            config.log_roll_ms = hours * 60 * 60 * 1000;
+        }
+        if let Some(bytes) = properties.get("socket.request.max.bytes") {
+            config.socket_request_max_bytes = bytes
+                .parse()
+                .with_context(|| format!("bad socket.request.max.bytes {bytes}"))?;
+        }
+        Ok(config)
+    }
+
+    pub fn metadata_log_dir(&self) -> &Path {
+        self.metadata_log_dir
+            .as_deref()
+            .unwrap_or_else(|| &self.log_dirs[0])
+    }
+
+    /// The endpoint clients that connected through the listener named
+    /// `listener_name` are told to use for this broker. Like Kafka, the
+    /// advertised listener of the same name is picked, the first one when
+    /// none has it.
+    pub fn advertised_listener(&self, listener_name: &str) -> Listener {
+        let advertised = self
+            .advertised_listeners
+            .as_deref()
+            .filter(|listeners| !listeners.is_empty())
+            .unwrap_or(&self.listeners);
+        let mut listener = advertised
+            .iter()
+            .find(|listener| listener.name == listener_name)
+            .unwrap_or(&advertised[0])
+            .clone();
+        if listener.host.is_empty() {
+            listener.host = DEFAULT_ADVERTISED_HOST.to_string();
+        }
+        listener
+    }
+}
+
+/// Sets the config every module reads through [`get`]. Only the first call
+/// has an effect.
+pub fn init(config: ServerConfig) {
+    let _ = CONFIG.set(config);
+}
+
+/// The config set by [`init`], or the defaults when it was never called.
+pub fn get() -> &'static ServerConfig {
+    CONFIG.get_or_init(initial)
+}
+
+#[cfg(not(test))]
+fn initial() -> ServerConfig {
+    ServerConfig::default()
+}
+
+// Tests share a directory of their own rather than the broker's
+#[cfg(test)]
+fn initial() -> ServerConfig {
+    let dir = std::env::temp_dir().join(format!("kafka-test-{}", std::process::id()));
+    ServerConfig {
+        log_dirs: vec![dir],
+        ..ServerConfig::default()
+    }
+}
+
+/// Parses `key=value` lines of a Java properties file, skipping comments.
+pub fn parse_properties(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('!'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|s| !s.is_empty())
+}
+
+fn parse_listeners(value: &str) -> anyhow::Result<Vec<Listener>> {
+    split_list(value).map(str::parse).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_win_over_the_properties_file() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("server-{}.properties", std::process::id()));
+        std::fs::write(
+            &path,
+            "# broker\nnode.id=2\nlog.dirs=/data/a, /data/b\nlisteners=PLAINTEXT://:9093,CONTROLLER://[::1]:9094\n",
+        )?;
+        let args = [
+            path.display().to_string(),
+            "--corrupt-metadata-batches=skip".to_string(),
+            "--override".to_string(),
+            "node.id=3".to_string(),
+            "--override".to_string(),
+            "log.roll.hours=1".to_string(),
+            "--override".to_string(),
+            "socket.request.max.bytes=1048576".to_string(),
+        ];
+        let config = ServerConfig::from_args(&args)?;
+        std::fs::remove_file(&path)?;
+
+        assert_eq!(config.node_id, 3);
+        assert_eq!(
+            config.log_dirs,
+            [PathBuf::from("/data/a"), PathBuf::from("/data/b")]
+        );
+        assert_eq!(config.metadata_log_dir(), Path::new("/data/a"));
+        assert_eq!(config.listeners[0].bind_address(), "0.0.0.0:9093");
+        assert_eq!(config.listeners[1].bind_address(), "[::1]:9094");
+        assert_eq!(config.advertised_listener("PLAINTEXT").host, "localhost");
+        assert_eq!(config.advertised_listener("PLAINTEXT").port, 9093);
+        assert_eq!(config.advertised_listener("CONTROLLER").host, "::1");
+        assert_eq!(config.advertised_listener("CONTROLLER").port, 9094);
+        assert_eq!(config.advertised_listener("EXTERNAL").port, 9093);
+        assert_eq!(config.log_roll_ms, 60 * 60 * 1000);
+        assert_eq!(config.socket_request_max_bytes, 1024 * 1024);
+        assert_eq!(
+            ServerConfig::default().socket_request_max_bytes,
+            100 * 1024 * 1024
+        );
+        assert_eq!(config.log_segment_bytes, DEFAULT_LOG_SEGMENT_BYTES);
+        assert_eq!(config.corrupt_metadata_batches, CorruptBatchPolicy::Skip);
+
+        let mistyped = ["--corupt-metadata-batches=stop".to_string()];
+        assert!(ServerConfig::from_args(&mistyped).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,342 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+pub static FETCH_SESSION_ID_NOT_FOUND: i16 = 70;
+pub static INVALID_FETCH_SESSION_EPOCH: i16 = 71;
+pub static FETCH_SESSION_TOPIC_ID_ERROR: i16 = 106;
+
+// Session ids and epochs of fetch requests with a special meaning
+pub static INVALID_SESSION_ID: i32 = 0;
+pub static INITIAL_EPOCH: i32 = 0;
+pub static FINAL_EPOCH: i32 = -1;
+
+/// Identifies a partition of a session: by topic id from Fetch v13, by
+/// name before.
+pub type SessionKey = (uuid::Uuid, String, i32);
+
+/// A partition a fetch session reads, with what the client last asked
+/// for it.
+#[derive(Clone, Debug)]
+pub struct SessionPartition {
+    // nil before Fetch v13
+    pub topic_id: uuid::Uuid,
+    // empty from Fetch v13
+    pub topic: String,
+    pub partition: i32,
+    pub current_leader_epoch: i32,
+    pub fetch_offset: i64,
+    pub last_fetched_epoch: i32,
+    pub log_start_offset: i64,
+    pub partition_max_bytes: i32,
+    // what the client was last told, None before the first response
+    pub last_response: Option<PartitionResponse>,
+}
+
+impl SessionPartition {
+    pub fn key(&self) -> SessionKey {
+        (self.topic_id, self.topic.clone(), self.partition)
+    }
+
+    fn update_request(&mut self, requested: SessionPartition) {
+        self.current_leader_epoch = requested.current_leader_epoch;
+        self.fetch_offset = requested.fetch_offset;
+        self.last_fetched_epoch = requested.last_fetched_epoch;
+        self.log_start_offset = requested.log_start_offset;
+        self.partition_max_bytes = requested.partition_max_bytes;
+    }
+}
+
+/// The offsets of a partition an incremental fetch leaves out unless they
+/// changed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PartitionResponse {
+    pub high_watermark: i64,
+    pub last_stable_offset: i64,
+    pub log_start_offset: i64,
+}
+
+/// How a fetch request relates to the session cache, see KIP-227.
+#[derive(Debug)]
+pub enum FetchContext {
+    /// A full fetch outside of any session.
+    Sessionless,
+    /// A full fetch that started a session, `INVALID_SESSION_ID` when the
+    /// cache had no room for it.
+    Full { session_id: i32 },
+    /// A fetch of every partition in the session, of which only the
+    /// changed ones are returned.
+    Incremental {
+        session_id: i32,
+        partitions: Vec<SessionPartition>,
+    },
+    /// The session cannot be used, answered with this error code alone.
+    Error(i16),
+}
+
+#[derive(Debug)]
+struct FetchSession {
+    // in the order they were added, the order responses list them in
+    partitions: Vec<SessionPartition>,
+    // the epoch the next fetch of the session must carry
+    next_epoch: i32,
+    // sessions of follower brokers are only evicted when stale
+    privileged: bool,
+    uses_topic_ids: bool,
+    last_used: Instant,
+}
+
+/// The incremental fetch sessions of the broker. At most `slots` sessions
+/// are kept; when full, a new session may evict a stale one, or the
+/// smallest consumer session if it is bigger than that or from a follower.
+pub struct FetchSessionCache {
+    sessions: Mutex<HashMap<i32, FetchSession>>,
+    slots: usize,
+    eviction: Duration,
+}
+
+impl FetchSessionCache {
+    pub fn new(slots: usize, eviction: Duration) -> FetchSessionCache {
+        FetchSessionCache {
+            sessions: Mutex::new(HashMap::new()),
+            slots,
+            eviction,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<i32, FetchSession>> {
+        self.sessions
+            .lock()
+            .expect("fetch session cache lock poisoned")
+    }
+
+    /// Resolves the session of a fetch request and applies its changes:
+    /// requested partitions are added or updated and forgotten ones
+    /// removed.
+    pub fn new_context(
+        &self,
+        session_id: i32,
+        epoch: i32,
+        requested: Vec<SessionPartition>,
+        forgotten: &[SessionKey],
+        privileged: bool,
+        uses_topic_ids: bool,
+    ) -> FetchContext {
+        let now = Instant::now();
+        let mut sessions = self.lock();
+        if epoch == INITIAL_EPOCH || epoch == FINAL_EPOCH {
+            // a full fetch closes the session it names
+            if session_id != INVALID_SESSION_ID {
+                sessions.remove(&session_id);
+            }
+            if epoch == FINAL_EPOCH {
+                return FetchContext::Sessionless;
+            }
+            let session = FetchSession {
+                partitions: requested,
+                next_epoch: next_epoch(INITIAL_EPOCH),
+                privileged,
+                uses_topic_ids,
+                last_used: now,
+            };
+            let session_id = self.insert(&mut sessions, session, now);
+            return FetchContext::Full { session_id };
+        }
+
+        let Some(session) = sessions.get_mut(&session_id) else {
+            return FetchContext::Error(FETCH_SESSION_ID_NOT_FOUND);
+        };
+        if session.next_epoch != epoch {
+            return FetchContext::Error(INVALID_FETCH_SESSION_EPOCH);
+        }
+        if session.uses_topic_ids != uses_topic_ids {
+            return FetchContext::Error(FETCH_SESSION_TOPIC_ID_ERROR);
+        }
+        for partition in requested {
+            let key = partition.key();
+            match session.partitions.iter_mut().find(|p| p.key() == key) {
+                Some(cached) => cached.update_request(partition),
+                None => session.partitions.push(partition),
+            }
+        }
+        session
+            .partitions
+            .retain(|partition| !forgotten.contains(&partition.key()));
+        session.next_epoch = next_epoch(epoch);
+        session.last_used = now;
+        FetchContext::Incremental {
+            session_id,
+            partitions: session.partitions.clone(),
+        }
+    }
+
+    /// Records what a fetch of the session returned. Tells for each
+    /// partition whether an incremental response must include it: when it
+    /// has records or an error, or its offsets changed since the client
+    /// last heard of them.
+    pub fn update_responses(
+        &self,
+        session_id: i32,
+        responses: &[(SessionKey, PartitionResponse, bool)],
+    ) -> Vec<bool> {
+        let mut sessions = self.lock();
+        let Some(session) = sessions.get_mut(&session_id) else {
+            return vec![true; responses.len()];
+        };
+        responses
+            .iter()
+            .map(|(key, response, must_respond)| {
+                let Some(cached) = session.partitions.iter_mut().find(|p| p.key() == *key) else {
+                    return true;
+                };
+                let changed = cached.last_response != Some(*response);
+                cached.last_response = Some(*response);
+                *must_respond || changed
+            })
+            .collect()
+    }
+
+    // Returns the new session's id, INVALID_SESSION_ID without room for it
+    fn insert(
+        &self,
+        sessions: &mut HashMap<i32, FetchSession>,
+        session: FetchSession,
+        now: Instant,
+    ) -> i32 {
+        if sessions.len() >= self.slots
+            && !self.evict_one(sessions, session.privileged, session.partitions.len(), now)
+        {
+            return INVALID_SESSION_ID;
+        }
+        let session_id = loop {
+            let id = uuid::Uuid::new_v4().as_u128() as i32 & i32::MAX;
+            if id != INVALID_SESSION_ID && !sessions.contains_key(&id) {
+                break id;
+            }
+        };
+        sessions.insert(session_id, session);
+        session_id
+    }
+
+    fn evict_one(
+        &self,
+        sessions: &mut HashMap<i32, FetchSession>,
+        privileged: bool,
+        size: usize,
+        now: Instant,
+    ) -> bool {
+        // any session unused for the eviction time goes first
+        let stale = sessions
+            .iter()
+            .filter(|(_, session)| now.duration_since(session.last_used) >= self.eviction)
+            .min_by_key(|(_, session)| session.last_used)
+            .map(|(id, _)| *id);
+        let smallest = sessions
+            .iter()
+            .filter(|(_, session)| !session.privileged)
+            .min_by_key(|(_, session)| (session.partitions.len(), session.last_used))
+            .filter(|(_, session)| privileged || size > session.partitions.len())
+            .map(|(id, _)| *id);
+        match stale.or(smallest) {
+            Some(id) => {
+                sessions.remove(&id);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// epochs wrap around to 1, 0 starts a new session
+fn next_epoch(epoch: i32) -> i32 {
+    if epoch == i32::MAX {
+        1
+    } else {
+        epoch + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partition(index: i32, fetch_offset: i64) -> SessionPartition {
+        SessionPartition {
+            topic_id: uuid::Uuid::nil(),
+            topic: "foo".to_string(),
+            partition: index,
+            current_leader_epoch: -1,
+            fetch_offset,
+            last_fetched_epoch: -1,
+            log_start_offset: -1,
+            partition_max_bytes: 1024,
+            last_response: None,
+        }
+    }
+
+    #[test]
+    fn applies_incremental_changes_and_evicts() {
+        let cache = FetchSessionCache::new(2, Duration::from_secs(60));
+        let FetchContext::Full { session_id } = cache.new_context(
+            INVALID_SESSION_ID,
+            INITIAL_EPOCH,
+            vec![partition(0, 0), partition(1, 0)],
+            &[],
+            false,
+            false,
+        ) else {
+            panic!("expected a full fetch");
+        };
+        assert_ne!(session_id, INVALID_SESSION_ID);
+
+        let context = cache.new_context(
+            session_id,
+            1,
+            vec![partition(1, 5), partition(2, 0)],
+            &[(uuid::Uuid::nil(), "foo".to_string(), 0)],
+            false,
+            false,
+        );
+        let FetchContext::Incremental { partitions, .. } = context else {
+            panic!("expected an incremental fetch");
+        };
+        let offsets: Vec<(i32, i64)> = partitions
+            .iter()
+            .map(|p| (p.partition, p.fetch_offset))
+            .collect();
+        assert_eq!(offsets, [(1, 5), (2, 0)]);
+
+        let response = PartitionResponse {
+            high_watermark: 5,
+            last_stable_offset: 5,
+            log_start_offset: 0,
+        };
+        let responses = [(partitions[0].key(), response, false)];
+        assert_eq!(cache.update_responses(session_id, &responses), [true]);
+        assert_eq!(cache.update_responses(session_id, &responses), [false]);
+
+        assert!(matches!(
+            cache.new_context(session_id, 1, vec![], &[], false, false),
+            FetchContext::Error(e) if e == INVALID_FETCH_SESSION_EPOCH
+        ));
+        assert!(matches!(
+            cache.new_context(session_id + 1, 2, vec![], &[], false, false),
+            FetchContext::Error(e) if e == FETCH_SESSION_ID_NOT_FOUND
+        ));
+
+        // a second session fills the cache, a smaller third one gets none
+        let second = cache.new_context(0, INITIAL_EPOCH, vec![partition(0, 0)], &[], false, false);
+        assert!(matches!(second, FetchContext::Full { session_id } if session_id != 0));
+        let third = cache.new_context(0, INITIAL_EPOCH, vec![partition(0, 0)], &[], false, false);
+        assert!(matches!(third, FetchContext::Full { session_id: 0 }));
+        // a follower evicts the smallest consumer session
+        let follower = cache.new_context(0, INITIAL_EPOCH, vec![partition(0, 0)], &[], true, false);
+        assert!(matches!(follower, FetchContext::Full { session_id } if session_id != 0));
+        assert!(matches!(
+            cache.new_context(session_id, 2, vec![], &[], false, false),
+            FetchContext::Incremental { .. }
+        ));
+    }
+}
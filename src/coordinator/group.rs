@@ -0,0 +1,833 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::oneshot;
+
+use super::offsets::OffsetStore;
+
+pub static COORDINATOR_NOT_AVAILABLE: i16 = 15;
+pub static ILLEGAL_GENERATION: i16 = 22;
+pub static INCONSISTENT_GROUP_PROTOCOL: i16 = 23;
+pub static INVALID_GROUP_ID: i16 = 24;
+pub static UNKNOWN_MEMBER_ID: i16 = 25;
+pub static INVALID_SESSION_TIMEOUT: i16 = 26;
+pub static REBALANCE_IN_PROGRESS: i16 = 27;
+pub static MEMBER_ID_REQUIRED: i16 = 79;
+
+// Same defaults as Kafka's `group.min.session.timeout.ms` and
+// `group.max.session.timeout.ms`
+static MIN_SESSION_TIMEOUT: Duration = Duration::from_millis(6_000);
+static MAX_SESSION_TIMEOUT: Duration = Duration::from_millis(1_800_000);
+// How often sessions and rebalances are checked for timeouts
+static EXPIRATION_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The classic rebalance protocol's group states.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupState {
+    /// No members
+    Empty,
+    /// Waiting for every member to send JoinGroup
+    PreparingRebalance,
+    /// Joined, waiting for the leader's SyncGroup with the assignment
+    CompletingRebalance,
+    Stable,
+    /// Removed from the coordinator
+    Dead,
+}
+
+/// A decoded JoinGroup request.
+#[derive(Debug)]
+pub struct JoinGroup {
+    pub group_id: String,
+    // empty for a member joining for the first time
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+    pub client_id: String,
+    pub session_timeout: Duration,
+    pub rebalance_timeout: Duration,
+    pub protocol_type: String,
+    pub protocols: Vec<(String, Vec<u8>)>,
+    // from JoinGroup v4 a new member gets its id back with
+    // MEMBER_ID_REQUIRED and joins again with it
+    pub require_known_member_id: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct JoinResult {
+    pub error_code: i16,
+    pub generation_id: i32,
+    pub protocol_type: Option<String>,
+    pub protocol_name: Option<String>,
+    pub leader_id: String,
+    pub member_id: String,
+    // only the leader gets the members, it computes the assignment
+    pub members: Vec<JoinedMember>,
+}
+
+#[derive(Clone, Debug)]
+pub struct JoinedMember {
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+    pub metadata: Vec<u8>,
+}
+
+impl JoinResult {
+    fn error(member_id: &str, error_code: i16) -> JoinResult {
+        JoinResult {
+            error_code,
+            generation_id: -1,
+            protocol_type: None,
+            protocol_name: None,
+            leader_id: String::new(),
+            member_id: member_id.to_string(),
+            members: vec![],
+        }
+    }
+}
+
+/// A decoded SyncGroup request.
+#[derive(Debug)]
+pub struct SyncGroup {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    pub protocol_type: Option<String>,
+    pub protocol_name: Option<String>,
+    // set by the leader only
+    pub assignments: Vec<(String, Vec<u8>)>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SyncResult {
+    pub error_code: i16,
+    pub protocol_type: Option<String>,
+    pub protocol_name: Option<String>,
+    pub assignment: Vec<u8>,
+}
+
+impl SyncResult {
+    fn error(error_code: i16) -> SyncResult {
+        SyncResult {
+            error_code,
+            ..SyncResult::default()
+        }
+    }
+}
+
+// A result ready now, or once the rebalance gets far enough
+enum Reply<T> {
+    Now(T),
+    Later(oneshot::Receiver<T>),
+}
+
+#[derive(Debug)]
+struct Member {
+    member_id: String,
+    group_instance_id: Option<String>,
+    session_timeout: Duration,
+    rebalance_timeout: Duration,
+    protocols: Vec<(String, Vec<u8>)>,
+    assignment: Vec<u8>,
+    last_heartbeat: Instant,
+    awaiting_join: Option<oneshot::Sender<JoinResult>>,
+    awaiting_sync: Option<oneshot::Sender<SyncResult>>,
+}
+
+impl Member {
+    fn supports(&self, protocol_name: &str) -> bool {
+        self.protocols.iter().any(|(name, _)| name == protocol_name)
+    }
+
+    fn metadata(&self, protocol_name: &str) -> Vec<u8> {
+        self.protocols
+            .iter()
+            .find(|(name, _)| name == protocol_name)
+            .map(|(_, metadata)| metadata.clone())
+            .unwrap_or_default()
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        // a member blocked in JoinGroup or SyncGroup cannot heartbeat
+        self.awaiting_join.is_none()
+            && self.awaiting_sync.is_none()
+            && now >= self.last_heartbeat + self.session_timeout
+    }
+}
+
+#[derive(Debug)]
+struct Group {
+    state: GroupState,
+    generation_id: i32,
+    protocol_type: Option<String>,
+    protocol_name: Option<String>,
+    leader_id: Option<String>,
+    // in join order, the first one leads when the leader is gone
+    members: Vec<Member>,
+    // ids handed out with MEMBER_ID_REQUIRED, until when they are kept
+    pending_members: HashMap<String, Instant>,
+    rebalance_deadline: Option<Instant>,
+}
+
+impl Group {
+    fn new() -> Group {
+        Group {
+            state: GroupState::Empty,
+            generation_id: 0,
+            protocol_type: None,
+            protocol_name: None,
+            leader_id: None,
+            members: Vec::new(),
+            pending_members: HashMap::new(),
+            rebalance_deadline: None,
+        }
+    }
+
+    fn position(&self, member_id: &str) -> Option<usize> {
+        self.members.iter().position(|m| m.member_id == member_id)
+    }
+
+    fn is_leader(&self, member_id: &str) -> bool {
+        self.leader_id.as_deref() == Some(member_id)
+    }
+
+    // A member must use the group's protocol type and share at least one
+    // protocol with every member.
+    fn supports(&self, protocol_type: &str, protocols: &[(String, Vec<u8>)]) -> bool {
+        if protocol_type.is_empty() || protocols.is_empty() {
+            return false;
+        }
+        if self.members.is_empty() {
+            return true;
+        }
+        self.protocol_type.as_deref() == Some(protocol_type)
+            && protocols
+                .iter()
+                .any(|(name, _)| self.members.iter().all(|m| m.supports(name)))
+    }
+
+    fn join(&mut self, request: JoinGroup, now: Instant) -> Reply<JoinResult> {
+        if request.member_id.is_empty() {
+            let member_id = format!("{}-{}", request.client_id, uuid::Uuid::new_v4());
+            if request.require_known_member_id {
+                self.pending_members
+                    .insert(member_id.clone(), now + request.session_timeout);
+                return Reply::Now(JoinResult::error(&member_id, MEMBER_ID_REQUIRED));
+            }
+            return Reply::Later(self.add_member(member_id, request, now));
+        }
+        if self.pending_members.remove(&request.member_id).is_some() {
+            let member_id = request.member_id.clone();
+            return Reply::Later(self.add_member(member_id, request, now));
+        }
+
+        let Some(index) = self.position(&request.member_id) else {
+            return Reply::Now(JoinResult::error(&request.member_id, UNKNOWN_MEMBER_ID));
+        };
+        let is_leader = self.is_leader(&request.member_id);
+        let member = &mut self.members[index];
+        member.last_heartbeat = now;
+        let unchanged = member.protocols == request.protocols;
+        match self.state {
+            // a follower that missed its JoinGroup response gets it again
+            GroupState::CompletingRebalance if unchanged => {
+                Reply::Now(self.join_result(&request.member_id))
+            }
+            GroupState::Stable if unchanged && !is_leader => {
+                Reply::Now(self.join_result(&request.member_id))
+            }
+            GroupState::Empty | GroupState::Dead => {
+                Reply::Now(JoinResult::error(&request.member_id, UNKNOWN_MEMBER_ID))
+            }
+            _ => {
+                let (sender, receiver) = oneshot::channel();
+                member.session_timeout = request.session_timeout;
+                member.rebalance_timeout = request.rebalance_timeout;
+                member.protocols = request.protocols;
+                member.awaiting_join = Some(sender);
+                self.prepare_rebalance(now);
+                self.try_complete_join(now);
+                Reply::Later(receiver)
+            }
+        }
+    }
+
+    fn add_member(
+        &mut self,
+        member_id: String,
+        request: JoinGroup,
+        now: Instant,
+    ) -> oneshot::Receiver<JoinResult> {
+        let (sender, receiver) = oneshot::channel();
+        if self.members.is_empty() {
+            self.protocol_type = Some(request.protocol_type);
+        }
+        self.members.push(Member {
+            member_id,
+            group_instance_id: request.group_instance_id,
+            session_timeout: request.session_timeout,
+            rebalance_timeout: request.rebalance_timeout,
+            protocols: request.protocols,
+            assignment: Vec::new(),
+            last_heartbeat: now,
+            awaiting_join: Some(sender),
+            awaiting_sync: None,
+        });
+        self.prepare_rebalance(now);
+        self.try_complete_join(now);
+        receiver
+    }
+
+    fn remove_member(&mut self, index: usize, now: Instant) {
+        // dropping its waiters answers them with UNKNOWN_MEMBER_ID
+        let member = self.members.remove(index);
+        if self.is_leader(&member.member_id) {
+            self.leader_id = None;
+        }
+        if matches!(
+            self.state,
+            GroupState::Stable | GroupState::CompletingRebalance
+        ) {
+            self.prepare_rebalance(now);
+        }
+        self.try_complete_join(now);
+    }
+
+    fn prepare_rebalance(&mut self, now: Instant) {
+        if self.state == GroupState::PreparingRebalance {
+            return;
+        }
+        if self.state == GroupState::CompletingRebalance {
+            // the assignment being waited for belongs to a dead generation
+            for member in &mut self.members {
+                if let Some(sender) = member.awaiting_sync.take() {
+                    let _ = sender.send(SyncResult::error(REBALANCE_IN_PROGRESS));
+                }
+            }
+        }
+        let timeout = self
+            .members
+            .iter()
+            .map(|m| m.rebalance_timeout)
+            .max()
+            .unwrap_or_default();
+        self.rebalance_deadline = Some(now + timeout);
+        self.state = GroupState::PreparingRebalance;
+    }
+
+    fn try_complete_join(&mut self, now: Instant) {
+        if self.state == GroupState::PreparingRebalance
+            && self.pending_members.is_empty()
+            && self.members.iter().all(|m| m.awaiting_join.is_some())
+        {
+            self.complete_join(now);
+        }
+    }
+
+    // Starts the next generation with the members that rejoined
+    fn complete_join(&mut self, now: Instant) {
+        self.members.retain(|m| m.awaiting_join.is_some());
+        self.generation_id += 1;
+        self.rebalance_deadline = None;
+        if self.members.is_empty() {
+            self.state = GroupState::Empty;
+            self.protocol_type = None;
+            self.protocol_name = None;
+            self.leader_id = None;
+            return;
+        }
+
+        self.protocol_name = Some(self.select_protocol());
+        if !self
+            .leader_id
+            .as_ref()
+            .is_some_and(|id| self.position(id).is_some())
+        {
+            self.leader_id = Some(self.members[0].member_id.clone());
+        }
+        self.state = GroupState::CompletingRebalance;
+
+        let results: Vec<JoinResult> = self
+            .members
+            .iter()
+            .map(|m| self.join_result(&m.member_id))
+            .collect();
+        for (member, result) in self.members.iter_mut().zip(results) {
+            member.last_heartbeat = now;
+            if let Some(sender) = member.awaiting_join.take() {
+                let _ = sender.send(result);
+            }
+        }
+    }
+
+    // Every member votes for the first of its protocols that all members
+    // support, the most voted one wins.
+    fn select_protocol(&self) -> String {
+        let candidates: Vec<&str> = self.members[0]
+            .protocols
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .filter(|name| self.members.iter().all(|m| m.supports(name)))
+            .collect();
+        let mut votes: HashMap<&str, usize> = HashMap::new();
+        for member in &self.members {
+            let vote = member
+                .protocols
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .find(|name| candidates.contains(name));
+            if let Some(vote) = vote {
+                *votes.entry(vote).or_default() += 1;
+            }
+        }
+        // ties go to the first member's preference
+        candidates
+            .iter()
+            .rev()
+            .max_by_key(|name| votes.get(*name).copied().unwrap_or_default())
+            .map(|name| name.to_string())
+            .unwrap_or_default()
+    }
+
+    fn join_result(&self, member_id: &str) -> JoinResult {
+        let protocol_name = self.protocol_name.clone().unwrap_or_default();
+        let members = if self.is_leader(member_id) {
+            self.members
+                .iter()
+                .map(|m| JoinedMember {
+                    member_id: m.member_id.clone(),
+                    group_instance_id: m.group_instance_id.clone(),
+                    metadata: m.metadata(&protocol_name),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        JoinResult {
+            error_code: 0,
+            generation_id: self.generation_id,
+            protocol_type: self.protocol_type.clone(),
+            protocol_name: self.protocol_name.clone(),
+            leader_id: self.leader_id.clone().unwrap_or_default(),
+            member_id: member_id.to_string(),
+            members,
+        }
+    }
+
+    fn sync(&mut self, request: SyncGroup, now: Instant) -> Reply<SyncResult> {
+        let Some(index) = self.position(&request.member_id) else {
+            return Reply::Now(SyncResult::error(UNKNOWN_MEMBER_ID));
+        };
+        if request.generation_id != self.generation_id {
+            return Reply::Now(SyncResult::error(ILLEGAL_GENERATION));
+        }
+        let mismatch = |requested: &Option<String>, current: &Option<String>| {
+            requested.is_some() && requested != current
+        };
+        if mismatch(&request.protocol_type, &self.protocol_type)
+            || mismatch(&request.protocol_name, &self.protocol_name)
+        {
+            return Reply::Now(SyncResult::error(INCONSISTENT_GROUP_PROTOCOL));
+        }
+
+        self.members[index].last_heartbeat = now;
+        match self.state {
+            GroupState::Empty | GroupState::Dead => {
+                Reply::Now(SyncResult::error(UNKNOWN_MEMBER_ID))
+            }
+            GroupState::PreparingRebalance => Reply::Now(SyncResult::error(REBALANCE_IN_PROGRESS)),
+            GroupState::CompletingRebalance => {
+                let (sender, receiver) = oneshot::channel();
+                self.members[index].awaiting_sync = Some(sender);
+                if self.is_leader(&request.member_id) {
+                    self.complete_sync(request.assignments);
+                }
+                Reply::Later(receiver)
+            }
+            GroupState::Stable => Reply::Now(self.sync_result(index)),
+        }
+    }
+
+    // Hands the leader's assignment out, members it left out get nothing
+    fn complete_sync(&mut self, assignments: Vec<(String, Vec<u8>)>) {
+        let mut assignments: HashMap<String, Vec<u8>> = assignments.into_iter().collect();
+        for member in &mut self.members {
+            member.assignment = assignments.remove(&member.member_id).unwrap_or_default();
+        }
+        self.state = GroupState::Stable;
+        for index in 0..self.members.len() {
+            let result = self.sync_result(index);
+            if let Some(sender) = self.members[index].awaiting_sync.take() {
+                let _ = sender.send(result);
+            }
+        }
+    }
+
+    fn sync_result(&self, index: usize) -> SyncResult {
+        SyncResult {
+            error_code: 0,
+            protocol_type: self.protocol_type.clone(),
+            protocol_name: self.protocol_name.clone(),
+            assignment: self.members[index].assignment.clone(),
+        }
+    }
+
+    fn heartbeat(&mut self, member_id: &str, generation_id: i32, now: Instant) -> i16 {
+        let Some(index) = self.position(member_id) else {
+            return UNKNOWN_MEMBER_ID;
+        };
+        if generation_id != self.generation_id {
+            return ILLEGAL_GENERATION;
+        }
+        self.members[index].last_heartbeat = now;
+        match self.state {
+            GroupState::Empty | GroupState::Dead => UNKNOWN_MEMBER_ID,
+            // tells the member to rejoin
+            GroupState::PreparingRebalance => REBALANCE_IN_PROGRESS,
+            GroupState::CompletingRebalance | GroupState::Stable => 0,
+        }
+    }
+
+    fn leave(&mut self, member_id: &str, group_instance_id: Option<&str>, now: Instant) -> i16 {
+        if self.pending_members.remove(member_id).is_some() {
+            self.try_complete_join(now);
+            return 0;
+        }
+        // static members may leave by instance id alone
+        let index = self.members.iter().position(|m| {
+            m.member_id == member_id
+                || (member_id.is_empty()
+                    && group_instance_id.is_some()
+                    && m.group_instance_id.as_deref() == group_instance_id)
+        });
+        match index {
+            Some(index) => {
+                self.remove_member(index, now);
+                0
+            }
+            None => UNKNOWN_MEMBER_ID,
+        }
+    }
+
+    // Members commit with their generation, admin tools and consumers
+    // without a group with generation -1 and only while the group is empty.
+    fn validate_offset_commit(&mut self, member_id: &str, generation_id: i32, now: Instant) -> i16 {
+        if generation_id >= 0 || !member_id.is_empty() {
+            let Some(index) = self.position(member_id) else {
+                return UNKNOWN_MEMBER_ID;
+            };
+            if generation_id != self.generation_id {
+                return ILLEGAL_GENERATION;
+            }
+            self.members[index].last_heartbeat = now;
+        } else if self.state != GroupState::Empty {
+            return UNKNOWN_MEMBER_ID;
+        }
+        // commits are still accepted while members rejoin
+        match self.state {
+            GroupState::CompletingRebalance => REBALANCE_IN_PROGRESS,
+            GroupState::Dead => COORDINATOR_NOT_AVAILABLE,
+            _ => 0,
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.pending_members.retain(|_, deadline| *deadline > now);
+        for index in (0..self.members.len()).rev() {
+            if self.members[index].is_expired(now) {
+                println!("member {} session expired", self.members[index].member_id);
+                self.remove_member(index, now);
+            }
+        }
+        let rebalance_timed_out = self.state == GroupState::PreparingRebalance
+            && self
+                .rebalance_deadline
+                .is_some_and(|deadline| now >= deadline);
+        if rebalance_timed_out {
+            // members that did not rejoin in time are left out
+            self.complete_join(now);
+        } else {
+            self.try_complete_join(now);
+        }
+    }
+}
+
+/// Runs the classic consumer group protocol for every group, this broker
+/// being the coordinator of all of them.
+#[derive(Debug, Default)]
+pub struct GroupCoordinator {
+    groups: Mutex<HashMap<String, Group>>,
+    offsets: OffsetStore,
+}
+
+impl GroupCoordinator {
+    /// Starts with the offsets committed before the last shutdown.
+    pub async fn load() -> anyhow::Result<GroupCoordinator> {
+        Ok(GroupCoordinator {
+            groups: Mutex::default(),
+            offsets: OffsetStore::load().await?,
+        })
+    }
+
+    pub fn offsets(&self) -> &OffsetStore {
+        &self.offsets
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Group>> {
+        self.groups.lock().expect("group coordinator lock poisoned")
+    }
+
+    /// Joins the member and waits until the rebalance it started, or is
+    /// part of, completes.
+    pub async fn join_group(&self, request: JoinGroup) -> JoinResult {
+        let member_id = request.member_id.clone();
+        let reply = {
+            let mut groups = self.lock();
+            if request.group_id.is_empty() {
+                return JoinResult::error(&member_id, INVALID_GROUP_ID);
+            }
+            if !(MIN_SESSION_TIMEOUT..=MAX_SESSION_TIMEOUT).contains(&request.session_timeout) {
+                return JoinResult::error(&member_id, INVALID_SESSION_TIMEOUT);
+            }
+            if !member_id.is_empty() && !groups.contains_key(&request.group_id) {
+                return JoinResult::error(&member_id, UNKNOWN_MEMBER_ID);
+            }
+            let group = groups
+                .entry(request.group_id.clone())
+                .or_insert_with(Group::new);
+            if group.state == GroupState::Dead {
+                return JoinResult::error(&member_id, COORDINATOR_NOT_AVAILABLE);
+            }
+            if !group.supports(&request.protocol_type, &request.protocols) {
+                return JoinResult::error(&member_id, INCONSISTENT_GROUP_PROTOCOL);
+            }
+            group.join(request, Instant::now())
+        };
+        match reply {
+            Reply::Now(result) => result,
+            // the sender is dropped when the member is removed meanwhile
+            Reply::Later(receiver) => receiver
+                .await
+                .unwrap_or_else(|_| JoinResult::error(&member_id, UNKNOWN_MEMBER_ID)),
+        }
+    }
+
+    /// Waits for the leader's assignment and returns the member's share.
+    pub async fn sync_group(&self, request: SyncGroup) -> SyncResult {
+        let reply = match self.lock().get_mut(&request.group_id) {
+            Some(group) if group.state != GroupState::Dead => group.sync(request, Instant::now()),
+            Some(_) => Reply::Now(SyncResult::error(COORDINATOR_NOT_AVAILABLE)),
+            None => Reply::Now(SyncResult::error(UNKNOWN_MEMBER_ID)),
+        };
+        match reply {
+            Reply::Now(result) => result,
+            Reply::Later(receiver) => receiver
+                .await
+                .unwrap_or_else(|_| SyncResult::error(UNKNOWN_MEMBER_ID)),
+        }
+    }
+
+    pub fn heartbeat(&self, group_id: &str, member_id: &str, generation_id: i32) -> i16 {
+        match self.lock().get_mut(group_id) {
+            Some(group) if group.state != GroupState::Dead => {
+                group.heartbeat(member_id, generation_id, Instant::now())
+            }
+            Some(_) => COORDINATOR_NOT_AVAILABLE,
+            None => UNKNOWN_MEMBER_ID,
+        }
+    }
+
+    /// Removes each `(member_id, group_instance_id)` from the group and
+    /// returns an error code per member.
+    pub fn leave_group(&self, group_id: &str, members: &[(String, Option<String>)]) -> Vec<i16> {
+        let mut groups = self.lock();
+        let Some(group) = groups.get_mut(group_id) else {
+            return vec![UNKNOWN_MEMBER_ID; members.len()];
+        };
+        let now = Instant::now();
+        members
+            .iter()
+            .map(|(member_id, group_instance_id)| {
+                group.leave(member_id, group_instance_id.as_deref(), now)
+            })
+            .collect()
+    }
+
+    /// Checks that the member may commit offsets for the group. A group
+    /// without members only accepts commits with generation -1.
+    pub fn validate_offset_commit(
+        &self,
+        group_id: &str,
+        member_id: &str,
+        generation_id: i32,
+    ) -> i16 {
+        if group_id.is_empty() {
+            return INVALID_GROUP_ID;
+        }
+        match self.lock().get_mut(group_id) {
+            Some(group) => group.validate_offset_commit(member_id, generation_id, Instant::now()),
+            None if generation_id < 0 => 0,
+            None => ILLEGAL_GENERATION,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn group_state(&self, group_id: &str) -> Option<GroupState> {
+        self.lock().get(group_id).map(|group| group.state)
+    }
+
+    /// Expires sessions and rebalances that timed out, until the process
+    /// exits.
+    pub async fn run_expiration(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(EXPIRATION_INTERVAL).await;
+            self.expire(Instant::now());
+        }
+    }
+
+    fn expire(&self, now: Instant) {
+        let mut groups = self.lock();
+        for group in groups.values_mut() {
+            group.expire(now);
+        }
+        // nothing but its committed offsets is kept for a group once its
+        // last member is gone
+        groups.retain(|_, group| {
+            if group.state == GroupState::Empty && group.pending_members.is_empty() {
+                group.state = GroupState::Dead;
+            }
+            group.state != GroupState::Dead
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn join_request(member_id: &str, protocols: &[&str]) -> JoinGroup {
+        JoinGroup {
+            group_id: "group".to_string(),
+            member_id: member_id.to_string(),
+            group_instance_id: None,
+            client_id: "client".to_string(),
+            session_timeout: Duration::from_secs(10),
+            rebalance_timeout: Duration::from_secs(10),
+            protocol_type: "consumer".to_string(),
+            protocols: protocols
+                .iter()
+                .map(|name| (name.to_string(), name.as_bytes().to_vec()))
+                .collect(),
+            require_known_member_id: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn rebalances_through_join_and_sync() {
+        let coordinator = Arc::new(GroupCoordinator::default());
+        let first = coordinator
+            .join_group(join_request("", &["range", "roundrobin"]))
+            .await;
+        assert_eq!(first.error_code, 0);
+        assert_eq!(first.generation_id, 1);
+        assert_eq!(first.leader_id, first.member_id);
+        assert_eq!(
+            coordinator.group_state("group"),
+            Some(GroupState::CompletingRebalance)
+        );
+
+        // a second member starts a rebalance the leader has to rejoin
+        let joining = tokio::spawn({
+            let coordinator = coordinator.clone();
+            async move {
+                coordinator
+                    .join_group(join_request("", &["roundrobin"]))
+                    .await
+            }
+        });
+        tokio::task::yield_now().await;
+        assert_eq!(
+            coordinator.heartbeat("group", &first.member_id, 1),
+            REBALANCE_IN_PROGRESS
+        );
+        let leader = coordinator
+            .join_group(join_request(&first.member_id, &["range", "roundrobin"]))
+            .await;
+        let follower = joining.await.unwrap();
+        assert_eq!(leader.generation_id, 2);
+        assert_eq!(leader.protocol_name.as_deref(), Some("roundrobin"));
+        assert_eq!(leader.members.len(), 2);
+        assert!(follower.members.is_empty());
+
+        let follower_sync = tokio::spawn({
+            let coordinator = coordinator.clone();
+            let member_id = follower.member_id.clone();
+            async move {
+                coordinator
+                    .sync_group(SyncGroup {
+                        group_id: "group".to_string(),
+                        generation_id: 2,
+                        member_id,
+                        protocol_type: None,
+                        protocol_name: None,
+                        assignments: vec![],
+                    })
+                    .await
+            }
+        });
+        tokio::task::yield_now().await;
+        let leader_sync = coordinator
+            .sync_group(SyncGroup {
+                group_id: "group".to_string(),
+                generation_id: 2,
+                member_id: leader.member_id.clone(),
+                protocol_type: Some("consumer".to_string()),
+                protocol_name: Some("roundrobin".to_string()),
+                assignments: vec![
+                    (leader.member_id.clone(), b"a".to_vec()),
+                    (follower.member_id.clone(), b"b".to_vec()),
+                ],
+            })
+            .await;
+        assert_eq!(leader_sync.assignment, b"a");
+        assert_eq!(follower_sync.await.unwrap().assignment, b"b");
+        assert_eq!(coordinator.group_state("group"), Some(GroupState::Stable));
+
+        assert_eq!(
+            coordinator.leave_group("group", &[(follower.member_id.clone(), None)]),
+            vec![0]
+        );
+        assert_eq!(
+            coordinator.group_state("group"),
+            Some(GroupState::PreparingRebalance)
+        );
+        // the leader can still commit what it consumed before rejoining
+        assert_eq!(
+            coordinator.validate_offset_commit("group", &leader.member_id, 2),
+            0
+        );
+        assert_eq!(
+            coordinator.validate_offset_commit("group", &leader.member_id, 1),
+            ILLEGAL_GENERATION
+        );
+        assert_eq!(
+            coordinator.validate_offset_commit("group", "", -1),
+            UNKNOWN_MEMBER_ID
+        );
+    }
+
+    #[tokio::test]
+    async fn expires_silent_members() {
+        let coordinator = GroupCoordinator::default();
+        let joined = coordinator.join_group(join_request("", &["range"])).await;
+        let later = Instant::now() + Duration::from_secs(11);
+        coordinator.expire(later);
+        assert_eq!(coordinator.group_state("group"), None);
+        assert_eq!(
+            coordinator.heartbeat("group", &joined.member_id, 1),
+            UNKNOWN_MEMBER_ID
+        );
+    }
+}
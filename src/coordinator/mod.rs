@@ -0,0 +1,5 @@
+pub mod fetch_session;
+pub mod group;
+pub mod offsets;
+pub mod producer_ids;
+pub mod transaction;
@@ -0,0 +1,449 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    sync::{Mutex, MutexGuard},
+};
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    custom_trait::wire::{ReadWire, WriteWire},
+    metadata::image::MetadataImage,
+    storage::{
+        self,
+        batch::{self, ControlMarker},
+        log::LogConfig,
+    },
+};
+
+/// The internal topic committed offsets are written to, compacted by key.
+pub static OFFSETS_TOPIC: &str = "__consumer_offsets";
+
+// Every group is kept in a single partition
+pub static OFFSETS_PARTITION: i32 = 0;
+
+// Schema versions of the records, as written by Kafka 2.1+. Keys 0 and 1
+// are offset commits, 2 is group metadata which is not persisted here.
+static OFFSET_COMMIT_KEY_VERSION: i16 = 1;
+static OFFSET_COMMIT_VALUE_VERSION: i16 = 3;
+
+static UNKNOWN_TOPIC_OR_PARTITION: i16 = 3;
+static OFFSET_METADATA_TOO_LARGE: i16 = 12;
+
+// Kafka's default `offset.metadata.max.bytes`
+static MAX_METADATA_SIZE: usize = 4096;
+
+pub type TopicPartition = (String, i32);
+
+/// An offset committed by a group for one partition.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CommittedOffset {
+    pub offset: i64,
+    // -1 when the client did not send it
+    pub leader_epoch: i32,
+    pub metadata: String,
+    pub commit_timestamp: i64,
+}
+
+type GroupOffsets = HashMap<String, HashMap<TopicPartition, CommittedOffset>>;
+
+// Offsets committed in a transaction, by producer id, applied once the
+// transaction commits
+type PendingOffsets = HashMap<i64, Vec<(String, TopicPartition, CommittedOffset)>>;
+
+/// Committed offsets of every group, cached in memory and persisted to
+/// [`OFFSETS_TOPIC`].
+#[derive(Debug, Default)]
+pub struct OffsetStore {
+    offsets: Mutex<GroupOffsets>,
+    pending: Mutex<PendingOffsets>,
+    // keeps the cache in the order the commits were appended
+    append_lock: tokio::sync::Mutex<()>,
+}
+
+impl OffsetStore {
+    fn lock(&self) -> MutexGuard<'_, GroupOffsets> {
+        self.offsets.lock().expect("offset store lock poisoned")
+    }
+
+    fn lock_pending(&self) -> MutexGuard<'_, PendingOffsets> {
+        self.pending.lock().expect("offset store lock poisoned")
+    }
+
+    /// Rebuilds the cache by replaying [`OFFSETS_TOPIC`], the last record
+    /// of a key winning and a tombstone deleting it.
+    pub async fn load() -> anyhow::Result<OffsetStore> {
+        let log = storage::open_partition_log(OFFSETS_TOPIC, OFFSETS_PARTITION).await?;
+        let mut next_offset = log.log_start_offset().await?;
+        let mut offsets = GroupOffsets::new();
+        let mut pending = PendingOffsets::new();
+        loop {
+            let read = log.read(next_offset, usize::MAX, true).await?;
+            let Some(content) = read.records.filter(|records| !records.is_empty()) else {
+                break;
+            };
+            let headers = batch::read_batch_headers(&content);
+            let Some((_, last)) = headers.last() else {
+                break;
+            };
+            next_offset = last.next_offset();
+            for (position, header) in &headers {
+                let batch_bytes = &content[*position..*position + header.size()];
+                if header.is_control() {
+                    let commits = pending.remove(&header.producer_id).unwrap_or_default();
+                    if batch::read_control_marker(batch_bytes) == Some(ControlMarker::Commit) {
+                        for (group_id, partition, committed) in commits {
+                            offsets
+                                .entry(group_id)
+                                .or_default()
+                                .insert(partition, committed);
+                        }
+                    }
+                    continue;
+                }
+                let Some(records) = batch::read_records(batch_bytes) else {
+                    eprintln!("skipping corrupt batch at offset {}", header.base_offset);
+                    continue;
+                };
+                for record in records {
+                    let (group_id, partition, committed) = match decode_record(&record).await {
+                        Ok(Some(decoded)) => decoded,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            eprintln!("skipping bad record at offset {}: {:#}", record.offset, e);
+                            continue;
+                        }
+                    };
+                    match committed {
+                        Some(committed) if header.is_transactional() => {
+                            pending
+                                .entry(header.producer_id)
+                                .or_default()
+                                .push((group_id, partition, committed));
+                        }
+                        Some(committed) => {
+                            offsets
+                                .entry(group_id)
+                                .or_default()
+                                .insert(partition, committed);
+                        }
+                        None => remove(&mut offsets, &group_id, &partition),
+                    }
+                }
+            }
+        }
+        println!(
+            "loaded committed offsets of {} groups from {}",
+            offsets.len(),
+            OFFSETS_TOPIC
+        );
+        Ok(OffsetStore {
+            offsets: Mutex::new(offsets),
+            pending: Mutex::new(pending),
+            append_lock: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Appends the commits to [`OFFSETS_TOPIC`] in one batch, then makes
+    /// them visible to fetches.
+    pub async fn commit(
+        &self,
+        group_id: &str,
+        commits: Vec<(TopicPartition, CommittedOffset)>,
+        timestamp: i64,
+    ) -> anyhow::Result<()> {
+        if commits.is_empty() {
+            return Ok(());
+        }
+        let _guard = self.append_lock.lock().await;
+        append(&commits, group_id, |records| {
+            batch::build_batch(records, timestamp)
+        })
+        .await?;
+        let mut offsets = self.lock();
+        let group = offsets.entry(group_id.to_string()).or_default();
+        group.extend(commits);
+        Ok(())
+    }
+
+    /// Appends offsets committed in the producer's transaction. They are
+    /// not visible to fetches until [`OffsetStore::complete_transaction`]
+    /// commits them.
+    pub async fn commit_transactional(
+        &self,
+        group_id: &str,
+        producer_id: i64,
+        producer_epoch: i16,
+        commits: Vec<(TopicPartition, CommittedOffset)>,
+        timestamp: i64,
+    ) -> anyhow::Result<()> {
+        if commits.is_empty() {
+            return Ok(());
+        }
+        let _guard = self.append_lock.lock().await;
+        append(&commits, group_id, |records| {
+            batch::build_transactional_batch(records, timestamp, producer_id, producer_epoch)
+        })
+        .await?;
+        let mut pending = self.lock_pending();
+        let transaction = pending.entry(producer_id).or_default();
+        for (partition, committed) in commits {
+            transaction.push((group_id.to_string(), partition, committed));
+        }
+        Ok(())
+    }
+
+    /// Applies or drops the offsets of the producer's transaction, once
+    /// its marker is written to [`OFFSETS_TOPIC`].
+    pub fn complete_transaction(&self, producer_id: i64, marker: ControlMarker) {
+        let Some(commits) = self.lock_pending().remove(&producer_id) else {
+            return;
+        };
+        if marker == ControlMarker::Commit {
+            let mut offsets = self.lock();
+            for (group_id, partition, committed) in commits {
+                offsets
+                    .entry(group_id)
+                    .or_default()
+                    .insert(partition, committed);
+            }
+        }
+    }
+
+    /// Whether an open transaction committed an offset for the partition.
+    pub fn has_pending(&self, group_id: &str, partition: &TopicPartition) -> bool {
+        self.lock_pending()
+            .values()
+            .flatten()
+            .any(|(group, pending, _)| group == group_id && pending == partition)
+    }
+
+    pub fn fetch(&self, group_id: &str, partition: &TopicPartition) -> Option<CommittedOffset> {
+        self.lock().get(group_id)?.get(partition).cloned()
+    }
+
+    /// Every offset the group committed, sorted by topic and partition.
+    pub fn group_offsets(&self, group_id: &str) -> Vec<(TopicPartition, CommittedOffset)> {
+        let mut offsets: Vec<_> = self
+            .lock()
+            .get(group_id)
+            .map(|group| group.clone().into_iter().collect())
+            .unwrap_or_default();
+        offsets.sort_by(|(a, _), (b, _)| a.cmp(b));
+        offsets
+    }
+}
+
+/// Validates the offsets of an OffsetCommit or TxnOffsetCommit request,
+/// given by topic with their partition index. Returns the error code of
+/// each partition, `request_error` for all of them when it is set, and
+/// the offsets that passed.
+pub fn validate_commits<'a>(
+    cluster: &MetadataImage,
+    request_error: i16,
+    topics: impl IntoIterator<Item = (&'a str, Vec<(i32, CommittedOffset)>)>,
+) -> (Vec<Vec<i16>>, Vec<(TopicPartition, CommittedOffset)>) {
+    let mut error_codes = Vec::new();
+    let mut commits = Vec::new();
+    for (topic_name, offsets) in topics {
+        let known_topic = cluster.topic(topic_name).is_some();
+        let mut topic_errors = Vec::new();
+        for (partition_index, committed) in offsets {
+            let error_code = if request_error != 0 {
+                request_error
+            } else if !known_topic {
+                UNKNOWN_TOPIC_OR_PARTITION
+            } else if committed.metadata.len() > MAX_METADATA_SIZE {
+                OFFSET_METADATA_TOO_LARGE
+            } else {
+                commits.push(((topic_name.to_string(), partition_index), committed));
+                0
+            };
+            topic_errors.push(error_code);
+        }
+        error_codes.push(topic_errors);
+    }
+    (error_codes, commits)
+}
+
+// Appends the commits to [`OFFSETS_TOPIC`] in the batch `build` makes of
+// their records. Callers hold the append lock.
+async fn append(
+    commits: &[(TopicPartition, CommittedOffset)],
+    group_id: &str,
+    build: impl FnOnce(&[batch::KeyValue]) -> Vec<u8>,
+) -> anyhow::Result<()> {
+    let mut encoded = Vec::new();
+    for (partition, committed) in commits {
+        encoded.push((encode_key(group_id, partition), encode_value(committed)));
+    }
+    let records: Vec<batch::KeyValue> = encoded
+        .iter()
+        .map(|(key, value)| (Some(key.as_slice()), Some(value.as_slice())))
+        .collect();
+    let mut records = build(&records);
+    let headers = batch::read_batch_headers(&records);
+    storage::append_partition_records(
+        OFFSETS_TOPIC,
+        OFFSETS_PARTITION,
+        0,
+        LogConfig::default(),
+        &mut records,
+        &headers,
+    )
+    .await?;
+    Ok(())
+}
+
+fn remove(offsets: &mut GroupOffsets, group_id: &str, partition: &TopicPartition) {
+    if let Some(group) = offsets.get_mut(group_id) {
+        group.remove(partition);
+        if group.is_empty() {
+            offsets.remove(group_id);
+        }
+    }
+}
+
+// Returns None for records without a key or with a key of another kind,
+// and a None offset for tombstones
+async fn decode_record(
+    record: &batch::Record,
+) -> anyhow::Result<Option<(String, TopicPartition, Option<CommittedOffset>)>> {
+    let Some(key) = &record.key else {
+        return Ok(None);
+    };
+    let Some((group_id, partition)) = decode_key(key).await? else {
+        return Ok(None);
+    };
+    let committed = match &record.value {
+        Some(value) => Some(decode_value(value).await?),
+        None => None,
+    };
+    Ok(Some((group_id, partition, committed)))
+}
+
+fn encode_key(group_id: &str, (topic, partition): &TopicPartition) -> Vec<u8> {
+    let mut key = Vec::new();
+    key.put_i16(OFFSET_COMMIT_KEY_VERSION); // version
+    key.write_string(false, group_id); // group
+    key.write_string(false, topic); // topic
+    key.put_i32(*partition); // partition
+    key
+}
+
+// Returns None for keys that are not offset commits
+async fn decode_key(key: &Vec<u8>) -> anyhow::Result<Option<(String, TopicPartition)>> {
+    let mut cursor = Cursor::new(key);
+    let version = cursor.read_i16().await?;
+    if !(0..=1).contains(&version) {
+        return Ok(None);
+    }
+    let group_id = cursor.read_string(false).await?;
+    let topic = cursor.read_string(false).await?;
+    let partition = cursor.read_i32().await?;
+    Ok(Some((group_id, (topic, partition))))
+}
+
+fn encode_value(committed: &CommittedOffset) -> Vec<u8> {
+    let mut value = Vec::new();
+    value.put_i16(OFFSET_COMMIT_VALUE_VERSION); // version
+    value.put_i64(committed.offset); // offset
+    value.put_i32(committed.leader_epoch); // leader_epoch
+    value.write_string(false, &committed.metadata); // metadata
+    value.put_i64(committed.commit_timestamp); // commit_timestamp
+    value
+}
+
+// Reads values v0 to v3, the ones Kafka writes before flexible versions
+async fn decode_value(value: &Vec<u8>) -> anyhow::Result<CommittedOffset> {
+    let mut cursor = Cursor::new(value);
+    let version = cursor.read_i16().await?;
+    if !(0..=3).contains(&version) {
+        anyhow::bail!("unsupported offset commit value version {version}");
+    }
+    let offset = cursor.read_i64().await?;
+    let leader_epoch = if version >= 3 {
+        cursor.read_i32().await?
+    } else {
+        -1
+    };
+    let metadata = cursor.read_string(false).await?;
+    let commit_timestamp = cursor.read_i64().await?;
+    if version == 1 {
+        let _expire_timestamp = cursor.read_i64().await?;
+    }
+    Ok(CommittedOffset {
+        offset,
+        leader_epoch,
+        metadata,
+        commit_timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trips_commit_records() -> anyhow::Result<()> {
+        let partition = ("orders".to_string(), 3);
+        let committed = CommittedOffset {
+            offset: 42,
+            leader_epoch: 7,
+            metadata: "meta".to_string(),
+            commit_timestamp: 1000,
+        };
+        let key = encode_key("group", &partition);
+        assert_eq!(
+            decode_key(&key).await?,
+            Some(("group".to_string(), partition))
+        );
+        assert_eq!(decode_value(&encode_value(&committed)).await?, committed);
+
+        // group metadata records share the topic and are skipped
+        let mut group_key = Vec::new();
+        group_key.put_i16(2);
+        group_key.write_string(false, "group");
+        assert_eq!(decode_key(&group_key).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn load_skips_records_that_do_not_decode() -> anyhow::Result<()> {
+        let good = ("load-skip".to_string(), 0);
+        let bad = ("load-skip".to_string(), 1);
+        let committed = CommittedOffset {
+            offset: 5,
+            leader_epoch: -1,
+            metadata: String::new(),
+            commit_timestamp: 1000,
+        };
+        let (good_key, good_value) = (encode_key("load-skip", &good), encode_value(&committed));
+        let bad_key = encode_key("load-skip", &bad);
+        // a value of an unknown version
+        let bad_value = [0, 9];
+        let mut records = batch::build_batch(
+            &[
+                (Some(&bad_key), Some(&bad_value)),
+                (Some(&good_key), Some(&good_value)),
+            ],
+            1000,
+        );
+        let headers = batch::read_batch_headers(&records);
+        storage::append_partition_records(
+            OFFSETS_TOPIC,
+            OFFSETS_PARTITION,
+            0,
+            LogConfig::default(),
+            &mut records,
+            &headers,
+        )
+        .await?;
+
+        let store = OffsetStore::load().await?;
+        assert_eq!(store.fetch("load-skip", &good), Some(committed));
+        assert_eq!(store.fetch("load-skip", &bad), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use std::ops::Range;
+
+use tokio::sync::Mutex;
+
+use crate::{
+    config,
+    metadata::{
+        cluster::{ProducerIdsValueRecord, ValueRecord},
+        tailer::SharedImage,
+    },
+};
+
+// Ids taken from the metadata log at once, like Kafka's controller hands out
+static BLOCK_SIZE: i64 = 1000;
+
+/// Hands out producer ids from blocks reserved with a `ProducerIdsRecord`,
+/// so no id is given out twice, even across restarts.
+#[derive(Debug, Default)]
+pub struct ProducerIdManager {
+    block: Mutex<Range<i64>>,
+}
+
+impl ProducerIdManager {
+    pub fn new() -> ProducerIdManager {
+        ProducerIdManager::default()
+    }
+
+    pub async fn generate(&self, shared_metadata: &SharedImage) -> anyhow::Result<i64> {
+        let mut block = self.block.lock().await;
+        if block.is_empty() {
+            *block = reserve_block(shared_metadata).await?;
+        }
+        let producer_id = block.start;
+        block.start += 1;
+        Ok(producer_id)
+    }
+}
+
+async fn reserve_block(shared_metadata: &SharedImage) -> anyhow::Result<Range<i64>> {
+    let mut writer = shared_metadata.writer().await?;
+    let node_id = config::get().node_id;
+    let start = writer.image().next_producer_id();
+    let broker_epoch = writer
+        .image()
+        .live_brokers()
+        .find(|broker| broker.registration.broker_id == node_id)
+        .map_or(-1, |broker| broker.registration.broker_epoch);
+    writer
+        .append(&[ValueRecord::ProducerIdsValue(ProducerIdsValueRecord {
+            broker_id: node_id,
+            broker_epoch,
+            next_producer_id: start + BLOCK_SIZE,
+        })])
+        .await?;
+    Ok(start..start + BLOCK_SIZE)
+}
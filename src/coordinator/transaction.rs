@@ -0,0 +1,995 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::Cursor,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use bytes::BufMut;
+use tokio::{io::AsyncReadExt, sync::Mutex};
+
+use crate::{
+    custom_trait::wire::{ReadWire, WriteWire},
+    metadata::tailer::SharedImage,
+    storage::{
+        self,
+        batch::{self, ControlMarker},
+        log::LogConfig,
+    },
+};
+
+use super::{
+    group::GroupCoordinator,
+    offsets::{TopicPartition, OFFSETS_PARTITION, OFFSETS_TOPIC},
+    producer_ids::ProducerIdManager,
+};
+
+pub static UNKNOWN_SERVER_ERROR: i16 = -1;
+pub static UNKNOWN_TOPIC_OR_PARTITION: i16 = 3;
+pub static INVALID_PRODUCER_EPOCH: i16 = 47;
+pub static INVALID_TXN_STATE: i16 = 48;
+pub static INVALID_PRODUCER_ID_MAPPING: i16 = 49;
+pub static INVALID_TRANSACTION_TIMEOUT: i16 = 50;
+pub static CONCURRENT_TRANSACTIONS: i16 = 51;
+
+/// The internal topic transaction states are written to, compacted by
+/// transactional id.
+pub static TRANSACTION_STATE_TOPIC: &str = "__transaction_state";
+
+// Every transactional id is kept in a single partition
+static TRANSACTION_STATE_PARTITION: i32 = 0;
+
+// Schema versions of the records, as written by Kafka before flexible
+// versions
+static TRANSACTION_LOG_KEY_VERSION: i16 = 0;
+static TRANSACTION_LOG_VALUE_VERSION: i16 = 0;
+
+// Same default as Kafka's `transaction.max.timeout.ms`
+static MAX_TRANSACTION_TIMEOUT_MS: i32 = 900_000;
+// How often transactions are checked for timeouts, and unfinished ones
+// completed
+static EXPIRATION_INTERVAL: Duration = Duration::from_millis(1_000);
+// This broker is the only transaction coordinator there ever is
+static COORDINATOR_EPOCH: i32 = 0;
+
+/// Kafka's transaction states, in the order of their ids in the log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransactionState {
+    /// No transaction started since the producer was initialized
+    Empty,
+    /// Partitions were added, the transaction is open
+    Ongoing,
+    /// EndTxn was received, markers are being written
+    PrepareCommit,
+    PrepareAbort,
+    /// Every partition has its marker
+    CompleteCommit,
+    CompleteAbort,
+    Dead,
+    PrepareEpochFence,
+}
+
+impl TransactionState {
+    fn id(self) -> i8 {
+        self as i8
+    }
+
+    fn from_id(id: i8) -> Option<TransactionState> {
+        use TransactionState::*;
+        [
+            Empty,
+            Ongoing,
+            PrepareCommit,
+            PrepareAbort,
+            CompleteCommit,
+            CompleteAbort,
+            Dead,
+            PrepareEpochFence,
+        ]
+        .get(usize::try_from(id).ok()?)
+        .copied()
+    }
+
+    fn is_prepared(self) -> bool {
+        matches!(
+            self,
+            TransactionState::PrepareCommit | TransactionState::PrepareAbort
+        )
+    }
+}
+
+/// What the coordinator knows about a transactional id.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransactionMetadata {
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub timeout_ms: i32,
+    pub state: TransactionState,
+    // partitions the open transaction wrote to
+    pub partitions: BTreeSet<TopicPartition>,
+    pub last_update_timestamp: i64,
+    // when the open transaction started, -1 without one
+    pub start_timestamp: i64,
+}
+
+/// Runs transactions for every transactional id, this broker being the
+/// coordinator of all of them. States are persisted to
+/// [`TRANSACTION_STATE_TOPIC`] before they are acted on.
+#[derive(Debug)]
+pub struct TransactionCoordinator {
+    transactions: Mutex<HashMap<String, TransactionMetadata>>,
+    producer_ids: ProducerIdManager,
+    shared_metadata: Arc<SharedImage>,
+    groups: Arc<GroupCoordinator>,
+}
+
+impl TransactionCoordinator {
+    /// Starts with the transaction states written before the last
+    /// shutdown. Transactions left half completed are finished by
+    /// [`TransactionCoordinator::run_expiration`].
+    pub async fn load(
+        shared_metadata: Arc<SharedImage>,
+        groups: Arc<GroupCoordinator>,
+    ) -> anyhow::Result<TransactionCoordinator> {
+        let log = storage::open_partition_log(TRANSACTION_STATE_TOPIC, TRANSACTION_STATE_PARTITION)
+            .await?;
+        let mut next_offset = log.log_start_offset().await?;
+        let mut transactions = HashMap::new();
+        loop {
+            let read = log.read(next_offset, usize::MAX, true).await?;
+            let Some(content) = read.records.filter(|records| !records.is_empty()) else {
+                break;
+            };
+            let headers = batch::read_batch_headers(&content);
+            let Some((_, last)) = headers.last() else {
+                break;
+            };
+            next_offset = last.next_offset();
+            for (position, header) in &headers {
+                let batch_bytes = &content[*position..*position + header.size()];
+                let Some(records) = batch::read_records(batch_bytes) else {
+                    eprintln!("skipping corrupt batch at offset {}", header.base_offset);
+                    continue;
+                };
+                for record in records {
+                    let (transactional_id, metadata) = match decode_record(&record).await {
+                        Ok(Some(decoded)) => decoded,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            eprintln!("skipping bad record at offset {}: {:#}", record.offset, e);
+                            continue;
+                        }
+                    };
+                    match metadata {
+                        Some(metadata) => {
+                            transactions.insert(transactional_id, metadata);
+                        }
+                        None => {
+                            transactions.remove(&transactional_id);
+                        }
+                    }
+                }
+            }
+        }
+        println!(
+            "loaded {} transactional ids from {}",
+            transactions.len(),
+            TRANSACTION_STATE_TOPIC
+        );
+        Ok(TransactionCoordinator {
+            transactions: Mutex::new(transactions),
+            producer_ids: ProducerIdManager::new(),
+            shared_metadata,
+            groups,
+        })
+    }
+
+    /// Gives an idempotent producer a new id. A transactional producer
+    /// keeps its id with the next epoch, which fences its older instances
+    /// and aborts their open transaction. Returns `(error_code,
+    /// producer_id, producer_epoch)`.
+    pub async fn init_producer_id(
+        &self,
+        transactional_id: Option<&str>,
+        timeout_ms: i32,
+        producer_id: i64,
+        producer_epoch: i16,
+    ) -> (i16, i64, i16) {
+        let Some(transactional_id) = transactional_id else {
+            return match self.producer_ids.generate(&self.shared_metadata).await {
+                Ok(producer_id) => (0, producer_id, 0),
+                Err(e) => {
+                    eprintln!("failed to allocate a producer id: {:#}", e);
+                    (UNKNOWN_SERVER_ERROR, -1, -1)
+                }
+            };
+        };
+        if !(1..=MAX_TRANSACTION_TIMEOUT_MS).contains(&timeout_ms) {
+            return (INVALID_TRANSACTION_TIMEOUT, -1, -1);
+        }
+
+        let mut transactions = self.transactions.lock().await;
+        let mut metadata = match transactions.get(transactional_id) {
+            None => match self.producer_ids.generate(&self.shared_metadata).await {
+                Ok(producer_id) => TransactionMetadata {
+                    producer_id,
+                    // bumped to 0 below
+                    producer_epoch: -1,
+                    timeout_ms,
+                    state: TransactionState::Empty,
+                    partitions: BTreeSet::new(),
+                    last_update_timestamp: now(),
+                    start_timestamp: -1,
+                },
+                Err(e) => {
+                    eprintln!("failed to allocate a producer id: {:#}", e);
+                    return (UNKNOWN_SERVER_ERROR, -1, -1);
+                }
+            },
+            Some(metadata) => {
+                // from v3 a producer says which id it had, so a stale one
+                // cannot bump the epoch of its replacement
+                if producer_id != -1
+                    && (producer_id != metadata.producer_id
+                        || producer_epoch != metadata.producer_epoch)
+                {
+                    return (INVALID_PRODUCER_EPOCH, -1, -1);
+                }
+                if metadata.state.is_prepared() {
+                    return (CONCURRENT_TRANSACTIONS, -1, -1);
+                }
+                metadata.clone()
+            }
+        };
+
+        if metadata.state == TransactionState::Ongoing {
+            // the abort markers carry the new epoch, fencing the old producer
+            metadata.producer_epoch += 1;
+            if let Err(e) = self
+                .complete(transactional_id, &mut metadata, ControlMarker::Abort)
+                .await
+            {
+                eprintln!(
+                    "failed to abort transaction of {}: {:#}",
+                    transactional_id, e
+                );
+                transactions.insert(transactional_id.to_string(), metadata);
+                return (CONCURRENT_TRANSACTIONS, -1, -1);
+            }
+        } else if metadata.producer_epoch >= i16::MAX - 1 {
+            match self.producer_ids.generate(&self.shared_metadata).await {
+                Ok(producer_id) => {
+                    metadata.producer_id = producer_id;
+                    metadata.producer_epoch = 0;
+                }
+                Err(e) => {
+                    eprintln!("failed to allocate a producer id: {:#}", e);
+                    return (UNKNOWN_SERVER_ERROR, -1, -1);
+                }
+            }
+        } else {
+            metadata.producer_epoch += 1;
+        }
+        metadata.timeout_ms = timeout_ms;
+        metadata.state = TransactionState::Empty;
+        metadata.partitions.clear();
+        metadata.start_timestamp = -1;
+        metadata.last_update_timestamp = now();
+        if let Err(e) = persist(transactional_id, &metadata).await {
+            eprintln!(
+                "failed to persist transaction of {}: {:#}",
+                transactional_id, e
+            );
+            return (UNKNOWN_SERVER_ERROR, -1, -1);
+        }
+        let result = (0, metadata.producer_id, metadata.producer_epoch);
+        transactions.insert(transactional_id.to_string(), metadata);
+        result
+    }
+
+    /// Adds partitions to the producer's transaction, starting one if none
+    /// is open.
+    pub async fn add_partitions(
+        &self,
+        transactional_id: &str,
+        producer_id: i64,
+        producer_epoch: i16,
+        partitions: &[TopicPartition],
+    ) -> i16 {
+        let mut transactions = self.transactions.lock().await;
+        let metadata = match validate_producer(
+            transactions.get(transactional_id),
+            producer_id,
+            producer_epoch,
+        ) {
+            Ok(metadata) => metadata,
+            Err(error_code) => return error_code,
+        };
+        if metadata.state.is_prepared() {
+            return CONCURRENT_TRANSACTIONS;
+        }
+        if metadata.state == TransactionState::Ongoing
+            && partitions.iter().all(|p| metadata.partitions.contains(p))
+        {
+            return 0;
+        }
+
+        let mut metadata = metadata.clone();
+        let now = now();
+        if metadata.state != TransactionState::Ongoing {
+            metadata.state = TransactionState::Ongoing;
+            metadata.start_timestamp = now;
+        }
+        metadata.partitions.extend(partitions.iter().cloned());
+        metadata.last_update_timestamp = now;
+        if let Err(e) = persist(transactional_id, &metadata).await {
+            eprintln!(
+                "failed to persist transaction of {}: {:#}",
+                transactional_id, e
+            );
+            return UNKNOWN_SERVER_ERROR;
+        }
+        transactions.insert(transactional_id.to_string(), metadata);
+        0
+    }
+
+    /// Commits or aborts the producer's open transaction. A retry of an
+    /// EndTxn that already completed succeeds again.
+    pub async fn end_transaction(
+        &self,
+        transactional_id: &str,
+        producer_id: i64,
+        producer_epoch: i16,
+        marker: ControlMarker,
+    ) -> i16 {
+        let mut transactions = self.transactions.lock().await;
+        let metadata = match validate_producer(
+            transactions.get(transactional_id),
+            producer_id,
+            producer_epoch,
+        ) {
+            Ok(metadata) => metadata,
+            Err(error_code) => return error_code,
+        };
+        match (metadata.state, marker) {
+            (TransactionState::Ongoing, _) => {}
+            (TransactionState::CompleteCommit, ControlMarker::Commit)
+            | (TransactionState::CompleteAbort, ControlMarker::Abort) => return 0,
+            (TransactionState::PrepareCommit, ControlMarker::Commit)
+            | (TransactionState::PrepareAbort, ControlMarker::Abort) => {
+                return CONCURRENT_TRANSACTIONS
+            }
+            _ => return INVALID_TXN_STATE,
+        }
+
+        let mut metadata = metadata.clone();
+        let completed = self.complete(transactional_id, &mut metadata, marker).await;
+        let prepared = metadata.state.is_prepared();
+        transactions.insert(transactional_id.to_string(), metadata);
+        match completed {
+            Ok(()) => 0,
+            // once prepared the outcome is decided, the markers are retried
+            Err(e) if prepared => {
+                eprintln!(
+                    "failed to complete transaction of {}: {:#}",
+                    transactional_id, e
+                );
+                0
+            }
+            Err(e) => {
+                eprintln!("failed to end transaction of {}: {:#}", transactional_id, e);
+                UNKNOWN_SERVER_ERROR
+            }
+        }
+    }
+
+    /// Checks that the producer may commit offsets in its transaction,
+    /// which must include [`OFFSETS_TOPIC`] through AddOffsetsToTxn.
+    pub async fn validate_offset_commit(
+        &self,
+        transactional_id: &str,
+        producer_id: i64,
+        producer_epoch: i16,
+    ) -> i16 {
+        let transactions = self.transactions.lock().await;
+        match validate_producer(
+            transactions.get(transactional_id),
+            producer_id,
+            producer_epoch,
+        ) {
+            Ok(metadata)
+                if metadata.state == TransactionState::Ongoing
+                    && metadata.partitions.contains(&offsets_partition()) =>
+            {
+                0
+            }
+            Ok(_) => INVALID_TXN_STATE,
+            Err(error_code) => error_code,
+        }
+    }
+
+    /// Checks that the producer may write transactional records to the
+    /// partition, which must have been added through AddPartitionsToTxn.
+    pub async fn validate_produce(
+        &self,
+        transactional_id: &str,
+        producer_id: i64,
+        producer_epoch: i16,
+        partition: &TopicPartition,
+    ) -> i16 {
+        let transactions = self.transactions.lock().await;
+        match validate_producer(
+            transactions.get(transactional_id),
+            producer_id,
+            producer_epoch,
+        ) {
+            Ok(metadata)
+                if metadata.state == TransactionState::Ongoing
+                    && metadata.partitions.contains(partition) =>
+            {
+                0
+            }
+            Ok(_) => INVALID_TXN_STATE,
+            Err(error_code) => error_code,
+        }
+    }
+
+    /// Writes the marker ending the producer's transaction to one
+    /// partition. Offsets committed in the transaction are applied or
+    /// dropped once it reaches [`OFFSETS_TOPIC`].
+    pub async fn write_marker(
+        &self,
+        producer_id: i64,
+        producer_epoch: i16,
+        marker: ControlMarker,
+        coordinator_epoch: i32,
+        (topic, partition): &TopicPartition,
+    ) -> anyhow::Result<i16> {
+        let image = self.shared_metadata.snapshot();
+        let leader_epoch = if topic == OFFSETS_TOPIC {
+            0
+        } else {
+            let partition_record = image.topic(topic).and_then(|t| t.partition(*partition));
+            match partition_record {
+                Some(partition_record) => partition_record.leader_epoch as i32,
+                None => return Ok(UNKNOWN_TOPIC_OR_PARTITION),
+            }
+        };
+        let mut records = batch::build_control_batch(
+            marker,
+            producer_id,
+            producer_epoch,
+            coordinator_epoch,
+            now(),
+        );
+        let headers = batch::read_batch_headers(&records);
+        storage::append_partition_records(
+            topic,
+            *partition,
+            leader_epoch,
+            LogConfig::for_topic(&image, topic),
+            &mut records,
+            &headers,
+        )
+        .await?;
+        if topic == OFFSETS_TOPIC {
+            self.groups
+                .offsets()
+                .complete_transaction(producer_id, marker);
+        }
+        Ok(0)
+    }
+
+    // Prepares the outcome, writes the markers, then completes the
+    // transaction. The metadata stays prepared when a marker fails.
+    async fn complete(
+        &self,
+        transactional_id: &str,
+        metadata: &mut TransactionMetadata,
+        marker: ControlMarker,
+    ) -> anyhow::Result<()> {
+        if !metadata.state.is_prepared() {
+            metadata.state = match marker {
+                ControlMarker::Commit => TransactionState::PrepareCommit,
+                ControlMarker::Abort => TransactionState::PrepareAbort,
+            };
+            metadata.last_update_timestamp = now();
+            if let Err(e) = persist(transactional_id, metadata).await {
+                // nothing was decided yet
+                metadata.state = TransactionState::Ongoing;
+                return Err(e);
+            }
+        }
+
+        for partition in &metadata.partitions {
+            let error_code = self
+                .write_marker(
+                    metadata.producer_id,
+                    metadata.producer_epoch,
+                    marker,
+                    COORDINATOR_EPOCH,
+                    partition,
+                )
+                .await?;
+            // a deleted topic needs no marker
+            if error_code != 0 {
+                println!(
+                    "no marker for {}-{} of {}: error {}",
+                    partition.0, partition.1, transactional_id, error_code
+                );
+            }
+        }
+
+        let mut completed = metadata.clone();
+        completed.state = match marker {
+            ControlMarker::Commit => TransactionState::CompleteCommit,
+            ControlMarker::Abort => TransactionState::CompleteAbort,
+        };
+        completed.partitions.clear();
+        completed.start_timestamp = -1;
+        completed.last_update_timestamp = now();
+        persist(transactional_id, &completed).await?;
+        *metadata = completed;
+        Ok(())
+    }
+
+    /// Aborts transactions open for longer than their timeout and finishes
+    /// prepared ones, until the process exits.
+    pub async fn run_expiration(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(EXPIRATION_INTERVAL).await;
+            self.expire(now()).await;
+        }
+    }
+
+    async fn expire(&self, now: i64) {
+        let mut transactions = self.transactions.lock().await;
+        for (transactional_id, metadata) in transactions.iter_mut() {
+            let marker = match metadata.state {
+                TransactionState::PrepareCommit => ControlMarker::Commit,
+                TransactionState::PrepareAbort => ControlMarker::Abort,
+                TransactionState::Ongoing
+                    if now - metadata.start_timestamp > metadata.timeout_ms as i64 =>
+                {
+                    println!("aborting timed out transaction of {}", transactional_id);
+                    // fences the producer, its next request fails
+                    metadata.producer_epoch += 1;
+                    ControlMarker::Abort
+                }
+                _ => continue,
+            };
+            let producer_epoch = metadata.producer_epoch;
+            if let Err(e) = self.complete(transactional_id, metadata, marker).await {
+                eprintln!(
+                    "failed to complete transaction of {}: {:#}",
+                    transactional_id, e
+                );
+                if metadata.state == TransactionState::Ongoing {
+                    // the timeout is handled again from the start
+                    metadata.producer_epoch = producer_epoch - 1;
+                }
+            }
+        }
+    }
+}
+
+pub fn offsets_partition() -> TopicPartition {
+    (OFFSETS_TOPIC.to_string(), OFFSETS_PARTITION)
+}
+
+fn validate_producer(
+    metadata: Option<&TransactionMetadata>,
+    producer_id: i64,
+    producer_epoch: i16,
+) -> Result<&TransactionMetadata, i16> {
+    match metadata {
+        Some(metadata) if metadata.producer_id == producer_id => {
+            if metadata.producer_epoch != producer_epoch {
+                return Err(INVALID_PRODUCER_EPOCH);
+            }
+            Ok(metadata)
+        }
+        _ => Err(INVALID_PRODUCER_ID_MAPPING),
+    }
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as i64)
+}
+
+async fn persist(transactional_id: &str, metadata: &TransactionMetadata) -> anyhow::Result<()> {
+    let key = encode_key(transactional_id);
+    let value = encode_value(metadata);
+    let mut records = batch::build_batch(&[(Some(&key), Some(&value))], now());
+    let headers = batch::read_batch_headers(&records);
+    storage::append_partition_records(
+        TRANSACTION_STATE_TOPIC,
+        TRANSACTION_STATE_PARTITION,
+        0,
+        LogConfig::default(),
+        &mut records,
+        &headers,
+    )
+    .await?;
+    Ok(())
+}
+
+// Returns None for records without a key or with a key of an unknown
+// version, and None metadata for tombstones
+async fn decode_record(
+    record: &batch::Record,
+) -> anyhow::Result<Option<(String, Option<TransactionMetadata>)>> {
+    let Some(key) = &record.key else {
+        return Ok(None);
+    };
+    let Some(transactional_id) = decode_key(key).await? else {
+        return Ok(None);
+    };
+    let metadata = match &record.value {
+        Some(value) => Some(decode_value(value).await?),
+        None => None,
+    };
+    Ok(Some((transactional_id, metadata)))
+}
+
+fn encode_key(transactional_id: &str) -> Vec<u8> {
+    let mut key = Vec::new();
+    key.put_i16(TRANSACTION_LOG_KEY_VERSION); // version
+    key.write_string(false, transactional_id); // transactional_id
+    key
+}
+
+// Returns None for keys of unknown versions
+async fn decode_key(key: &Vec<u8>) -> anyhow::Result<Option<String>> {
+    let mut cursor = Cursor::new(key);
+    if cursor.read_i16().await? != TRANSACTION_LOG_KEY_VERSION {
+        return Ok(None);
+    }
+    Ok(Some(cursor.read_string(false).await?))
+}
+
+fn encode_value(metadata: &TransactionMetadata) -> Vec<u8> {
+    let mut value = Vec::new();
+    value.put_i16(TRANSACTION_LOG_VALUE_VERSION); // version
+    value.put_i64(metadata.producer_id); // producer_id
+    value.put_i16(metadata.producer_epoch); // producer_epoch
+    value.put_i32(metadata.timeout_ms); // transaction_timeout_ms
+    value.put_i8(metadata.state.id()); // transaction_status
+    if metadata.partitions.is_empty() {
+        value.put_i32(-1); // null transaction_partitions
+    } else {
+        let mut topics: BTreeMap<&str, Vec<i32>> = BTreeMap::new();
+        for (topic, partition) in &metadata.partitions {
+            topics.entry(topic).or_default().push(*partition);
+        }
+        value.put_i32(topics.len() as i32);
+        for (topic, partition_ids) in topics {
+            value.write_string(false, topic); // topic
+            value.put_i32(partition_ids.len() as i32);
+            for partition_id in partition_ids {
+                value.put_i32(partition_id); // partition_ids
+            }
+        }
+    }
+    value.put_i64(metadata.last_update_timestamp); // transaction_last_update_timestamp_ms
+    value.put_i64(metadata.start_timestamp); // transaction_start_timestamp_ms
+    value
+}
+
+async fn decode_value(value: &Vec<u8>) -> anyhow::Result<TransactionMetadata> {
+    let mut cursor = Cursor::new(value);
+    let version = cursor.read_i16().await?;
+    if version != TRANSACTION_LOG_VALUE_VERSION {
+        anyhow::bail!("unsupported transaction log value version {version}");
+    }
+    let producer_id = cursor.read_i64().await?;
+    let producer_epoch = cursor.read_i16().await?;
+    let timeout_ms = cursor.read_i32().await?;
+    let status = cursor.read_i8().await?;
+    let state = TransactionState::from_id(status)
+        .ok_or_else(|| anyhow::anyhow!("unknown transaction status {status}"))?;
+    let mut partitions = BTreeSet::new();
+    let topics_length = cursor.read_nullable_array_length(false).await?;
+    for _ in 0..topics_length.unwrap_or_default() {
+        let topic = cursor.read_string(false).await?;
+        for _ in 0..cursor.read_array_length(false).await? {
+            partitions.insert((topic.clone(), cursor.read_i32().await?));
+        }
+    }
+    let last_update_timestamp = cursor.read_i64().await?;
+    let start_timestamp = cursor.read_i64().await?;
+    Ok(TransactionMetadata {
+        producer_id,
+        producer_epoch,
+        timeout_ms,
+        state,
+        partitions,
+        last_update_timestamp,
+        start_timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        handler::create_topics::partition_record,
+        metadata::{
+            cluster::{CorruptBatchPolicy, TopicValueRecord, ValueRecord},
+            image::MetadataImage,
+        },
+    };
+
+    // A coordinator without the transactions of other tests, and a topic of
+    // one partition for its markers
+    async fn coordinator(topic: &str) -> anyhow::Result<TransactionCoordinator> {
+        let shared_metadata = Arc::new(SharedImage::new(
+            MetadataImage::default(),
+            0,
+            CorruptBatchPolicy::default(),
+        ));
+        let topic_id = uuid::Uuid::new_v4();
+        shared_metadata
+            .writer()
+            .await?
+            .append(&[
+                ValueRecord::TopicValue(TopicValueRecord {
+                    name: topic.to_string(),
+                    uuid: topic_id,
+                }),
+                ValueRecord::PartitionValue(partition_record(topic_id, 0, &[1])),
+            ])
+            .await?;
+        Ok(TransactionCoordinator {
+            transactions: Mutex::new(HashMap::new()),
+            producer_ids: ProducerIdManager::new(),
+            shared_metadata,
+            groups: Arc::new(GroupCoordinator::load().await?),
+        })
+    }
+
+    async fn state(
+        coordinator: &TransactionCoordinator,
+        transactional_id: &str,
+    ) -> TransactionMetadata {
+        coordinator.transactions.lock().await[transactional_id].clone()
+    }
+
+    async fn high_watermark(topic: &str) -> anyhow::Result<i64> {
+        storage::open_partition_log(topic, 0)
+            .await?
+            .high_watermark()
+            .await
+    }
+
+    #[tokio::test]
+    async fn retries_end_txn_and_finishes_prepared_transactions() -> anyhow::Result<()> {
+        let topic = "txn-retries";
+        let coordinator = coordinator(topic).await?;
+        let (error_code, producer_id, epoch) = coordinator
+            .init_producer_id(Some("retries"), 60_000, -1, -1)
+            .await;
+        assert_eq!(error_code, 0);
+        let partitions = [(topic.to_string(), 0)];
+        assert_eq!(
+            coordinator
+                .add_partitions("retries", producer_id, epoch, &partitions)
+                .await,
+            0
+        );
+
+        // a file where the partition directory should be fails the marker
+        let dir = storage::partition_dir(topic, 0);
+        std::fs::create_dir_all(dir.parent().unwrap())?;
+        std::fs::write(&dir, b"")?;
+        let commit = ControlMarker::Commit;
+        assert_eq!(
+            coordinator
+                .end_transaction("retries", producer_id, epoch, commit)
+                .await,
+            0
+        );
+        assert_eq!(
+            state(&coordinator, "retries").await.state,
+            TransactionState::PrepareCommit
+        );
+        assert_eq!(
+            coordinator
+                .end_transaction("retries", producer_id, epoch, commit)
+                .await,
+            CONCURRENT_TRANSACTIONS
+        );
+        assert_eq!(
+            coordinator
+                .end_transaction("retries", producer_id, epoch, ControlMarker::Abort)
+                .await,
+            INVALID_TXN_STATE
+        );
+        assert_eq!(
+            coordinator
+                .add_partitions("retries", producer_id, epoch, &partitions)
+                .await,
+            CONCURRENT_TRANSACTIONS
+        );
+
+        // the expiration writes the marker once it can
+        std::fs::remove_file(&dir)?;
+        coordinator.expire(now()).await;
+        let completed = state(&coordinator, "retries").await;
+        assert_eq!(completed.state, TransactionState::CompleteCommit);
+        assert!(completed.partitions.is_empty());
+        assert_eq!(high_watermark(topic).await?, 1);
+
+        assert_eq!(
+            coordinator
+                .end_transaction("retries", producer_id, epoch, commit)
+                .await,
+            0
+        );
+        assert_eq!(
+            coordinator
+                .end_transaction("retries", producer_id, epoch, ControlMarker::Abort)
+                .await,
+            INVALID_TXN_STATE
+        );
+        // no second marker for the retry
+        assert_eq!(high_watermark(topic).await?, 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn only_added_partitions_take_transactional_records() -> anyhow::Result<()> {
+        let topic = "txn-produce";
+        let coordinator = coordinator(topic).await?;
+        let (_, producer_id, epoch) = coordinator
+            .init_producer_id(Some("produce"), 60_000, -1, -1)
+            .await;
+        let partition = (topic.to_string(), 0);
+        let validate = |producer_epoch, partition| {
+            coordinator.validate_produce("produce", producer_id, producer_epoch, partition)
+        };
+        assert_eq!(validate(epoch, &partition).await, INVALID_TXN_STATE);
+
+        let added = coordinator
+            .add_partitions(
+                "produce",
+                producer_id,
+                epoch,
+                std::slice::from_ref(&partition),
+            )
+            .await;
+        assert_eq!(added, 0);
+        assert_eq!(validate(epoch, &partition).await, 0);
+        let other = (topic.to_string(), 1);
+        assert_eq!(validate(epoch, &other).await, INVALID_TXN_STATE);
+        assert_eq!(
+            validate(epoch + 1, &partition).await,
+            INVALID_PRODUCER_EPOCH
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn init_producer_id_aborts_the_open_transaction() -> anyhow::Result<()> {
+        let topic = "txn-reinit";
+        let coordinator = coordinator(topic).await?;
+        let (_, producer_id, epoch) = coordinator
+            .init_producer_id(Some("reinit"), 60_000, -1, -1)
+            .await;
+        let partitions = [(topic.to_string(), 0)];
+        coordinator
+            .add_partitions("reinit", producer_id, epoch, &partitions)
+            .await;
+
+        assert_eq!(
+            coordinator
+                .init_producer_id(Some("reinit"), 60_000, -1, -1)
+                .await,
+            (0, producer_id, epoch + 1)
+        );
+        let metadata = state(&coordinator, "reinit").await;
+        assert_eq!(metadata.state, TransactionState::Empty);
+        assert!(metadata.partitions.is_empty());
+        // the abort marker
+        assert_eq!(high_watermark(topic).await?, 1);
+        assert_eq!(
+            coordinator
+                .add_partitions("reinit", producer_id, epoch, &partitions)
+                .await,
+            INVALID_PRODUCER_EPOCH
+        );
+        // a stale producer cannot bump its replacement's epoch
+        assert_eq!(
+            coordinator
+                .init_producer_id(Some("reinit"), 60_000, producer_id, epoch)
+                .await
+                .0,
+            INVALID_PRODUCER_EPOCH
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expire_aborts_timed_out_transactions() -> anyhow::Result<()> {
+        let topic = "txn-expire";
+        let coordinator = coordinator(topic).await?;
+        let (_, producer_id, epoch) = coordinator
+            .init_producer_id(Some("expire"), 1_000, -1, -1)
+            .await;
+        let partitions = [(topic.to_string(), 0)];
+        coordinator
+            .add_partitions("expire", producer_id, epoch, &partitions)
+            .await;
+
+        coordinator.expire(now()).await;
+        assert_eq!(
+            state(&coordinator, "expire").await.state,
+            TransactionState::Ongoing
+        );
+        coordinator.expire(now() + 2_000).await;
+        let metadata = state(&coordinator, "expire").await;
+        assert_eq!(metadata.state, TransactionState::CompleteAbort);
+        assert_eq!(metadata.producer_epoch, epoch + 1);
+        assert_eq!(high_watermark(topic).await?, 1);
+
+        // the producer was fenced
+        assert_eq!(
+            coordinator
+                .end_transaction("expire", producer_id, epoch, ControlMarker::Commit)
+                .await,
+            INVALID_PRODUCER_EPOCH
+        );
+        assert_eq!(
+            coordinator
+                .add_partitions("expire", producer_id, epoch, &partitions)
+                .await,
+            INVALID_PRODUCER_EPOCH
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn round_trips_transaction_log_records() -> anyhow::Result<()> {
+        let mut metadata = TransactionMetadata {
+            producer_id: 1000,
+            producer_epoch: 3,
+            timeout_ms: 60_000,
+            state: TransactionState::Ongoing,
+            partitions: BTreeSet::from([
+                ("orders".to_string(), 1),
+                ("orders".to_string(), 0),
+                offsets_partition(),
+            ]),
+            last_update_timestamp: 2000,
+            start_timestamp: 1000,
+        };
+        assert_eq!(
+            decode_key(&encode_key("txn")).await?,
+            Some("txn".to_string())
+        );
+        assert_eq!(decode_value(&encode_value(&metadata)).await?, metadata);
+
+        metadata.state = TransactionState::CompleteCommit;
+        metadata.partitions.clear();
+        assert_eq!(decode_value(&encode_value(&metadata)).await?, metadata);
+
+        // a tombstone, and a status `load` skips
+        let mut record = batch::Record {
+            offset: 0,
+            timestamp: 0,
+            key: Some(encode_key("txn")),
+            value: None,
+        };
+        assert_eq!(
+            decode_record(&record).await?,
+            Some(("txn".to_string(), None))
+        );
+        let mut value = encode_value(&metadata);
+        value[16] = 42; // transaction_status
+        record.value = Some(value);
+        assert!(decode_record(&record).await.is_err());
+        Ok(())
+    }
+}
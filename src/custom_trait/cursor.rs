@@ -6,10 +6,6 @@ pub trait AsyncReadVarint {
     async fn async_read_varint(&mut self) -> anyhow::Result<i64>;
     async fn async_read_uvarint(&mut self) -> anyhow::Result<u64>;
 }
-pub trait AsyncWriteVarint {
-    async fn async_write_varint(&mut self, num: i64) -> anyhow::Result<()>;
-    async fn async_write_uvarint(&mut self, num: u64) -> anyhow::Result<()>;
-}
 
 pub trait ReadVarint {
     fn read_varint(&mut self) -> anyhow::Result<i64>;
@@ -58,45 +54,6 @@ impl AsyncReadVarint for Cursor<&Vec<u8>> {
     }
 }
 
-impl AsyncWriteVarint for Vec<u8> {
-    async fn async_write_varint(&mut self, num: i64) -> anyhow::Result<()> {
-        let mut n = num;
-        let mut result = Vec::new();
-
-        // Zig-zag encode the signed integer
-        n = (n << 1) ^ (n >> 63); // Apply zig-zag encoding
-
-        while (n & !0x7F) != 0 {
-            result.push(((n & 0x7F) | 0x80) as u8); // Push 7 bits with continuation bit
-            n >>= 7;
-        }
-
-        result.push(n as u8); // Push the last byte
-
-        // Extend the result into the original vector
-        self.extend(result);
-
-        Ok(())
-    }
-
-    async fn async_write_uvarint(&mut self, num: u64) -> anyhow::Result<()> {
-        let mut n = num;
-        let mut result = Vec::new();
-
-        while (n & !0x7F) != 0 {
-            result.push(((n & 0x7F) | 0x80) as u8); // Push 7 bits with continuation bit
-            n >>= 7;
-        }
-
-        result.push(n as u8); // Push the last byte
-
-        // Extend the result into the original vector
-        self.extend(result);
-
-        Ok(())
-    }
-}
-
 impl ReadVarint for Cursor<&Vec<u8>> {
     fn read_varint(&mut self) -> anyhow::Result<i64> {
         // Step 1: Decode varint to get the unsigned value
@@ -113,7 +70,7 @@ impl ReadVarint for Cursor<&Vec<u8>> {
 
         loop {
             let mut buffer = [0u8; 1];
-            self.read_exact(&mut buffer);
+            std::io::Read::read_exact(self, &mut buffer)?;
 
             let byte = buffer[0];
             value |= ((byte & 0x7F) as u64) << shift;
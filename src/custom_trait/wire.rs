@@ -0,0 +1,231 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use super::cursor::{AsyncReadVarint, WriteVarint};
+
+/// Reads the primitive types of the Kafka protocol in either the classic or
+/// the flexible (compact, tagged fields) encoding.
+pub trait ReadWire {
+    async fn read_array_length(&mut self, flexible: bool) -> anyhow::Result<usize>;
+    async fn read_nullable_array_length(&mut self, flexible: bool)
+        -> anyhow::Result<Option<usize>>;
+    async fn read_string(&mut self, flexible: bool) -> anyhow::Result<String>;
+    async fn read_nullable_string(&mut self, flexible: bool) -> anyhow::Result<Option<String>>;
+    async fn read_bytes(&mut self, flexible: bool) -> anyhow::Result<Vec<u8>>;
+    async fn read_nullable_bytes(&mut self, flexible: bool) -> anyhow::Result<Option<Vec<u8>>>;
+    async fn skip_tagged_fields(&mut self, flexible: bool) -> anyhow::Result<()>;
+    async fn read_tagged_fields(&mut self) -> anyhow::Result<Vec<(u64, Vec<u8>)>>;
+}
+
+/// Writes the primitive types of the Kafka protocol in either the classic or
+/// the flexible (compact, tagged fields) encoding.
+pub trait WriteWire {
+    fn write_array_length(&mut self, flexible: bool, length: usize);
+    fn write_string(&mut self, flexible: bool, value: &str);
+    fn write_nullable_string(&mut self, flexible: bool, value: Option<&str>);
+    fn write_bytes(&mut self, flexible: bool, value: &[u8]);
+    fn write_nullable_bytes(&mut self, flexible: bool, value: Option<&[u8]>);
+    fn write_tagged_fields(&mut self, flexible: bool);
+}
+
+impl ReadWire for Cursor<&Vec<u8>> {
+    async fn read_array_length(&mut self, flexible: bool) -> anyhow::Result<usize> {
+        Ok(self
+            .read_nullable_array_length(flexible)
+            .await?
+            .unwrap_or_default())
+    }
+
+    async fn read_nullable_array_length(
+        &mut self,
+        flexible: bool,
+    ) -> anyhow::Result<Option<usize>> {
+        let length = if flexible {
+            // compact arrays store length + 1, 0 means null
+            self.async_read_uvarint().await? as i64 - 1
+        } else {
+            self.read_i32().await? as i64
+        };
+        Ok((length >= 0).then_some(length as usize))
+    }
+
+    async fn read_string(&mut self, flexible: bool) -> anyhow::Result<String> {
+        Ok(self
+            .read_nullable_string(flexible)
+            .await?
+            .unwrap_or_default())
+    }
+
+    async fn read_nullable_string(&mut self, flexible: bool) -> anyhow::Result<Option<String>> {
+        let length = if flexible {
+            self.async_read_uvarint().await? as i64 - 1
+        } else {
+            self.read_i16().await? as i64
+        };
+        if length < 0 {
+            return Ok(None);
+        }
+        let buf = read_checked(self, length as u64, "string").await?;
+        Ok(Some(String::from_utf8(buf)?))
+    }
+
+    async fn read_bytes(&mut self, flexible: bool) -> anyhow::Result<Vec<u8>> {
+        Ok(self
+            .read_nullable_bytes(flexible)
+            .await?
+            .unwrap_or_default())
+    }
+
+    async fn read_nullable_bytes(&mut self, flexible: bool) -> anyhow::Result<Option<Vec<u8>>> {
+        let length = if flexible {
+            self.async_read_uvarint().await? as i64 - 1
+        } else {
+            self.read_i32().await? as i64
+        };
+        if length < 0 {
+            return Ok(None);
+        }
+        Ok(Some(read_checked(self, length as u64, "bytes").await?))
+    }
+
+    async fn skip_tagged_fields(&mut self, flexible: bool) -> anyhow::Result<()> {
+        if !flexible {
+            return Ok(());
+        }
+        let tagged_fields_count = self.async_read_uvarint().await?;
+        for _ in 0..tagged_fields_count {
+            let _tag = self.async_read_uvarint().await?;
+            let size = self.async_read_uvarint().await?;
+            self.set_position(self.position() + size);
+        }
+        Ok(())
+    }
+
+    async fn read_tagged_fields(&mut self) -> anyhow::Result<Vec<(u64, Vec<u8>)>> {
+        let tagged_fields_count = self.async_read_uvarint().await?;
+        let mut tagged_fields = Vec::new();
+        for _ in 0..tagged_fields_count {
+            let tag = self.async_read_uvarint().await?;
+            let size = self.async_read_uvarint().await?;
+            let data = read_checked(self, size, "tagged field").await?;
+            tagged_fields.push((tag, data));
+        }
+        Ok(tagged_fields)
+    }
+}
+
+// Reads `length` bytes, refusing lengths beyond the end of the request
+// before allocating for them
+async fn read_checked(
+    cursor: &mut Cursor<&Vec<u8>>,
+    length: u64,
+    what: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let size = cursor.get_ref().len() as u64;
+    let remaining = size - cursor.position().min(size);
+    if length > remaining {
+        anyhow::bail!("{} length {} exceeds remaining {}", what, length, remaining);
+    }
+    let mut buf = vec![0u8; length as usize];
+    cursor.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+impl WriteWire for Vec<u8> {
+    fn write_array_length(&mut self, flexible: bool, length: usize) {
+        if flexible {
+            self.write_uvarint(length as u64 + 1);
+        } else {
+            self.put_i32(length as i32);
+        }
+    }
+
+    fn write_string(&mut self, flexible: bool, value: &str) {
+        self.write_nullable_string(flexible, Some(value));
+    }
+
+    fn write_nullable_string(&mut self, flexible: bool, value: Option<&str>) {
+        match (value, flexible) {
+            (Some(value), true) => {
+                self.write_uvarint(value.len() as u64 + 1);
+                self.put_slice(value.as_bytes());
+            }
+            (Some(value), false) => {
+                self.put_i16(value.len() as i16);
+                self.put_slice(value.as_bytes());
+            }
+            (None, true) => self.write_uvarint(0),
+            (None, false) => self.put_i16(-1),
+        }
+    }
+
+    fn write_bytes(&mut self, flexible: bool, value: &[u8]) {
+        self.write_nullable_bytes(flexible, Some(value));
+    }
+
+    fn write_nullable_bytes(&mut self, flexible: bool, value: Option<&[u8]>) {
+        match (value, flexible) {
+            (Some(value), true) => {
+                self.write_uvarint(value.len() as u64 + 1);
+                self.put_slice(value);
+            }
+            (Some(value), false) => {
+                self.put_i32(value.len() as i32);
+                self.put_slice(value);
+            }
+            (None, true) => self.write_uvarint(0),
+            (None, false) => self.put_i32(-1),
+        }
+    }
+
+    fn write_tagged_fields(&mut self, flexible: bool) {
+        if flexible {
+            self.put_u8(0); // empty tagged fields
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn round_trip_classic_and_compact() -> anyhow::Result<()> {
+        for flexible in [false, true] {
+            let mut data: Vec<u8> = Vec::new();
+            data.write_array_length(flexible, 2);
+            data.write_string(flexible, "topic");
+            data.write_nullable_string(flexible, None);
+            data.write_nullable_bytes(flexible, Some(&[1, 2, 3]));
+            data.write_tagged_fields(flexible);
+
+            let mut cursor = Cursor::new(&data);
+            assert_eq!(cursor.read_array_length(flexible).await?, 2);
+            assert_eq!(cursor.read_string(flexible).await?, "topic");
+            assert_eq!(cursor.read_nullable_string(flexible).await?, None);
+            assert_eq!(
+                cursor.read_nullable_bytes(flexible).await?,
+                Some(vec![1, 2, 3])
+            );
+            cursor.skip_tagged_fields(flexible).await?;
+            assert_eq!(cursor.position() as usize, data.len());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_lengths_past_the_end() {
+        // a compact string claiming i64::MAX - 1 bytes
+        let mut data: Vec<u8> = Vec::new();
+        data.write_uvarint(i64::MAX as u64);
+        data.put_slice(b"abc");
+        assert!(Cursor::new(&data).read_nullable_string(true).await.is_err());
+
+        let mut data: Vec<u8> = Vec::new();
+        data.put_i16(4);
+        data.put_slice(b"abc");
+        assert!(Cursor::new(&data).read_string(false).await.is_err());
+    }
+}
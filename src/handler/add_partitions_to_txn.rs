@@ -0,0 +1,113 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    coordinator::{offsets::TopicPartition, transaction::TransactionCoordinator},
+    custom_trait::wire::{ReadWire, WriteWire},
+    metadata::image::MetadataImage,
+    protocol::{request::Request, response::Response},
+};
+
+static UNKNOWN_TOPIC_OR_PARTITION: i16 = 3;
+static OPERATION_NOT_ATTEMPTED: i16 = 55;
+
+#[derive(Debug)]
+pub struct AddPartitionsToTxnRequest {
+    pub transactional_id: String,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub topics: Vec<(String, Vec<i32>)>,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    cluster: &MetadataImage,
+    transactions: &TransactionCoordinator,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 3 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    let partitions: Vec<TopicPartition> = parsed
+        .topics
+        .iter()
+        .flat_map(|(name, indexes)| indexes.iter().map(|index| (name.clone(), *index)))
+        .collect();
+    let unknown: Vec<&TopicPartition> = partitions
+        .iter()
+        .filter(|(name, index)| {
+            cluster
+                .topic(name)
+                .and_then(|topic| topic.partition(*index))
+                .is_none()
+        })
+        .collect();
+    // the partitions are added all together or not at all
+    let error_code = if unknown.is_empty() {
+        transactions
+            .add_partitions(
+                &parsed.transactional_id,
+                parsed.producer_id,
+                parsed.producer_epoch,
+                &partitions,
+            )
+            .await
+    } else {
+        OPERATION_NOT_ATTEMPTED
+    };
+
+    res.body.put_i32(0); // throttle_time_ms
+    res.body.write_array_length(flexible, parsed.topics.len());
+    for (name, indexes) in &parsed.topics {
+        res.body.write_string(flexible, name); // name
+        res.body.write_array_length(flexible, indexes.len());
+        for index in indexes {
+            let partition_error_code = if unknown.contains(&&(name.clone(), *index)) {
+                UNKNOWN_TOPIC_OR_PARTITION
+            } else {
+                error_code
+            };
+            res.body.put_i32(*index); // partition_index
+            res.body.put_i16(partition_error_code); // partition_error_code
+            res.body.write_tagged_fields(flexible);
+        }
+        res.body.write_tagged_fields(flexible);
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<AddPartitionsToTxnRequest> {
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let transactional_id = cursor.read_string(flexible).await?;
+    let producer_id = cursor.read_i64().await?;
+    let producer_epoch = cursor.read_i16().await?;
+    let topics_length = cursor.read_array_length(flexible).await?;
+    let mut topics = Vec::new();
+    for _ in 0..topics_length {
+        let name = cursor.read_string(flexible).await?;
+        let mut partitions = Vec::new();
+        for _ in 0..cursor.read_array_length(flexible).await? {
+            partitions.push(cursor.read_i32().await?);
+        }
+        cursor.skip_tagged_fields(flexible).await?;
+        topics.push((name, partitions));
+    }
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(AddPartitionsToTxnRequest {
+        transactional_id,
+        producer_id,
+        producer_epoch,
+        topics,
+    })
+}
@@ -9,6 +9,16 @@ struct SupportedAPI {
 }
 
 static SUPPORTED_APIS: &[SupportedAPI] = &[
+    SupportedAPI {
+        api_key: 0,
+        min_version: 3,
+        max_version: 11,
+    },
+    SupportedAPI {
+        api_key: 3,
+        min_version: 0,
+        max_version: 12,
+    },
     SupportedAPI {
         api_key: 18,
         min_version: 0,
@@ -21,9 +31,94 @@ static SUPPORTED_APIS: &[SupportedAPI] = &[
     },
     SupportedAPI {
         api_key: 1,
-        min_version: 0,
+        min_version: 4,
         max_version: 16,
     },
+    SupportedAPI {
+        api_key: 2,
+        min_version: 1,
+        max_version: 8,
+    },
+    SupportedAPI {
+        api_key: 10,
+        min_version: 0,
+        max_version: 4,
+    },
+    SupportedAPI {
+        api_key: 11,
+        min_version: 0,
+        max_version: 9,
+    },
+    SupportedAPI {
+        api_key: 12,
+        min_version: 0,
+        max_version: 4,
+    },
+    SupportedAPI {
+        api_key: 13,
+        min_version: 0,
+        max_version: 5,
+    },
+    SupportedAPI {
+        api_key: 14,
+        min_version: 0,
+        max_version: 5,
+    },
+    SupportedAPI {
+        api_key: 8,
+        min_version: 0,
+        max_version: 9,
+    },
+    SupportedAPI {
+        api_key: 9,
+        min_version: 0,
+        max_version: 9,
+    },
+    SupportedAPI {
+        api_key: 19,
+        min_version: 0,
+        max_version: 7,
+    },
+    SupportedAPI {
+        api_key: 20,
+        min_version: 0,
+        max_version: 6,
+    },
+    SupportedAPI {
+        api_key: 37,
+        min_version: 0,
+        max_version: 3,
+    },
+    SupportedAPI {
+        api_key: 22,
+        min_version: 0,
+        max_version: 4,
+    },
+    SupportedAPI {
+        api_key: 24,
+        min_version: 0,
+        max_version: 3,
+    },
+    SupportedAPI {
+        api_key: 25,
+        min_version: 0,
+        max_version: 3,
+    },
+    SupportedAPI {
+        api_key: 26,
+        min_version: 0,
+        max_version: 3,
+    },
+    SupportedAPI {
+        api_key: 27,
+        min_version: 0,
+        max_version: 1,
+    },
+    SupportedAPI {
+        api_key: 28,
+        min_version: 0,
+        max_version: 3,
+    },
 ];
 
 pub fn handle(req: &Request, res: &mut Response) {
@@ -0,0 +1,379 @@
+use std::{collections::HashMap, io::Cursor};
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    custom_trait::wire::{ReadWire, WriteWire},
+    handler::create_topics::{
+        assign_replicas, available_brokers, create_partition_dirs, partition_record,
+        validate_replicas,
+    },
+    metadata::{
+        cluster::ValueRecord,
+        image::{MetadataImage, TopicImage},
+        tailer::SharedImage,
+    },
+    protocol::{request::Request, response::Response},
+};
+
+static UNKNOWN_SERVER_ERROR: i16 = -1;
+static UNKNOWN_TOPIC_OR_PARTITION: i16 = 3;
+static INVALID_PARTITIONS: i16 = 37;
+static INVALID_REPLICATION_FACTOR: i16 = 38;
+static INVALID_REPLICA_ASSIGNMENT: i16 = 39;
+static INVALID_REQUEST: i16 = 42;
+
+#[derive(Debug)]
+pub struct CreatePartitionsRequest {
+    pub topics: Vec<CreatePartitionsTopic>,
+    pub validate_only: bool,
+}
+
+#[derive(Debug)]
+pub struct CreatePartitionsTopic {
+    pub name: String,
+    // the new total, not the number added
+    pub count: i32,
+    // broker ids of each new partition, null to spread them automatically
+    pub assignments: Option<Vec<Vec<i32>>>,
+}
+
+#[derive(Debug)]
+struct TopicResult {
+    name: String,
+    error_code: i16,
+    error_message: Option<String>,
+}
+
+// Partitions a topic grows by, numbered from `start`
+struct NewPartitions {
+    topic_id: uuid::Uuid,
+    start: i32,
+    assignment: Vec<Vec<u32>>,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    shared_metadata: &SharedImage,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 3 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    let mut writer = shared_metadata.writer().await?;
+    let brokers = available_brokers(writer.image());
+    let mut name_counts: HashMap<&str, usize> = HashMap::new();
+    for topic in &parsed.topics {
+        *name_counts.entry(&topic.name).or_default() += 1;
+    }
+
+    let mut results = Vec::new();
+    let mut additions = Vec::new();
+    for topic in &parsed.topics {
+        let planned = if name_counts[topic.name.as_str()] > 1 {
+            Err((INVALID_REQUEST, "Duplicate topic name.".to_string()))
+        } else {
+            plan_partitions(topic, writer.image(), &brokers)
+        };
+        match planned {
+            Ok(new_partitions) => {
+                additions.push((topic.name.as_str(), new_partitions));
+                results.push(TopicResult {
+                    name: topic.name.clone(),
+                    error_code: 0,
+                    error_message: None,
+                });
+            }
+            Err((error_code, message)) => results.push(TopicResult {
+                name: topic.name.clone(),
+                error_code,
+                error_message: Some(message),
+            }),
+        }
+    }
+
+    if !parsed.validate_only && !additions.is_empty() {
+        let records: Vec<ValueRecord> = additions
+            .iter()
+            .flat_map(|(_, new_partitions)| {
+                (new_partitions.start..)
+                    .zip(&new_partitions.assignment)
+                    .map(|(index, replicas)| {
+                        ValueRecord::PartitionValue(partition_record(
+                            new_partitions.topic_id,
+                            index as u32,
+                            replicas,
+                        ))
+                    })
+            })
+            .collect();
+        if let Err(e) = writer.append(&records).await {
+            eprintln!("failed to create partitions: {:#}", e);
+            for result in results.iter_mut().filter(|r| r.error_code == 0) {
+                result.error_code = UNKNOWN_SERVER_ERROR;
+                result.error_message = Some(e.to_string());
+            }
+        } else {
+            for (name, new_partitions) in &additions {
+                let created =
+                    create_partition_dirs(name, new_partitions.start, &new_partitions.assignment)
+                        .await;
+                if let Err(e) = created {
+                    eprintln!("failed to create partition directories of {}: {}", name, e);
+                }
+            }
+        }
+    }
+    drop(writer);
+
+    res.body.put_i32(0); // throttle_time_ms
+    res.body.write_array_length(flexible, results.len());
+    for result in &results {
+        res.body.write_string(flexible, &result.name); // name
+        res.body.put_i16(result.error_code); // error_code
+        let error_message = result.error_message.as_deref();
+        res.body.write_nullable_string(flexible, error_message); // error_message
+        res.body.write_tagged_fields(flexible);
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+// Validates the new count and returns the replicas of the added partitions
+fn plan_partitions(
+    topic: &CreatePartitionsTopic,
+    image: &MetadataImage,
+    brokers: &[i32],
+) -> Result<NewPartitions, (i16, String)> {
+    let Some(existing) = image.topic(&topic.name) else {
+        return Err((
+            UNKNOWN_TOPIC_OR_PARTITION,
+            format!("The topic '{}' does not exist.", topic.name),
+        ));
+    };
+    let current = existing.partitions.len() as i32;
+    if topic.count == current {
+        return Err((
+            INVALID_PARTITIONS,
+            format!("Topic already has {current} partitions."),
+        ));
+    }
+    if topic.count < current {
+        return Err((
+            INVALID_PARTITIONS,
+            format!(
+                "Topic currently has {current} partitions, which is higher than the requested {}.",
+                topic.count
+            ),
+        ));
+    }
+
+    let replication_factor = replication_factor(existing);
+    let added = topic.count - current;
+    let assignment = match &topic.assignments {
+        Some(assignments) => {
+            if assignments.len() != added as usize {
+                return Err((
+                    INVALID_REPLICA_ASSIGNMENT,
+                    format!(
+                        "Increasing the number of partitions by {added} but {} assignments provided.",
+                        assignments.len()
+                    ),
+                ));
+            }
+            let mut assignment = Vec::new();
+            for (index, broker_ids) in (current..).zip(assignments) {
+                if broker_ids.len() != replication_factor {
+                    return Err((
+                        INVALID_REPLICA_ASSIGNMENT,
+                        format!(
+                            "Partition {index} has {} replicas, the topic's partitions have {replication_factor}.",
+                            broker_ids.len()
+                        ),
+                    ));
+                }
+                let replicas = validate_replicas(index, broker_ids, brokers)
+                    .map_err(|message| (INVALID_REPLICA_ASSIGNMENT, message))?;
+                assignment.push(replicas);
+            }
+            assignment
+        }
+        None => {
+            if replication_factor > brokers.len() {
+                return Err((
+                    INVALID_REPLICATION_FACTOR,
+                    format!(
+                        "Unable to replicate the partition {} time(s): The target replication factor of {} cannot be reached because only {} broker(s) are registered.",
+                        replication_factor,
+                        replication_factor,
+                        brokers.len()
+                    ),
+                ));
+            }
+            assign_replicas(current, topic.count, replication_factor as i16, brokers)
+        }
+    };
+    Ok(NewPartitions {
+        topic_id: existing.uuid,
+        start: current,
+        assignment,
+    })
+}
+
+// New partitions get as many replicas as the topic's first partition
+fn replication_factor(topic: &TopicImage) -> usize {
+    topic
+        .partitions
+        .values()
+        .next()
+        .map_or(1, |partition| partition.replica_nodes.len())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<CreatePartitionsRequest> {
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let topics_length = cursor.read_array_length(flexible).await?;
+    let mut topics = Vec::new();
+    for _ in 0..topics_length {
+        let name = cursor.read_string(flexible).await?;
+        let count = cursor.read_i32().await?;
+        let assignments = match cursor.read_nullable_array_length(flexible).await? {
+            Some(assignments_length) => {
+                let mut assignments = Vec::new();
+                for _ in 0..assignments_length {
+                    let mut broker_ids = Vec::new();
+                    for _ in 0..cursor.read_array_length(flexible).await? {
+                        broker_ids.push(cursor.read_i32().await?);
+                    }
+                    cursor.skip_tagged_fields(flexible).await?;
+                    assignments.push(broker_ids);
+                }
+                Some(assignments)
+            }
+            None => None,
+        };
+        cursor.skip_tagged_fields(flexible).await?;
+        topics.push(CreatePartitionsTopic {
+            name,
+            count,
+            assignments,
+        });
+    }
+    // partitions are created before the response, so nothing waits
+    let _timeout_ms = cursor.read_i32().await?;
+    let validate_only = cursor.read_u8().await? != 0;
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(CreatePartitionsRequest {
+        topics,
+        validate_only,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        metadata::cluster::{CorruptBatchPolicy, TopicValueRecord},
+        storage,
+    };
+
+    async fn create_topic(shared: &SharedImage, name: &str) -> anyhow::Result<()> {
+        let topic_id = uuid::Uuid::new_v4();
+        shared
+            .writer()
+            .await?
+            .append(&[
+                ValueRecord::TopicValue(TopicValueRecord {
+                    name: name.to_string(),
+                    uuid: topic_id,
+                }),
+                ValueRecord::PartitionValue(partition_record(topic_id, 0, &[1])),
+            ])
+            .await
+    }
+
+    // The error code of each topic in the response
+    async fn create_partitions(
+        shared: &SharedImage,
+        topics: &[(&str, i32)],
+        validate_only: bool,
+    ) -> anyhow::Result<Vec<i16>> {
+        let version = 2;
+        let flexible = true;
+        let mut data: Vec<u8> = Vec::new();
+        data.write_array_length(flexible, topics.len());
+        for (name, count) in topics {
+            data.write_string(flexible, name); // name
+            data.put_i32(*count); // count
+            data.put_u8(0); // assignments, null
+            data.write_tagged_fields(flexible);
+        }
+        data.put_i32(30_000); // timeout_ms
+        data.put_u8(validate_only as u8); // validate_only
+        data.write_tagged_fields(flexible);
+        let req = Request {
+            message_size: 0,
+            request_api_key: 37,
+            request_api_version: version,
+            correlation_id: 7,
+            data,
+            client_id: String::new(),
+        };
+        let mut res = Response::build_from_request(&req);
+        handle(&req, &mut res, shared).await?;
+
+        let mut cursor = Cursor::new(&res.body);
+        assert_eq!(cursor.read_i32().await?, 0); // throttle_time_ms
+        let mut error_codes = Vec::new();
+        for _ in 0..cursor.read_array_length(flexible).await? {
+            cursor.read_string(flexible).await?; // name
+            error_codes.push(cursor.read_i16().await?);
+            cursor.read_nullable_string(flexible).await?; // error_message
+            cursor.skip_tagged_fields(flexible).await?;
+        }
+        cursor.skip_tagged_fields(flexible).await?;
+        assert_eq!(cursor.position() as usize, res.body.len());
+        Ok(error_codes)
+    }
+
+    #[tokio::test]
+    async fn grows_topics_unless_validating_only() -> anyhow::Result<()> {
+        let shared = SharedImage::new(MetadataImage::default(), 0, CorruptBatchPolicy::default());
+        create_topic(&shared, "grow-me").await?;
+        create_topic(&shared, "grow-not").await?;
+        let partition_count = |name| shared.snapshot().topic(name).unwrap().partitions.len();
+
+        let topics = [("grow-me", 3), ("grow-missing", 2), ("grow-not", 1)];
+        let expected = vec![0, UNKNOWN_TOPIC_OR_PARTITION, INVALID_PARTITIONS];
+        assert_eq!(create_partitions(&shared, &topics, true).await?, expected);
+        assert_eq!(partition_count("grow-me"), 1);
+        assert!(!storage::partition_dir("grow-me", 1).exists());
+
+        assert_eq!(create_partitions(&shared, &topics, false).await?, expected);
+        assert_eq!(partition_count("grow-me"), 3);
+        assert!(storage::partition_dir("grow-me", 1).exists());
+        assert!(storage::partition_dir("grow-me", 2).exists());
+
+        // the count is the new total, so a lower one is an error
+        let shrink = [("grow-me", 2)];
+        assert_eq!(
+            create_partitions(&shared, &shrink, false).await?,
+            vec![INVALID_PARTITIONS]
+        );
+        let duplicate = [("grow-me", 4), ("grow-me", 5)];
+        assert_eq!(
+            create_partitions(&shared, &duplicate, false).await?,
+            vec![INVALID_REQUEST, INVALID_REQUEST]
+        );
+        assert_eq!(partition_count("grow-me"), 3);
+        Ok(())
+    }
+}
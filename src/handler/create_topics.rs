@@ -0,0 +1,540 @@
+use std::{collections::HashMap, io::Cursor};
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    config,
+    custom_trait::wire::{ReadWire, WriteWire},
+    metadata::{
+        cluster::{
+            ConfigValueRecord, PartitionValueRecord, TopicValueRecord, ValueRecord,
+            TOPIC_RESOURCE_TYPE,
+        },
+        image::MetadataImage,
+        tailer::SharedImage,
+        writer::MetadataWriter,
+    },
+    protocol::{request::Request, response::Response},
+    storage,
+};
+
+static UNKNOWN_SERVER_ERROR: i16 = -1;
+static INVALID_TOPIC_EXCEPTION: i16 = 17;
+static TOPIC_ALREADY_EXISTS: i16 = 36;
+static INVALID_PARTITIONS: i16 = 37;
+static INVALID_REPLICATION_FACTOR: i16 = 38;
+static INVALID_REPLICA_ASSIGNMENT: i16 = 39;
+static INVALID_CONFIG: i16 = 40;
+static INVALID_REQUEST: i16 = 42;
+
+// Kafka's defaults of `num.partitions` and `default.replication.factor`,
+// used when a topic asks for -1
+static DEFAULT_NUM_PARTITIONS: i32 = 1;
+static DEFAULT_REPLICATION_FACTOR: i16 = 1;
+
+static MAX_TOPIC_NAME_LENGTH: usize = 249;
+
+// `config_source` of a config set on the topic itself
+static TOPIC_CONFIG_SOURCE: i8 = 1;
+
+#[derive(Debug)]
+pub struct CreateTopicsRequest {
+    pub topics: Vec<CreatableTopic>,
+    pub validate_only: bool,
+}
+
+#[derive(Debug)]
+pub struct CreatableTopic {
+    pub name: String,
+    // -1 with explicit assignments or to use the default
+    pub num_partitions: i32,
+    pub replication_factor: i16,
+    // (partition_index, broker_ids)
+    pub assignments: Vec<(i32, Vec<i32>)>,
+    pub configs: Vec<(String, Option<String>)>,
+}
+
+#[derive(Debug)]
+struct TopicResult {
+    name: String,
+    topic_id: uuid::Uuid,
+    error_code: i16,
+    error_message: Option<String>,
+    // replicas of each partition, empty on errors
+    assignment: Vec<Vec<u32>>,
+    configs: Vec<(String, Option<String>)>,
+}
+
+impl TopicResult {
+    fn error(name: &str, error_code: i16, error_message: String) -> TopicResult {
+        TopicResult {
+            name: name.to_string(),
+            topic_id: uuid::Uuid::nil(),
+            error_code,
+            error_message: Some(error_message),
+            assignment: Vec::new(),
+            configs: Vec::new(),
+        }
+    }
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    shared_metadata: &SharedImage,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 7 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    let mut writer = shared_metadata.writer().await?;
+    let brokers = available_brokers(writer.image());
+    let mut name_counts: HashMap<&str, usize> = HashMap::new();
+    for topic in &parsed.topics {
+        *name_counts.entry(&topic.name).or_default() += 1;
+    }
+
+    let mut results = Vec::new();
+    let mut records = Vec::new();
+    for topic in &parsed.topics {
+        if name_counts[topic.name.as_str()] > 1 {
+            results.push(TopicResult::error(
+                &topic.name,
+                INVALID_REQUEST,
+                "Duplicate topic name.".to_string(),
+            ));
+            continue;
+        }
+        let assignment = match plan_topic(topic, writer.image(), &brokers) {
+            Ok(assignment) => assignment,
+            Err((error_code, message)) => {
+                results.push(TopicResult::error(&topic.name, error_code, message));
+                continue;
+            }
+        };
+        let topic_id = if parsed.validate_only {
+            uuid::Uuid::nil()
+        } else {
+            uuid::Uuid::new_v4()
+        };
+        records.extend(topic_records(topic, topic_id, &assignment));
+        results.push(TopicResult {
+            name: topic.name.clone(),
+            topic_id,
+            error_code: 0,
+            error_message: None,
+            assignment,
+            configs: topic.configs.clone(),
+        });
+    }
+
+    if !parsed.validate_only && !records.is_empty() {
+        if let Err(e) = create(&mut writer, &records, &results).await {
+            eprintln!("failed to create topics: {:#}", e);
+            for result in results.iter_mut().filter(|r| r.error_code == 0) {
+                *result = TopicResult::error(&result.name, UNKNOWN_SERVER_ERROR, e.to_string());
+            }
+        }
+    }
+    drop(writer);
+
+    if version >= 2 {
+        res.body.put_i32(0); // throttle_time_ms
+    }
+    res.body.write_array_length(flexible, results.len());
+    for result in &results {
+        res.body.write_string(flexible, &result.name); // name
+        if version >= 7 {
+            res.body.put_slice(result.topic_id.as_bytes()); // topic_id
+        }
+        res.body.put_i16(result.error_code); // error_code
+        if version >= 1 {
+            let error_message = result.error_message.as_deref();
+            res.body.write_nullable_string(flexible, error_message); // error_message
+        }
+        if version >= 5 {
+            if result.error_code == 0 {
+                let replication_factor = result.assignment[0].len() as i16;
+                res.body.put_i32(result.assignment.len() as i32); // num_partitions
+                res.body.put_i16(replication_factor); // replication_factor
+                res.body.write_array_length(flexible, result.configs.len());
+                for (name, value) in &result.configs {
+                    res.body.write_string(flexible, name); // name
+                    res.body.write_nullable_string(flexible, value.as_deref()); // value
+                    res.body.put_u8(0); // read_only
+                    res.body.put_i8(TOPIC_CONFIG_SOURCE); // config_source
+                    res.body.put_u8(0); // is_sensitive
+                    res.body.write_tagged_fields(flexible);
+                }
+            } else {
+                res.body.put_i32(-1); // num_partitions
+                res.body.put_i16(-1); // replication_factor
+                res.body.put_u8(0); // null configs
+            }
+        }
+        res.body.write_tagged_fields(flexible);
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+/// Brokers replicas can be placed on. Without registrations in the
+/// metadata log this broker is the whole cluster.
+pub fn available_brokers(image: &MetadataImage) -> Vec<i32> {
+    let brokers: Vec<i32> = image
+        .live_brokers()
+        .map(|broker| broker.registration.broker_id)
+        .collect();
+    if brokers.is_empty() {
+        vec![config::get().node_id]
+    } else {
+        brokers
+    }
+}
+
+// Validates the topic and returns the replicas of each partition
+fn plan_topic(
+    topic: &CreatableTopic,
+    image: &MetadataImage,
+    brokers: &[i32],
+) -> Result<Vec<Vec<u32>>, (i16, String)> {
+    validate_topic_name(&topic.name).map_err(|message| (INVALID_TOPIC_EXCEPTION, message))?;
+    if image.topic(&topic.name).is_some() {
+        return Err((
+            TOPIC_ALREADY_EXISTS,
+            format!("Topic '{}' already exists.", topic.name),
+        ));
+    }
+    if let Some((name, _)) = topic.configs.iter().find(|(_, value)| value.is_none()) {
+        return Err((
+            INVALID_CONFIG,
+            format!("Null value not supported for {name}"),
+        ));
+    }
+    for (name, value) in &topic.configs {
+        validate_config(name, value.as_deref().unwrap_or_default())
+            .map_err(|message| (INVALID_CONFIG, message))?;
+    }
+
+    if !topic.assignments.is_empty() {
+        if topic.num_partitions != -1 || topic.replication_factor != -1 {
+            return Err((
+                INVALID_REQUEST,
+                "Both numPartitions or replicationFactor and replicasAssignments were set. \
+                 Both cannot be used at the same time."
+                    .to_string(),
+            ));
+        }
+        return validate_assignments(&topic.assignments, brokers)
+            .map_err(|message| (INVALID_REPLICA_ASSIGNMENT, message));
+    }
+
+    let num_partitions = match topic.num_partitions {
+        -1 => DEFAULT_NUM_PARTITIONS,
+        n if n > 0 => n,
+        n => {
+            return Err((
+                INVALID_PARTITIONS,
+                format!("Number of partitions was set to an invalid non-positive value {n}."),
+            ))
+        }
+    };
+    let replication_factor = match topic.replication_factor {
+        -1 => DEFAULT_REPLICATION_FACTOR,
+        n if n > 0 => n,
+        n => {
+            return Err((
+                INVALID_REPLICATION_FACTOR,
+                format!("Replication factor must be larger than 0, or -1 to use the default value. Got {n}."),
+            ))
+        }
+    };
+    if replication_factor as usize > brokers.len() {
+        return Err((
+            INVALID_REPLICATION_FACTOR,
+            format!(
+                "Unable to replicate the partition {} time(s): The target replication factor of {} cannot be reached because only {} broker(s) are registered.",
+                replication_factor,
+                replication_factor,
+                brokers.len()
+            ),
+        ));
+    }
+    Ok(assign_replicas(
+        0,
+        num_partitions,
+        replication_factor,
+        brokers,
+    ))
+}
+
+/// Spreads the replicas of partitions `start..end` over the brokers round
+/// robin, each partition led by a different broker.
+pub fn assign_replicas(
+    start: i32,
+    end: i32,
+    replication_factor: i16,
+    brokers: &[i32],
+) -> Vec<Vec<u32>> {
+    (start..end)
+        .map(|partition| {
+            (0..replication_factor as usize)
+                .map(|replica| brokers[(partition as usize + replica) % brokers.len()] as u32)
+                .collect()
+        })
+        .collect()
+}
+
+// Explicit assignments must number the partitions from 0 and place every
+// partition on the same number of replicas.
+fn validate_assignments(
+    assignments: &[(i32, Vec<i32>)],
+    brokers: &[i32],
+) -> Result<Vec<Vec<u32>>, String> {
+    let mut sorted: Vec<&(i32, Vec<i32>)> = assignments.iter().collect();
+    sorted.sort_by_key(|(index, _)| *index);
+    let replication_factor = sorted[0].1.len();
+    let mut assignment = Vec::new();
+    for (expected, (index, broker_ids)) in sorted.into_iter().enumerate() {
+        if *index != expected as i32 {
+            return Err("Partitions should be numbered consecutively starting from 0.".to_string());
+        }
+        if broker_ids.len() != replication_factor {
+            return Err(format!(
+                "Partition {index} has {} replicas, every partition needs {replication_factor}.",
+                broker_ids.len()
+            ));
+        }
+        assignment.push(validate_replicas(*index, broker_ids, brokers)?);
+    }
+    Ok(assignment)
+}
+
+/// Checks that a partition's replicas are distinct registered brokers.
+pub fn validate_replicas(
+    index: i32,
+    broker_ids: &[i32],
+    brokers: &[i32],
+) -> Result<Vec<u32>, String> {
+    if broker_ids.is_empty() {
+        return Err(format!("Partition {index} has no replicas."));
+    }
+    let mut replicas = Vec::new();
+    for &broker_id in broker_ids {
+        if !brokers.contains(&broker_id) {
+            return Err(format!("Broker {broker_id} is not registered."));
+        }
+        if replicas.contains(&(broker_id as u32)) {
+            return Err(format!("Partition {index} repeats broker {broker_id}."));
+        }
+        replicas.push(broker_id as u32);
+    }
+    Ok(replicas)
+}
+
+// Same rules as Kafka's `Topic.validate`
+// Checks the configs the broker reads, the others are stored as given
+fn validate_config(name: &str, value: &str) -> Result<(), String> {
+    // Kafka's lower bounds: the size of a record batch header, and 1 ms
+    let (min, type_name) = match name {
+        "segment.bytes" => (14, "INT"),
+        "segment.ms" => (1, "LONG"),
+        _ => return Ok(()),
+    };
+    match value.parse::<i64>() {
+        Ok(parsed) if parsed >= min => Ok(()),
+        Ok(_) => Err(format!(
+            "Invalid value {value} for configuration {name}: Value must be at least {min}"
+        )),
+        Err(_) => Err(format!(
+            "Invalid value {value} for configuration {name}: Not a number of type {type_name}"
+        )),
+    }
+}
+
+fn validate_topic_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Topic name is illegal, it can't be empty".to_string());
+    }
+    if name == "." || name == ".." {
+        return Err("Topic name cannot be \".\" or \"..\"".to_string());
+    }
+    if name.len() > MAX_TOPIC_NAME_LENGTH {
+        return Err(format!(
+            "Topic name is illegal, it can't be longer than {MAX_TOPIC_NAME_LENGTH} characters, topic name: {name}"
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    {
+        return Err(format!(
+            "Topic name \"{name}\" is illegal, it contains a character other than ASCII alphanumerics, '.', '_' and '-'"
+        ));
+    }
+    Ok(())
+}
+
+fn topic_records(
+    topic: &CreatableTopic,
+    topic_id: uuid::Uuid,
+    assignment: &[Vec<u32>],
+) -> Vec<ValueRecord> {
+    let mut records = vec![ValueRecord::TopicValue(TopicValueRecord {
+        name: topic.name.clone(),
+        uuid: topic_id,
+    })];
+    for (index, replicas) in assignment.iter().enumerate() {
+        records.push(ValueRecord::PartitionValue(partition_record(
+            topic_id,
+            index as u32,
+            replicas,
+        )));
+    }
+    for (name, value) in &topic.configs {
+        records.push(ValueRecord::ConfigValue(ConfigValueRecord {
+            resource_type: TOPIC_RESOURCE_TYPE,
+            resource_name: topic.name.clone(),
+            name: name.clone(),
+            value: value.clone(),
+        }));
+    }
+    records
+}
+
+/// A new partition, led by its first replica with every replica in sync.
+pub fn partition_record(
+    topic_id: uuid::Uuid,
+    index: u32,
+    replicas: &[u32],
+) -> PartitionValueRecord {
+    PartitionValueRecord {
+        id: index,
+        topic_uuid: topic_id,
+        leader_id: replicas[0],
+        leader_epoch: 0,
+        replica_nodes: replicas.to_vec(),
+        in_sync_replica_nodes: replicas.to_vec(),
+        removing_replica_nodes: vec![],
+        adding_replica_nodes: vec![],
+        leader_recovery_state: 0,
+        partition_epoch: 0,
+        directories: vec![],
+    }
+}
+
+// Appends the records, then creates the directories of the new partitions
+async fn create(
+    writer: &mut MetadataWriter<'_>,
+    records: &[ValueRecord],
+    results: &[TopicResult],
+) -> anyhow::Result<()> {
+    writer.append(records).await?;
+    for result in results.iter().filter(|r| r.error_code == 0) {
+        create_partition_dirs(&result.name, 0, &result.assignment).await?;
+    }
+    Ok(())
+}
+
+/// Creates the directories of the partitions numbered from `start` that
+/// this broker holds a replica of.
+pub async fn create_partition_dirs(
+    topic_name: &str,
+    start: i32,
+    assignment: &[Vec<u32>],
+) -> std::io::Result<()> {
+    let node_id = config::get().node_id as u32;
+    for (index, replicas) in (start..).zip(assignment) {
+        if replicas.contains(&node_id) {
+            tokio::fs::create_dir_all(storage::partition_dir(topic_name, index)).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<CreateTopicsRequest> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let topics_length = cursor.read_array_length(flexible).await?;
+    let mut topics = Vec::new();
+    for _ in 0..topics_length {
+        let name = cursor.read_string(flexible).await?;
+        let num_partitions = cursor.read_i32().await?;
+        let replication_factor = cursor.read_i16().await?;
+        let mut assignments = Vec::new();
+        for _ in 0..cursor.read_array_length(flexible).await? {
+            let partition_index = cursor.read_i32().await?;
+            let mut broker_ids = Vec::new();
+            for _ in 0..cursor.read_array_length(flexible).await? {
+                broker_ids.push(cursor.read_i32().await?);
+            }
+            cursor.skip_tagged_fields(flexible).await?;
+            assignments.push((partition_index, broker_ids));
+        }
+        let mut configs = Vec::new();
+        for _ in 0..cursor.read_array_length(flexible).await? {
+            let name = cursor.read_string(flexible).await?;
+            let value = cursor.read_nullable_string(flexible).await?;
+            cursor.skip_tagged_fields(flexible).await?;
+            configs.push((name, value));
+        }
+        cursor.skip_tagged_fields(flexible).await?;
+        topics.push(CreatableTopic {
+            name,
+            num_partitions,
+            replication_factor,
+            assignments,
+            configs,
+        });
+    }
+    // topics are created before the response, so nothing waits
+    let _timeout_ms = cursor.read_i32().await?;
+    let validate_only = if version >= 1 {
+        cursor.read_u8().await? != 0
+    } else {
+        false
+    };
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(CreateTopicsRequest {
+        topics,
+        validate_only,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_names_and_assignments() {
+        assert!(validate_topic_name("orders.v1_eu-west").is_ok());
+        assert!(validate_topic_name("..").is_err());
+        assert!(validate_topic_name("a/b").is_err());
+        assert!(validate_topic_name(&"a".repeat(250)).is_err());
+
+        assert_eq!(
+            assign_replicas(0, 3, 2, &[1, 2, 3]),
+            vec![vec![1, 2], vec![2, 3], vec![3, 1]]
+        );
+        assert_eq!(
+            validate_assignments(&[(1, vec![2]), (0, vec![1])], &[1, 2]),
+            Ok(vec![vec![1], vec![2]])
+        );
+        assert!(validate_assignments(&[(0, vec![1]), (2, vec![1])], &[1, 2]).is_err());
+        assert!(validate_assignments(&[(0, vec![1, 1])], &[1, 2]).is_err());
+        assert!(validate_assignments(&[(0, vec![3])], &[1, 2]).is_err());
+
+        assert!(validate_config("segment.bytes", "1048576").is_ok());
+        assert!(validate_config("segment.bytes", "10").is_err());
+        assert!(validate_config("segment.ms", "soon").is_err());
+        assert!(validate_config("cleanup.policy", "compact").is_ok());
+    }
+}
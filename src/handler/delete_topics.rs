@@ -0,0 +1,314 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    custom_trait::{
+        cursor::ReadUUID,
+        wire::{ReadWire, WriteWire},
+    },
+    metadata::{
+        cluster::{RemoveTopicValueRecord, ValueRecord},
+        tailer::SharedImage,
+    },
+    protocol::{request::Request, response::Response},
+    storage,
+};
+
+static UNKNOWN_SERVER_ERROR: i16 = -1;
+static UNKNOWN_TOPIC_OR_PARTITION: i16 = 3;
+static INVALID_REQUEST: i16 = 42;
+static UNKNOWN_TOPIC_ID: i16 = 100;
+
+#[derive(Debug)]
+pub struct DeleteTopicsRequest {
+    // by name before v6, by name or id from v6 on
+    pub topics: Vec<DeleteTopicState>,
+}
+
+#[derive(Debug)]
+pub struct DeleteTopicState {
+    pub name: Option<String>,
+    // nil when the topic is named
+    pub topic_id: uuid::Uuid,
+}
+
+#[derive(Debug)]
+struct TopicResult {
+    name: Option<String>,
+    topic_id: uuid::Uuid,
+    error_code: i16,
+    error_message: Option<String>,
+    partitions: Vec<i32>,
+}
+
+impl TopicResult {
+    fn error(topic: &DeleteTopicState, error_code: i16, error_message: &str) -> TopicResult {
+        TopicResult {
+            name: topic.name.clone(),
+            topic_id: topic.topic_id,
+            error_code,
+            error_message: Some(error_message.to_string()),
+            partitions: Vec::new(),
+        }
+    }
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    shared_metadata: &SharedImage,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 6 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    let mut writer = shared_metadata.writer().await?;
+    let mut results: Vec<TopicResult> = Vec::new();
+    let mut records = Vec::new();
+    for topic in &parsed.topics {
+        let found = match &topic.name {
+            Some(_) if !topic.topic_id.is_nil() => {
+                results.push(TopicResult::error(
+                    topic,
+                    INVALID_REQUEST,
+                    "Topic name and topic id cannot both be set.",
+                ));
+                continue;
+            }
+            Some(name) => writer.image().topic(name).ok_or((
+                UNKNOWN_TOPIC_OR_PARTITION,
+                "This server does not host this topic-partition.",
+            )),
+            None => writer
+                .image()
+                .topic_by_id(&topic.topic_id)
+                .ok_or((UNKNOWN_TOPIC_ID, "This server does not host this topic ID.")),
+        };
+        let image = match found {
+            Ok(image) => image,
+            Err((error_code, message)) => {
+                results.push(TopicResult::error(topic, error_code, message));
+                continue;
+            }
+        };
+        // a topic named twice is deleted once
+        if !results
+            .iter()
+            .any(|r| r.error_code == 0 && r.topic_id == image.uuid)
+        {
+            records.push(ValueRecord::RemoveTopicValue(RemoveTopicValueRecord {
+                topic_uuid: image.uuid,
+            }));
+        }
+        results.push(TopicResult {
+            name: Some(image.name.clone()),
+            topic_id: image.uuid,
+            error_code: 0,
+            error_message: None,
+            partitions: image.partitions.keys().map(|&index| index as i32).collect(),
+        });
+    }
+
+    if !records.is_empty() {
+        if let Err(e) = writer.append(&records).await {
+            eprintln!("failed to delete topics: {:#}", e);
+            for result in results.iter_mut().filter(|r| r.error_code == 0) {
+                result.error_code = UNKNOWN_SERVER_ERROR;
+                result.error_message = Some(e.to_string());
+                result.partitions.clear();
+            }
+        }
+    }
+    drop(writer);
+
+    // the topic is gone from the metadata, its data goes in the background
+    for result in &results {
+        let Some(name) = &result.name else {
+            continue;
+        };
+        for index in &result.partitions {
+            if let Err(e) = storage::delete_partition(name, *index).await {
+                eprintln!("failed to delete partition {}-{}: {}", name, index, e);
+            }
+        }
+    }
+
+    if version >= 1 {
+        res.body.put_i32(0); // throttle_time_ms
+    }
+    res.body.write_array_length(flexible, results.len());
+    for result in &results {
+        let name = result.name.as_deref();
+        if version >= 6 {
+            res.body.write_nullable_string(flexible, name); // name
+            res.body.put_slice(result.topic_id.as_bytes()); // topic_id
+        } else {
+            res.body.write_string(flexible, name.unwrap_or_default()); // name
+        }
+        res.body.put_i16(result.error_code); // error_code
+        if version >= 5 {
+            let error_message = result.error_message.as_deref();
+            res.body.write_nullable_string(flexible, error_message); // error_message
+        }
+        res.body.write_tagged_fields(flexible);
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<DeleteTopicsRequest> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let topics_length = cursor.read_array_length(flexible).await?;
+    let mut topics = Vec::new();
+    for _ in 0..topics_length {
+        if version >= 6 {
+            let name = cursor.read_nullable_string(flexible).await?;
+            let topic_id = cursor.read_uuid().await?;
+            cursor.skip_tagged_fields(flexible).await?;
+            topics.push(DeleteTopicState { name, topic_id });
+        } else {
+            let name = cursor.read_string(flexible).await?;
+            topics.push(DeleteTopicState {
+                name: Some(name),
+                topic_id: uuid::Uuid::nil(),
+            });
+        }
+    }
+    // topics are deleted before the response, so nothing waits
+    let _timeout_ms = cursor.read_i32().await?;
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(DeleteTopicsRequest { topics })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        handler::create_topics::partition_record,
+        metadata::{
+            cluster::{CorruptBatchPolicy, TopicValueRecord},
+            image::MetadataImage,
+        },
+    };
+
+    fn delete_request(version: u16, topics: &[(Option<&str>, uuid::Uuid)]) -> Request {
+        let flexible = version >= 4;
+        let mut data: Vec<u8> = Vec::new();
+        data.write_array_length(flexible, topics.len());
+        for (name, topic_id) in topics {
+            if version >= 6 {
+                data.write_nullable_string(flexible, *name); // name
+                data.put_slice(topic_id.as_bytes()); // topic_id
+                data.write_tagged_fields(flexible);
+            } else {
+                data.write_string(flexible, name.unwrap_or_default()); // name
+            }
+        }
+        data.put_i32(30_000); // timeout_ms
+        data.write_tagged_fields(flexible);
+        Request {
+            message_size: 0,
+            request_api_key: 20,
+            request_api_version: version,
+            correlation_id: 7,
+            data,
+            client_id: String::new(),
+        }
+    }
+
+    // The error code of each topic in the response
+    async fn delete(
+        version: u16,
+        shared: &SharedImage,
+        topics: &[(Option<&str>, uuid::Uuid)],
+    ) -> anyhow::Result<Vec<i16>> {
+        let req = delete_request(version, topics);
+        let mut res = Response::build_from_request(&req);
+        handle(&req, &mut res, shared).await?;
+
+        let flexible = version >= 4;
+        let mut cursor = Cursor::new(&res.body);
+        assert_eq!(cursor.read_i32().await?, 0); // throttle_time_ms
+        let mut error_codes = Vec::new();
+        for _ in 0..cursor.read_array_length(flexible).await? {
+            if version >= 6 {
+                cursor.read_nullable_string(flexible).await?; // name
+                cursor.read_uuid().await?; // topic_id
+            } else {
+                cursor.read_string(flexible).await?; // name
+            }
+            error_codes.push(cursor.read_i16().await?);
+            if version >= 5 {
+                cursor.read_nullable_string(flexible).await?; // error_message
+            }
+            cursor.skip_tagged_fields(flexible).await?;
+        }
+        cursor.skip_tagged_fields(flexible).await?;
+        assert_eq!(cursor.position() as usize, res.body.len());
+        Ok(error_codes)
+    }
+
+    #[tokio::test]
+    async fn deletes_topics_and_renames_their_partitions() -> anyhow::Result<()> {
+        let shared = SharedImage::new(MetadataImage::default(), 0, CorruptBatchPolicy::default());
+        let name = "delete-me";
+        let topic_id = uuid::Uuid::new_v4();
+        shared
+            .writer()
+            .await?
+            .append(&[
+                ValueRecord::TopicValue(TopicValueRecord {
+                    name: name.to_string(),
+                    uuid: topic_id,
+                }),
+                ValueRecord::PartitionValue(partition_record(topic_id, 0, &[1])),
+            ])
+            .await?;
+        let dir = storage::partition_dir(name, 0);
+        std::fs::create_dir_all(&dir)?;
+
+        let nil = uuid::Uuid::nil();
+        let missing = [(Some("delete-missing"), nil)];
+        assert_eq!(
+            delete(5, &shared, &missing).await?,
+            vec![UNKNOWN_TOPIC_OR_PARTITION]
+        );
+        let topics = [
+            (None, uuid::Uuid::new_v4()),
+            (Some(name), topic_id),
+            (Some(name), nil),
+        ];
+        assert_eq!(
+            delete(6, &shared, &topics).await?,
+            vec![UNKNOWN_TOPIC_ID, INVALID_REQUEST, 0]
+        );
+        assert!(shared.snapshot().topic(name).is_none());
+
+        // the removal of the renamed directory is spawned, and has not run
+        // on this single threaded runtime yet
+        assert!(!dir.exists());
+        let renamed: Vec<String> = std::fs::read_dir(dir.parent().unwrap())?
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|file_name| file_name.starts_with("delete-me-0."))
+            .collect();
+        assert_eq!(renamed.len(), 1);
+        assert!(renamed[0].ends_with("-delete"));
+
+        // deleting it again finds nothing
+        assert_eq!(
+            delete(6, &shared, &[(None, topic_id)]).await?,
+            vec![UNKNOWN_TOPIC_ID]
+        );
+        Ok(())
+    }
+}
@@ -6,46 +6,34 @@ use tokio::io::AsyncReadExt;
 
 use crate::{
     custom_trait::cursor::{AsyncReadVarint, ReadVarint, WriteVarint},
-    metadata::cluster::{self, Cluster, ClusterSummary},
+    metadata::image::MetadataImage,
     protocol::{request::Request, response::Response},
 };
 
 #[derive(Debug)]
 struct DescribeRequest {
     length: u8,
-    response_partition_limit: u32,
-    cursor: u8,
-    tag_buffer: u8,
     topics: Vec<DescribeTopic>,
 }
 
 #[derive(Debug)]
 struct DescribeTopic {
-    name_length: u64,
     topic_name: String,
-    tag_buffer: u8,
 }
-struct DescribeResponse {}
 
 pub async fn handle<'a>(
     req: &Request,
     res: &mut Response<'a>,
-    cluster: &Cluster,
+    cluster: &MetadataImage,
 ) -> anyhow::Result<()> {
     if req.request_api_version == 0 {
         res.body.put_u32(0x00); // Throttle time
-        let parsed_request = parse(&req).await?;
+        let parsed_request = parse(req).await?;
         dbg!(&parsed_request);
         res.body.put_u8(parsed_request.length + 1);
 
-        let list_available_topic = cluster.topics();
-        let list_available_partitions = cluster.partitions();
-
         for topic in parsed_request.topics {
-            match list_available_topic
-                .iter()
-                .find(|t| t.name == topic.topic_name)
-            {
+            match cluster.topic(&topic.topic_name) {
                 None => {
                     res.body.put_u16(3); // error_code
                     res.body.write_uvarint((topic.topic_name.len() + 1) as u64); // topic_name length + 1
@@ -61,13 +49,8 @@ pub async fn handle<'a>(
                     res.body.put_slice(t.uuid.as_ref()); // topic_id
                     res.body.put_u8(0); // is_internal
 
-                    let partitions = list_available_partitions
-                        .iter()
-                        .filter(|part| part.topic_uuid == t.uuid)
-                        .cloned()
-                        .collect::<Vec<&cluster::PartitionValueRecord>>();
-                    res.body.put_u8((partitions.len() + 1) as u8);
-                    for (index, partition) in partitions.iter().enumerate() {
+                    res.body.put_u8((t.partitions.len() + 1) as u8);
+                    for (index, partition) in t.partitions.values().enumerate() {
                         res.body.put_u16(0); // error_code
                         res.body.put_u32(index as u32); // partition_index
                         res.body.put_u32(partition.leader_id); // leader
@@ -110,23 +93,18 @@ async fn parse(req: &Request) -> anyhow::Result<DescribeRequest> {
         let mut buf = vec![0u8; name_length as usize];
         cursor.read_exact(&mut buf).await?;
         let topic_name = String::from_utf8(buf)?;
-        let tag_buffer = cursor.read_u8().await?;
-        topics.push(DescribeTopic {
-            name_length,
-            tag_buffer,
-            topic_name,
-        });
+        let _tag_buffer = cursor.read_u8().await?;
+        topics.push(DescribeTopic { topic_name });
     }
 
-    let response_partition_limit = cursor.read_u32().await?;
-    let curs = cursor.read_u8().await?;
-    let tag_buffer = cursor.read_u8().await?;
+    // every partition fits in one response, so there is no limit or
+    // cursor to page with
+    let _response_partition_limit = cursor.read_u32().await?;
+    let _cursor = cursor.read_u8().await?;
+    let _tag_buffer = cursor.read_u8().await?;
 
-    return Ok(DescribeRequest {
-        cursor: curs,
+    Ok(DescribeRequest {
         length: array_length as u8,
-        response_partition_limit,
-        tag_buffer,
         topics,
-    });
+    })
 }
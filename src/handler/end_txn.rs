@@ -0,0 +1,70 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    coordinator::transaction::TransactionCoordinator,
+    custom_trait::wire::{ReadWire, WriteWire},
+    protocol::{request::Request, response::Response},
+    storage::batch::ControlMarker,
+};
+
+#[derive(Debug)]
+pub struct EndTxnRequest {
+    pub transactional_id: String,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub committed: bool,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    transactions: &TransactionCoordinator,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 3 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    let marker = if parsed.committed {
+        ControlMarker::Commit
+    } else {
+        ControlMarker::Abort
+    };
+    let error_code = transactions
+        .end_transaction(
+            &parsed.transactional_id,
+            parsed.producer_id,
+            parsed.producer_epoch,
+            marker,
+        )
+        .await;
+
+    res.body.put_i32(0); // throttle_time_ms
+    res.body.put_i16(error_code); // error_code
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<EndTxnRequest> {
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let transactional_id = cursor.read_string(flexible).await?;
+    let producer_id = cursor.read_i64().await?;
+    let producer_epoch = cursor.read_i16().await?;
+    let committed = cursor.read_u8().await? != 0;
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(EndTxnRequest {
+        transactional_id,
+        producer_id,
+        producer_epoch,
+        committed,
+    })
+}
@@ -1,18 +1,32 @@
-use core::error;
-use std::{any, io::Cursor};
+use std::{io::Cursor, time::Duration};
 
 use anyhow::Ok;
-use bytes::{Buf, BufMut};
-use tokio::io::AsyncReadExt;
+use bytes::BufMut;
+use tokio::{io::AsyncReadExt, time::Instant};
 
 use crate::{
-    custom_trait::cursor::{AsyncReadVarint, ReadUUID, WriteVarint},
-    metadata::cluster::{Cluster, ClusterSummary},
+    coordinator::fetch_session::{
+        FetchContext, FetchSessionCache, PartitionResponse, SessionKey, SessionPartition,
+        INVALID_SESSION_ID,
+    },
+    custom_trait::{
+        cursor::ReadUUID,
+        wire::{ReadWire, WriteWire},
+    },
+    metadata::image::{MetadataImage, TopicImage},
     protocol::{request::Request, response::Response},
+    storage::{self, batch, compression::CompressionType, purgatory::FetchWatch, PartitionRead},
 };
 
+static OFFSET_OUT_OF_RANGE: i16 = 1;
+static UNKNOWN_TOPIC_OR_PARTITION: i16 = 3;
+static UNKNOWN_TOPIC_ID: i16 = 100;
+
+static READ_COMMITTED: i8 = 1;
+
 #[derive(Debug)]
 pub struct FetchRequest {
+    pub replica_id: i32,
     pub max_wait_ms: i32,
     pub min_bytes: i32,
     pub max_bytes: i32,
@@ -21,11 +35,12 @@ pub struct FetchRequest {
     pub session_epoch: i32,
     pub topics: Vec<Topic>,
     pub forgotten_topics_data: Vec<ForgottenTopicData>,
-    pub rack_id: String,
 }
 
 #[derive(Debug)]
 pub struct Topic {
+    // topics are sent by name before v13 and by id from v13
+    pub topic: String,
     pub topic_id: uuid::Uuid,
     pub partitions: Vec<Partition>,
 }
@@ -42,88 +57,372 @@ pub struct Partition {
 
 #[derive(Debug)]
 pub struct ForgottenTopicData {
+    pub topic: String,
     pub topic_id: uuid::Uuid,
     pub partitions: Vec<i32>, // List of partitions
 }
 
+// A partition's part of the response, `read` is None when it has none
+struct PartitionFetch {
+    partition: i32,
+    error_code: i16,
+    read: Option<PartitionRead>,
+}
+
 pub async fn handle<'a>(
     req: &Request,
     res: &mut Response<'a>,
-    cluster: &Cluster,
+    cluster: &MetadataImage,
+    sessions: &FetchSessionCache,
 ) -> anyhow::Result<()> {
-    if req.request_api_version == 16 {
-        let available_topics = cluster.topics();
-        let parsed = parse(req).await?;
-        res.body.put_u32(0x00); // throttle time
+    let version = req.request_api_version;
+    if !(4..=16).contains(&version) {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let mut parsed = parse(req).await?;
+
+    // Incremental fetches (KIP-227) only send the partitions that changed
+    // since the previous fetch of their session, and read all of them.
+    let forgotten: Vec<SessionKey> = parsed
+        .forgotten_topics_data
+        .iter()
+        .flat_map(|topic| {
+            topic
+                .partitions
+                .iter()
+                .map(|partition| (topic.topic_id, topic.topic.clone(), *partition))
+        })
+        .collect();
+    let context = sessions.new_context(
+        parsed.session_id,
+        parsed.session_epoch,
+        session_partitions(&parsed.topics),
+        &forgotten,
+        parsed.replica_id >= 0,
+        version >= 13,
+    );
+    let (session_id, incremental) = match context {
+        FetchContext::Error(error_code) => {
+            res.body.put_i32(0); // throttle_time_ms
+            res.body.put_i16(error_code); // error_code
+            res.body.put_i32(INVALID_SESSION_ID); // session_id
+            res.body.write_array_length(flexible, 0); // responses
+            res.body.write_tagged_fields(flexible);
+            return Ok(());
+        }
+        FetchContext::Sessionless => (INVALID_SESSION_ID, false),
+        FetchContext::Full { session_id } => (session_id, false),
+        FetchContext::Incremental {
+            session_id,
+            partitions,
+        } => {
+            parsed.topics = session_topics(partitions);
+            (session_id, true)
+        }
+    };
+
+    // A fetch without enough records for min_bytes is parked until an
+    // append to one of its partitions or until max_wait_ms runs out.
+    let deadline = Instant::now() + Duration::from_millis(parsed.max_wait_ms.max(0) as u64);
+    let watched: Vec<(&str, i32)> = parsed
+        .topics
+        .iter()
+        .filter_map(|topic| Some((find_topic(cluster, topic, version)?, topic)))
+        .flat_map(|(found, topic)| {
+            topic
+                .partitions
+                .iter()
+                .map(|partition| (found.name.as_str(), partition.partition))
+        })
+        .collect();
+    let fetched = loop {
+        let watch = FetchWatch::new(watched.iter().copied());
+        let fetched = read_topics(&parsed, cluster, version).await?;
+        let partitions = fetched.iter().flatten();
+        let read_bytes: usize = partitions
+            .clone()
+            .filter_map(|p| p.read.as_ref()?.records.as_ref())
+            .map(Vec::len)
+            .sum();
+        // errors are returned right away
+        let failed = partitions.clone().any(|p| p.error_code != 0);
+        if failed || read_bytes >= parsed.min_bytes.max(0) as usize || Instant::now() >= deadline {
+            break fetched;
+        }
+        // read again after an append, or one last time after max_wait_ms
+        let _ = tokio::time::timeout_at(deadline, watch.appended()).await;
+    };
+
+    // the session remembers what the client was told, an incremental
+    // response leaves out the partitions without news
+    let mut fetched = fetched;
+    if session_id != INVALID_SESSION_ID {
+        let responses: Vec<(SessionKey, PartitionResponse, bool)> = parsed
+            .topics
+            .iter()
+            .zip(&fetched)
+            .flat_map(|(topic, partitions)| {
+                partitions.iter().map(|p| {
+                    let read = p.read.as_ref();
+                    let response = PartitionResponse {
+                        high_watermark: read.map_or(-1, |r| r.high_watermark),
+                        last_stable_offset: read.map_or(-1, |r| r.last_stable_offset),
+                        log_start_offset: read.map_or(-1, |r| r.log_start_offset),
+                    };
+                    let has_records = read
+                        .and_then(|r| r.records.as_ref())
+                        .is_some_and(|records| !records.is_empty());
+                    let key = (topic.topic_id, topic.topic.clone(), p.partition);
+                    (key, response, has_records || p.error_code != 0)
+                })
+            })
+            .collect();
+        let mut included = sessions
+            .update_responses(session_id, &responses)
+            .into_iter();
+        if incremental {
+            for partitions in &mut fetched {
+                partitions.retain(|_| included.next().unwrap_or(true));
+            }
+        }
+    }
+    let topics: Vec<(&Topic, Vec<PartitionFetch>)> = parsed
+        .topics
+        .iter()
+        .zip(fetched)
+        .filter(|(_, partitions)| !incremental || !partitions.is_empty())
+        .collect();
+
+    res.body.put_u32(0x00); // throttle time
+    if version >= 7 {
         res.body.put_u16(0x00); // error code
-        res.body.put_u32(0x00); // session_id
+        res.body.put_i32(session_id); // session_id
+    }
 
-        res.body.put_u8((parsed.topics.len() + 1) as u8);
-        for topic in parsed.topics {
+    res.body.write_array_length(flexible, topics.len());
+    for (topic, partitions) in topics {
+        if version >= 13 {
             res.body.put_slice(topic.topic_id.as_ref());
-            res.body.put_u8((1 + 1) as u8);
+        } else {
+            res.body.write_string(flexible, &topic.topic);
+        }
+        res.body.write_array_length(flexible, partitions.len());
 
-            res.body.put_i32(0); // partition index
-            let topic = available_topics.iter().find(|x| x.uuid.eq(&topic.topic_id));
-            let error_code = match topic {
-                Some(_) => 0,
-                None => 100,
-            };
+        for PartitionFetch {
+            partition,
+            error_code,
+            read,
+        } in partitions
+        {
+            let high_watermark = read.as_ref().map_or(-1, |r| r.high_watermark);
+            let last_stable_offset = read.as_ref().map_or(-1, |r| r.last_stable_offset);
+            let log_start_offset = read.as_ref().map_or(-1, |r| r.log_start_offset);
+
+            res.body.put_i32(partition); // partition index
             res.body.put_i16(error_code); // error code
-            res.body.put_i64(0); // high watermark
-            res.body.put_i64(0); // last_stable_offset
-            res.body.put_i64(0); // log_start_offset
-
-            // aborted transactions
-            res.body.put_u8((0 + 1) as u8); // aborted transaction length
-
-            res.body.put_i32(0); // prefered read replica
-
-            match topic {
-                Some(topic) => {
-                    let bytes_data = cluster
-                        .get_partition_record_from_file(&topic.name, 0)
-                        .await?;
-                    res.body.write_uvarint((bytes_data.len() + 1) as u64);
-                    res.body.put_slice(&bytes_data);
-                }
-                _ => res.body.write_uvarint((0 + 1) as u64),
-            };
-            res.body.put_u8(0); // partitions tag buffer
+            res.body.put_i64(high_watermark); // high watermark
+            res.body.put_i64(last_stable_offset); // last_stable_offset
+            if version >= 5 {
+                res.body.put_i64(log_start_offset); // log_start_offset
+            }
+
+            // aborted transactions among the records, for read_committed
+            // consumers to skip
+            let aborted = read.as_ref().map_or(&[][..], |r| &r.aborted_transactions);
+            res.body.write_array_length(flexible, aborted.len());
+            for txn in aborted {
+                res.body.put_i64(txn.producer_id); // producer_id
+                res.body.put_i64(txn.first_offset); // first_offset
+                res.body.write_tagged_fields(flexible);
+            }
+
+            if version >= 11 {
+                res.body.put_i32(-1); // prefered read replica
+            }
 
-            res.body.put_u8(0); // topic tag buffer
+            let records = read.and_then(|r| r.records).unwrap_or_default();
+            res.body.write_nullable_bytes(flexible, Some(&records));
+            res.body.write_tagged_fields(flexible); // partitions tag buffer
         }
 
-        // res.body.put_u8(0x00); // num response
-        res.body.put_u8(0x00); // tag buffer
+        res.body.write_tagged_fields(flexible); // topic tag buffer
     }
+
+    res.body.write_tagged_fields(flexible); // tag buffer
     Ok(())
 }
 
+fn session_partitions(topics: &[Topic]) -> Vec<SessionPartition> {
+    topics
+        .iter()
+        .flat_map(|topic| {
+            topic.partitions.iter().map(|partition| SessionPartition {
+                topic_id: topic.topic_id,
+                topic: topic.topic.clone(),
+                partition: partition.partition,
+                current_leader_epoch: partition.current_leader_epoch,
+                fetch_offset: partition.fetch_offset,
+                last_fetched_epoch: partition.last_fetched_epoch,
+                log_start_offset: partition.log_start_offset,
+                partition_max_bytes: partition.partition_max_bytes,
+                last_response: None,
+            })
+        })
+        .collect()
+}
+
+// Groups the partitions of a session by topic, keeping their order
+fn session_topics(partitions: Vec<SessionPartition>) -> Vec<Topic> {
+    let mut topics: Vec<Topic> = Vec::new();
+    for cached in partitions {
+        let partition = Partition {
+            partition: cached.partition,
+            current_leader_epoch: cached.current_leader_epoch,
+            fetch_offset: cached.fetch_offset,
+            last_fetched_epoch: cached.last_fetched_epoch,
+            log_start_offset: cached.log_start_offset,
+            partition_max_bytes: cached.partition_max_bytes,
+        };
+        match topics.last_mut() {
+            Some(last) if last.topic_id == cached.topic_id && last.topic == cached.topic => {
+                last.partitions.push(partition)
+            }
+            _ => topics.push(Topic {
+                topic: cached.topic,
+                topic_id: cached.topic_id,
+                partitions: vec![partition],
+            }),
+        }
+    }
+    topics
+}
+
+// Topics are requested by name before v13 and by id from v13
+fn find_topic<'c>(
+    cluster: &'c MetadataImage,
+    topic: &Topic,
+    version: u16,
+) -> Option<&'c TopicImage> {
+    if version >= 13 {
+        cluster.topic_by_id(&topic.topic_id)
+    } else {
+        cluster.topic(&topic.topic)
+    }
+}
+
+// Reads the requested partitions, in request order
+async fn read_topics(
+    parsed: &FetchRequest,
+    cluster: &MetadataImage,
+    version: u16,
+) -> anyhow::Result<Vec<Vec<PartitionFetch>>> {
+    let read_committed = parsed.isolation_level == READ_COMMITTED;
+    // request level max_bytes, shared by all partitions in order
+    let mut remaining_bytes = parsed.max_bytes.max(0) as usize;
+
+    let mut topics = Vec::new();
+    for topic in &parsed.topics {
+        let found_topic = find_topic(cluster, topic, version);
+        let mut partitions = Vec::new();
+        for partition in &topic.partitions {
+            let found_partition = found_topic.and_then(|t| t.partition(partition.partition));
+            let read = match (found_topic, found_partition) {
+                (Some(t), Some(_)) => {
+                    let max_bytes =
+                        (partition.partition_max_bytes.max(0) as usize).min(remaining_bytes);
+                    // the first batch is always returned so that consumers make progress
+                    let min_one_batch = remaining_bytes == parsed.max_bytes.max(0) as usize;
+                    let mut read = storage::read_partition_records(
+                        &t.name,
+                        partition.partition,
+                        partition.fetch_offset,
+                        max_bytes,
+                        min_one_batch,
+                        read_committed,
+                    )
+                    .await?;
+                    // clients before v10 cannot read zstd, so those batches
+                    // are down-converted to uncompressed ones
+                    if let Some(records) = read.records.as_mut().filter(|_| version < 10) {
+                        *records = batch::recompress_batches(
+                            records,
+                            |codec| codec == CompressionType::Zstd,
+                            CompressionType::None,
+                        )?;
+                    }
+                    let read_size = read.records.as_ref().map_or(0, Vec::len);
+                    remaining_bytes = remaining_bytes.saturating_sub(read_size);
+                    Some(read)
+                }
+                _ => None,
+            };
+
+            let error_code = match (found_topic, &read) {
+                (None, _) if version >= 13 => UNKNOWN_TOPIC_ID,
+                (None, _) | (Some(_), None) => UNKNOWN_TOPIC_OR_PARTITION,
+                (Some(_), Some(read)) if read.records.is_none() => OFFSET_OUT_OF_RANGE,
+                _ => 0,
+            };
+            partitions.push(PartitionFetch {
+                partition: partition.partition,
+                error_code,
+                read,
+            });
+        }
+        topics.push(partitions);
+    }
+    Ok(topics)
+}
+
 async fn parse(req: &Request) -> anyhow::Result<FetchRequest> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
     let mut cursor = Cursor::new(&req.data);
 
-    let max_wait_ms = cursor.get_i32();
-    let min_bytes = cursor.get_i32();
-    let max_bytes = cursor.get_i32();
-    let isolation_level = cursor.get_i8();
-    let session_id = cursor.get_i32();
-    let session_epoch = cursor.get_i32();
+    // from v15 the replica id moved into the replica_state tagged field
+    let replica_id = if version <= 14 {
+        cursor.read_i32().await?
+    } else {
+        -1
+    };
+    let max_wait_ms = cursor.read_i32().await?;
+    let min_bytes = cursor.read_i32().await?;
+    let max_bytes = cursor.read_i32().await?;
+    let isolation_level = cursor.read_i8().await?;
+    let (session_id, session_epoch) = if version >= 7 {
+        (cursor.read_i32().await?, cursor.read_i32().await?)
+    } else {
+        (0, -1)
+    };
 
-    let topic_length = cursor.async_read_uvarint().await? - 1;
+    let topic_length = cursor.read_array_length(flexible).await?;
     let mut topics = Vec::new();
     for _ in 0..topic_length {
-        let topic_id = cursor.read_uuid().await?;
-        let partitions_length = cursor.async_read_uvarint().await? - 1;
+        let (topic, topic_id) = read_topic(&mut cursor, version, flexible).await?;
+        let partitions_length = cursor.read_array_length(flexible).await?;
         let mut partitions = Vec::new();
 
         for _ in 0..partitions_length {
-            let partition = cursor.get_i32(); // Partition ID (INT32)
-            let current_leader_epoch = cursor.get_i32(); // Current leader epoch (INT32)
-            let fetch_offset = cursor.get_i64(); // Fetch offset (INT64)
-            let last_fetched_epoch = cursor.get_i32(); // Last fetched epoch (INT32)
-            let log_start_offset = cursor.get_i64(); // Log start offset (INT64)
-            let partition_max_bytes = cursor.get_i32(); // Partition max bytes (INT32)
+            let partition = cursor.read_i32().await?; // Partition ID (INT32)
+            let current_leader_epoch = if version >= 9 {
+                cursor.read_i32().await? // Current leader epoch (INT32)
+            } else {
+                -1
+            };
+            let fetch_offset = cursor.read_i64().await?; // Fetch offset (INT64)
+            let last_fetched_epoch = if version >= 12 {
+                cursor.read_i32().await? // Last fetched epoch (INT32)
+            } else {
+                -1
+            };
+            let log_start_offset = if version >= 5 {
+                cursor.read_i64().await? // Log start offset (INT64)
+            } else {
+                -1
+            };
+            let partition_max_bytes = cursor.read_i32().await?; // Partition max bytes (INT32)
             partitions.push(Partition {
                 partition,
                 current_leader_epoch,
@@ -133,44 +432,48 @@ async fn parse(req: &Request) -> anyhow::Result<FetchRequest> {
                 partition_max_bytes,
             });
 
-            cursor.advance(1); // tag buffer
+            cursor.skip_tagged_fields(flexible).await?;
         }
         topics.push(Topic {
+            topic,
             topic_id,
             partitions,
         });
 
-        cursor.advance(1); // tag buffer
+        cursor.skip_tagged_fields(flexible).await?;
     }
 
-    let forgotten_topic_length = cursor.async_read_uvarint().await? - 1;
     let mut forgotten_topics = Vec::new();
-    for _ in 0..forgotten_topic_length {
-        let topic_id = cursor.read_uuid().await?;
-        let forgotten_partitions_length = cursor.async_read_uvarint().await?;
-        let mut forgotten_partitions: Vec<i32> = Vec::new();
+    if version >= 7 {
+        let forgotten_topic_length = cursor.read_array_length(flexible).await?;
+        for _ in 0..forgotten_topic_length {
+            let (topic, topic_id) = read_topic(&mut cursor, version, flexible).await?;
+            let forgotten_partitions_length = cursor.read_array_length(flexible).await?;
+            let mut forgotten_partitions: Vec<i32> = Vec::new();
 
-        for _ in 0..forgotten_partitions_length {
-            let partition = cursor.get_i32(); // Partition ID (INT32)
-            forgotten_partitions.push(partition);
-        }
+            for _ in 0..forgotten_partitions_length {
+                let partition = cursor.read_i32().await?; // Partition ID (INT32)
+                forgotten_partitions.push(partition);
+            }
 
-        forgotten_topics.push(ForgottenTopicData {
-            topic_id,
-            partitions: forgotten_partitions,
-        });
+            forgotten_topics.push(ForgottenTopicData {
+                topic,
+                topic_id,
+                partitions: forgotten_partitions,
+            });
 
-        cursor.advance(1); // tag buffer
+            cursor.skip_tagged_fields(flexible).await?;
+        }
     }
 
-    // Read rack_id as a compact string
-    let rack_id_length = cursor.async_read_uvarint().await? - 1; // Read length of rack_id
-    let mut rack_id_bytes = vec![0u8; rack_id_length as usize];
-    cursor.read_exact(&mut rack_id_bytes).await?;
-    let rack_id = String::from_utf8(rack_id_bytes)?;
-    cursor.advance(1); // tag buffer
+    if version >= 11 {
+        // there are no other replicas to pick by rack
+        let _rack_id = cursor.read_string(flexible).await?;
+    }
+    cursor.skip_tagged_fields(flexible).await?;
 
     Ok(FetchRequest {
+        replica_id,
         max_wait_ms,
         min_bytes,
         max_bytes,
@@ -179,6 +482,325 @@ async fn parse(req: &Request) -> anyhow::Result<FetchRequest> {
         session_epoch,
         topics,
         forgotten_topics_data: forgotten_topics,
-        rack_id,
     })
 }
+
+async fn read_topic(
+    cursor: &mut Cursor<&Vec<u8>>,
+    version: u16,
+    flexible: bool,
+) -> anyhow::Result<(String, uuid::Uuid)> {
+    if version >= 13 {
+        Ok((String::new(), cursor.read_uuid().await?))
+    } else {
+        Ok((cursor.read_string(flexible).await?, uuid::Uuid::nil()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        handler::create_topics::partition_record,
+        metadata::{
+            cluster::{CorruptBatchPolicy, TopicValueRecord, ValueRecord},
+            tailer::SharedImage,
+        },
+        storage::log::LogConfig,
+    };
+
+    // An image with a topic of one partition
+    async fn image_with_topic(name: &str) -> anyhow::Result<(Arc<MetadataImage>, uuid::Uuid)> {
+        let shared = SharedImage::new(MetadataImage::default(), 0, CorruptBatchPolicy::default());
+        let topic_id = uuid::Uuid::new_v4();
+        shared
+            .writer()
+            .await?
+            .append(&[
+                ValueRecord::TopicValue(TopicValueRecord {
+                    name: name.to_string(),
+                    uuid: topic_id,
+                }),
+                ValueRecord::PartitionValue(partition_record(topic_id, 0, &[1])),
+            ])
+            .await?;
+        Ok((shared.snapshot(), topic_id))
+    }
+
+    async fn append(topic: &str, values: &[&[u8]]) -> anyhow::Result<()> {
+        let records: Vec<batch::KeyValue> =
+            values.iter().map(|value| (None, Some(*value))).collect();
+        let mut records = batch::build_batch(&records, 0);
+        let headers = batch::read_batch_headers(&records);
+        storage::append_partition_records(
+            topic,
+            0,
+            0,
+            LogConfig::default(),
+            &mut records,
+            &headers,
+        )
+        .await?;
+        Ok(())
+    }
+
+    // A sessionless fetch of partition 0 of one topic, with every field
+    // the version has
+    fn fetch_request(
+        version: u16,
+        (topic, topic_id): (&str, uuid::Uuid),
+        min_bytes: i32,
+        max_wait_ms: i32,
+    ) -> Request {
+        let flexible = version >= 12;
+        let write_topic = |data: &mut Vec<u8>| {
+            if version >= 13 {
+                data.put_slice(topic_id.as_ref()); // topic_id
+            } else {
+                data.write_string(flexible, topic); // topic
+            }
+        };
+        let mut data: Vec<u8> = Vec::new();
+        if version <= 14 {
+            data.put_i32(-1); // replica_id
+        }
+        data.put_i32(max_wait_ms); // max_wait_ms
+        data.put_i32(min_bytes); // min_bytes
+        data.put_i32(1024 * 1024); // max_bytes
+        data.put_i8(0); // isolation_level
+        if version >= 7 {
+            data.put_i32(INVALID_SESSION_ID); // session_id
+            data.put_i32(-1); // session_epoch
+        }
+        data.write_array_length(flexible, 1); // topics
+        write_topic(&mut data);
+        data.write_array_length(flexible, 1); // partitions
+        data.put_i32(0); // partition
+        if version >= 9 {
+            data.put_i32(4); // current_leader_epoch
+        }
+        data.put_i64(0); // fetch_offset
+        if version >= 12 {
+            data.put_i32(2); // last_fetched_epoch
+        }
+        if version >= 5 {
+            data.put_i64(0); // log_start_offset
+        }
+        data.put_i32(1024 * 1024); // partition_max_bytes
+        data.write_tagged_fields(flexible);
+        data.write_tagged_fields(flexible);
+        if version >= 7 {
+            data.write_array_length(flexible, 1); // forgotten_topics_data
+            write_topic(&mut data);
+            data.write_array_length(flexible, 1); // partitions
+            data.put_i32(3);
+            data.write_tagged_fields(flexible);
+        }
+        if version >= 11 {
+            data.write_string(flexible, "rack"); // rack_id
+        }
+        if version >= 15 {
+            data.put_u8(1); // one tagged field
+            data.put_u8(1); // replica_state
+            data.put_u8(13); // size
+            data.put_i32(-1); // replica_id
+            data.put_i64(-1); // replica_epoch
+            data.write_tagged_fields(flexible);
+        } else {
+            data.write_tagged_fields(flexible);
+        }
+        Request {
+            message_size: 0,
+            request_api_key: 1,
+            request_api_version: version,
+            correlation_id: 7,
+            data,
+            client_id: String::new(),
+        }
+    }
+
+    // What a response to `fetch_request` says about its partition
+    #[derive(Debug)]
+    struct Fetched {
+        topic: String,
+        topic_id: uuid::Uuid,
+        error_code: i16,
+        high_watermark: i64,
+        log_start_offset: i64,
+        records: Vec<u8>,
+    }
+
+    async fn fetch(
+        version: u16,
+        cluster: &MetadataImage,
+        topic: (&str, uuid::Uuid),
+        min_bytes: i32,
+        max_wait_ms: i32,
+    ) -> anyhow::Result<Fetched> {
+        let req = fetch_request(version, topic, min_bytes, max_wait_ms);
+        let mut res = Response::build_from_request(&req);
+        let sessions = FetchSessionCache::new(10, Duration::from_secs(60));
+        handle(&req, &mut res, cluster, &sessions).await?;
+
+        let flexible = version >= 12;
+        let mut cursor = Cursor::new(&res.body);
+        assert_eq!(cursor.read_i32().await?, 0); // throttle_time_ms
+        if version >= 7 {
+            assert_eq!(cursor.read_i16().await?, 0); // error_code
+            assert_eq!(cursor.read_i32().await?, INVALID_SESSION_ID); // session_id
+        }
+        assert_eq!(cursor.read_array_length(flexible).await?, 1);
+        let (topic, topic_id) = read_topic(&mut cursor, version, flexible).await?;
+        assert_eq!(cursor.read_array_length(flexible).await?, 1);
+        assert_eq!(cursor.read_i32().await?, 0); // partition_index
+        let error_code = cursor.read_i16().await?;
+        let high_watermark = cursor.read_i64().await?;
+        assert_eq!(cursor.read_i64().await?, high_watermark); // last_stable_offset
+        let log_start_offset = if version >= 5 {
+            cursor.read_i64().await?
+        } else {
+            -1
+        };
+        assert_eq!(cursor.read_array_length(flexible).await?, 0); // aborted_transactions
+        if version >= 11 {
+            assert_eq!(cursor.read_i32().await?, -1); // preferred_read_replica
+        }
+        let records = cursor.read_nullable_bytes(flexible).await?;
+        // of the partition, the topic and the response
+        for _ in 0..3 {
+            cursor.skip_tagged_fields(flexible).await?;
+        }
+        assert_eq!(cursor.position() as usize, res.body.len());
+        Ok(Fetched {
+            topic,
+            topic_id,
+            error_code,
+            high_watermark,
+            log_start_offset,
+            records: records.unwrap_or_default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn parses_requests_across_versions() -> anyhow::Result<()> {
+        let topic_id = uuid::Uuid::new_v4();
+        for version in 4..=16 {
+            let parsed = parse(&fetch_request(version, ("events", topic_id), 1, 500)).await?;
+            assert_eq!((parsed.max_wait_ms, parsed.min_bytes), (500, 1));
+            assert_eq!(parsed.replica_id, -1);
+            let topic = &parsed.topics[0];
+            if version >= 13 {
+                assert_eq!((topic.topic.as_str(), topic.topic_id), ("", topic_id));
+            } else {
+                assert_eq!(
+                    (topic.topic.as_str(), topic.topic_id),
+                    ("events", uuid::Uuid::nil())
+                );
+            }
+            let partition = &topic.partitions[0];
+            assert_eq!(
+                partition.current_leader_epoch,
+                if version >= 9 { 4 } else { -1 }
+            );
+            assert_eq!(
+                partition.last_fetched_epoch,
+                if version >= 12 { 2 } else { -1 }
+            );
+            assert_eq!(
+                partition.log_start_offset,
+                if version >= 5 { 0 } else { -1 }
+            );
+            assert_eq!(partition.partition_max_bytes, 1024 * 1024);
+
+            let forgotten: Vec<&[i32]> = parsed
+                .forgotten_topics_data
+                .iter()
+                .map(|topic| topic.partitions.as_slice())
+                .collect();
+            assert_eq!(
+                forgotten,
+                if version >= 7 { vec![&[3][..]] } else { vec![] }
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn encodes_responses_across_versions() -> anyhow::Result<()> {
+        let name = "fetch-versions";
+        let (cluster, topic_id) = image_with_topic(name).await?;
+        append(name, &[b"a", b"b"]).await?;
+
+        for version in 4..=16 {
+            let fetched = fetch(version, &cluster, (name, topic_id), 0, 0).await?;
+            if version >= 13 {
+                assert_eq!((fetched.topic.as_str(), fetched.topic_id), ("", topic_id));
+            } else {
+                assert_eq!(fetched.topic, name);
+            }
+            assert_eq!((fetched.error_code, fetched.high_watermark), (0, 2));
+            assert_eq!(fetched.log_start_offset, if version >= 5 { 0 } else { -1 });
+            assert_eq!(batch::read_batch_headers(&fetched.records).len(), 1);
+
+            let missing = ("fetch-missing", uuid::Uuid::new_v4());
+            let fetched = fetch(version, &cluster, missing, 0, 0).await?;
+            let error_code = if version >= 13 {
+                UNKNOWN_TOPIC_ID
+            } else {
+                UNKNOWN_TOPIC_OR_PARTITION
+            };
+            assert_eq!(
+                (fetched.error_code, fetched.high_watermark),
+                (error_code, -1)
+            );
+            assert!(fetched.records.is_empty());
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parked_fetches_wake_on_append() -> anyhow::Result<()> {
+        let name = "fetch-parked";
+        let (cluster, topic_id) = image_with_topic(name).await?;
+
+        let started = Instant::now();
+        let (fetched, appended) =
+            tokio::join!(fetch(16, &cluster, (name, topic_id), 1, 10_000), async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                append(name, &[b"a"]).await
+            });
+        appended?;
+        let fetched = fetched?;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(fetched.high_watermark, 1);
+        assert_eq!(batch::read_batch_headers(&fetched.records).len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn parked_fetches_return_at_max_wait() -> anyhow::Result<()> {
+        let name = "fetch-max-wait";
+        let (cluster, topic_id) = image_with_topic(name).await?;
+
+        let started = Instant::now();
+        let fetched = fetch(16, &cluster, (name, topic_id), 1, 200).await?;
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!((fetched.error_code, fetched.high_watermark), (0, 0));
+        assert!(fetched.records.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetches_with_errors_are_not_parked() -> anyhow::Result<()> {
+        let (cluster, _) = image_with_topic("fetch-errors").await?;
+
+        let started = Instant::now();
+        let missing = ("fetch-errors-missing", uuid::Uuid::nil());
+        let fetched = fetch(12, &cluster, missing, 1, 10_000).await?;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(fetched.error_code, UNKNOWN_TOPIC_OR_PARTITION);
+        Ok(())
+    }
+}
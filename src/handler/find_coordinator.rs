@@ -0,0 +1,103 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    config,
+    custom_trait::wire::{ReadWire, WriteWire},
+    protocol::{request::Request, response::Response},
+};
+
+static INVALID_REQUEST: i16 = 42;
+
+// Coordinator key types
+static GROUP_KEY_TYPE: i8 = 0;
+static TRANSACTION_KEY_TYPE: i8 = 1;
+
+#[derive(Debug)]
+pub struct FindCoordinatorRequest {
+    pub key_type: i8,
+    // a single key before v4, a batch from v4 on
+    pub keys: Vec<String>,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    listener_name: &str,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 4 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    // this broker coordinates every group and transaction
+    let config = config::get();
+    let node = config.advertised_listener(listener_name);
+    let error_code = if parsed.key_type == GROUP_KEY_TYPE || parsed.key_type == TRANSACTION_KEY_TYPE
+    {
+        0
+    } else {
+        INVALID_REQUEST
+    };
+    let (node_id, host, port) = if error_code == 0 {
+        (config.node_id, node.host.as_str(), node.port as i32)
+    } else {
+        (-1, "", -1)
+    };
+
+    if version >= 1 {
+        res.body.put_i32(0); // throttle_time_ms
+    }
+    if version < 4 {
+        res.body.put_i16(error_code); // error_code
+        if version >= 1 {
+            res.body.write_nullable_string(flexible, None); // error_message
+        }
+        res.body.put_i32(node_id); // node_id
+        res.body.write_string(flexible, host); // host
+        res.body.put_i32(port); // port
+    } else {
+        res.body.write_array_length(flexible, parsed.keys.len());
+        for key in &parsed.keys {
+            res.body.write_string(flexible, key); // key
+            res.body.put_i32(node_id); // node_id
+            res.body.write_string(flexible, host); // host
+            res.body.put_i32(port); // port
+            res.body.put_i16(error_code); // error_code
+            res.body.write_nullable_string(flexible, None); // error_message
+            res.body.write_tagged_fields(flexible);
+        }
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<FindCoordinatorRequest> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let mut keys = Vec::new();
+    if version < 4 {
+        keys.push(cursor.read_string(flexible).await?);
+    }
+    let key_type = if version >= 1 {
+        cursor.read_i8().await?
+    } else {
+        GROUP_KEY_TYPE
+    };
+    if version >= 4 {
+        let keys_length = cursor.read_array_length(flexible).await?;
+        for _ in 0..keys_length {
+            keys.push(cursor.read_string(flexible).await?);
+        }
+    }
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(FindCoordinatorRequest { key_type, keys })
+}
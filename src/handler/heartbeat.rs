@@ -0,0 +1,60 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    coordinator::group::GroupCoordinator,
+    custom_trait::wire::{ReadWire, WriteWire},
+    protocol::{request::Request, response::Response},
+};
+
+#[derive(Debug)]
+pub struct HeartbeatRequest {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    groups: &GroupCoordinator,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 4 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+    let error_code = groups.heartbeat(&parsed.group_id, &parsed.member_id, parsed.generation_id);
+
+    if version >= 1 {
+        res.body.put_i32(0); // throttle_time_ms
+    }
+    res.body.put_i16(error_code); // error_code
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<HeartbeatRequest> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let group_id = cursor.read_string(flexible).await?;
+    let generation_id = cursor.read_i32().await?;
+    let member_id = cursor.read_string(flexible).await?;
+    if version >= 3 {
+        // static membership is not supported
+        let _group_instance_id = cursor.read_nullable_string(flexible).await?;
+    }
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(HeartbeatRequest {
+        group_id,
+        generation_id,
+        member_id,
+    })
+}
@@ -0,0 +1,71 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    coordinator::transaction::TransactionCoordinator,
+    custom_trait::wire::{ReadWire, WriteWire},
+    protocol::{request::Request, response::Response},
+};
+
+#[derive(Debug)]
+pub struct InitProducerIdRequest {
+    pub transactional_id: Option<String>,
+    pub transaction_timeout_ms: i32,
+    // the producer's current id and epoch from v3 on, -1 otherwise
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    transactions: &TransactionCoordinator,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 4 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    let (error_code, producer_id, producer_epoch) = transactions
+        .init_producer_id(
+            parsed.transactional_id.as_deref(),
+            parsed.transaction_timeout_ms,
+            parsed.producer_id,
+            parsed.producer_epoch,
+        )
+        .await;
+
+    res.body.put_i32(0); // throttle_time_ms
+    res.body.put_i16(error_code); // error_code
+    res.body.put_i64(producer_id); // producer_id
+    res.body.put_i16(producer_epoch); // producer_epoch
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<InitProducerIdRequest> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let transactional_id = cursor.read_nullable_string(flexible).await?;
+    let transaction_timeout_ms = cursor.read_i32().await?;
+    let (producer_id, producer_epoch) = if version >= 3 {
+        (cursor.read_i64().await?, cursor.read_i16().await?)
+    } else {
+        (-1, -1)
+    };
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(InitProducerIdRequest {
+        transactional_id,
+        transaction_timeout_ms,
+        producer_id,
+        producer_epoch,
+    })
+}
@@ -0,0 +1,103 @@
+use std::{io::Cursor, time::Duration};
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    coordinator::group::{GroupCoordinator, JoinGroup},
+    custom_trait::wire::{ReadWire, WriteWire},
+    protocol::{request::Request, response::Response},
+};
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    groups: &GroupCoordinator,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 9 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+    let result = groups.join_group(parsed).await;
+
+    if version >= 2 {
+        res.body.put_i32(0); // throttle_time_ms
+    }
+    res.body.put_i16(result.error_code); // error_code
+    res.body.put_i32(result.generation_id); // generation_id
+    if version >= 7 {
+        let protocol_type = result.protocol_type.as_deref();
+        res.body.write_nullable_string(flexible, protocol_type); // protocol_type
+        let protocol_name = result.protocol_name.as_deref();
+        res.body.write_nullable_string(flexible, protocol_name); // protocol_name
+    } else {
+        let protocol_name = result.protocol_name.as_deref().unwrap_or_default();
+        res.body.write_string(flexible, protocol_name); // protocol_name
+    }
+    res.body.write_string(flexible, &result.leader_id); // leader
+    if version >= 9 {
+        res.body.put_u8(0); // skip_assignment
+    }
+    res.body.write_string(flexible, &result.member_id); // member_id
+    res.body.write_array_length(flexible, result.members.len());
+    for member in &result.members {
+        res.body.write_string(flexible, &member.member_id); // member_id
+        if version >= 5 {
+            let group_instance_id = member.group_instance_id.as_deref();
+            res.body.write_nullable_string(flexible, group_instance_id); // group_instance_id
+        }
+        res.body.write_bytes(flexible, &member.metadata); // metadata
+        res.body.write_tagged_fields(flexible);
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<JoinGroup> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let group_id = cursor.read_string(flexible).await?;
+    let session_timeout_ms = cursor.read_i32().await?;
+    // v0 rebalances within the session timeout
+    let rebalance_timeout_ms = if version >= 1 {
+        cursor.read_i32().await?
+    } else {
+        session_timeout_ms
+    };
+    let member_id = cursor.read_string(flexible).await?;
+    let group_instance_id = if version >= 5 {
+        cursor.read_nullable_string(flexible).await?
+    } else {
+        None
+    };
+    let protocol_type = cursor.read_string(flexible).await?;
+    let protocols_length = cursor.read_array_length(flexible).await?;
+    let mut protocols = Vec::new();
+    for _ in 0..protocols_length {
+        let name = cursor.read_string(flexible).await?;
+        let metadata = cursor.read_bytes(flexible).await?;
+        cursor.skip_tagged_fields(flexible).await?;
+        protocols.push((name, metadata));
+    }
+    if version >= 8 {
+        let _reason = cursor.read_nullable_string(flexible).await?;
+    }
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(JoinGroup {
+        group_id,
+        member_id,
+        group_instance_id,
+        client_id: req.client_id.clone(),
+        session_timeout: Duration::from_millis(session_timeout_ms.max(0) as u64),
+        rebalance_timeout: Duration::from_millis(rebalance_timeout_ms.max(0) as u64),
+        protocol_type,
+        protocols,
+        require_known_member_id: version >= 4,
+    })
+}
@@ -0,0 +1,78 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    coordinator::group::GroupCoordinator,
+    custom_trait::wire::{ReadWire, WriteWire},
+    protocol::{request::Request, response::Response},
+};
+
+#[derive(Debug)]
+pub struct LeaveGroupRequest {
+    pub group_id: String,
+    // (member_id, group_instance_id), a single member before v3
+    pub members: Vec<(String, Option<String>)>,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    groups: &GroupCoordinator,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 5 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+    let error_codes = groups.leave_group(&parsed.group_id, &parsed.members);
+
+    if version >= 1 {
+        res.body.put_i32(0); // throttle_time_ms
+    }
+    if version < 3 {
+        let error_code = error_codes.first().copied().unwrap_or_default();
+        res.body.put_i16(error_code); // error_code
+    } else {
+        res.body.put_i16(0); // error_code
+        res.body.write_array_length(flexible, parsed.members.len());
+        for ((member_id, group_instance_id), error_code) in parsed.members.iter().zip(error_codes) {
+            res.body.write_string(flexible, member_id); // member_id
+            let group_instance_id = group_instance_id.as_deref();
+            res.body.write_nullable_string(flexible, group_instance_id); // group_instance_id
+            res.body.put_i16(error_code); // error_code
+            res.body.write_tagged_fields(flexible);
+        }
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<LeaveGroupRequest> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let group_id = cursor.read_string(flexible).await?;
+    let mut members = Vec::new();
+    if version < 3 {
+        members.push((cursor.read_string(flexible).await?, None));
+    } else {
+        let members_length = cursor.read_array_length(flexible).await?;
+        for _ in 0..members_length {
+            let member_id = cursor.read_string(flexible).await?;
+            let group_instance_id = cursor.read_nullable_string(flexible).await?;
+            if version >= 5 {
+                let _reason = cursor.read_nullable_string(flexible).await?;
+            }
+            cursor.skip_tagged_fields(flexible).await?;
+            members.push((member_id, group_instance_id));
+        }
+    }
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(LeaveGroupRequest { group_id, members })
+}
@@ -0,0 +1,170 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    custom_trait::wire::{ReadWire, WriteWire},
+    metadata::image::MetadataImage,
+    protocol::{request::Request, response::Response},
+    storage,
+};
+
+static UNKNOWN_TOPIC_OR_PARTITION: i16 = 3;
+static FENCED_LEADER_EPOCH: i16 = 74;
+static UNKNOWN_LEADER_EPOCH: i16 = 75;
+
+// Special timestamps asking for a position instead of a time
+static EARLIEST_TIMESTAMP: i64 = -2;
+static LATEST_TIMESTAMP: i64 = -1;
+static MAX_TIMESTAMP: i64 = -3;
+
+static READ_COMMITTED: i8 = 1;
+
+#[derive(Debug)]
+pub struct ListOffsetsRequest {
+    pub isolation_level: i8,
+    pub topics: Vec<ListOffsetsTopic>,
+}
+
+#[derive(Debug)]
+pub struct ListOffsetsTopic {
+    pub name: String,
+    pub partitions: Vec<ListOffsetsPartition>,
+}
+
+#[derive(Debug)]
+pub struct ListOffsetsPartition {
+    pub partition_index: i32,
+    pub current_leader_epoch: i32,
+    pub timestamp: i64,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    cluster: &MetadataImage,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if !(1..=8).contains(&version) {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    if version >= 2 {
+        res.body.put_i32(0); // throttle_time_ms
+    }
+
+    res.body.write_array_length(flexible, parsed.topics.len());
+    for topic in parsed.topics {
+        res.body.write_string(flexible, &topic.name); // name
+        let found_topic = cluster.topic(&topic.name);
+
+        res.body
+            .write_array_length(flexible, topic.partitions.len());
+        for partition in topic.partitions {
+            let found_partition = found_topic.and_then(|t| t.partition(partition.partition_index));
+
+            let (error_code, timestamp, offset, leader_epoch) = match (found_topic, found_partition)
+            {
+                (Some(t), Some(p)) => {
+                    let leader_epoch = p.leader_epoch as i32;
+                    if partition.current_leader_epoch >= 0
+                        && partition.current_leader_epoch < leader_epoch
+                    {
+                        (FENCED_LEADER_EPOCH, -1, -1, -1)
+                    } else if partition.current_leader_epoch > leader_epoch {
+                        (UNKNOWN_LEADER_EPOCH, -1, -1, -1)
+                    } else {
+                        let (timestamp, offset) =
+                            list_offset(&t.name, &partition, version, parsed.isolation_level)
+                                .await?;
+                        (0, timestamp, offset, leader_epoch)
+                    }
+                }
+                _ => (UNKNOWN_TOPIC_OR_PARTITION, -1, -1, -1),
+            };
+
+            res.body.put_i32(partition.partition_index); // partition_index
+            res.body.put_i16(error_code); // error_code
+            res.body.put_i64(timestamp); // timestamp
+            res.body.put_i64(offset); // offset
+            if version >= 4 {
+                res.body.put_i32(leader_epoch); // leader_epoch
+            }
+            res.body.write_tagged_fields(flexible);
+        }
+        res.body.write_tagged_fields(flexible);
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+// Returns the (timestamp, offset) pair answering a partition's query
+async fn list_offset(
+    topic_name: &str,
+    partition: &ListOffsetsPartition,
+    version: u16,
+    isolation_level: i8,
+) -> anyhow::Result<(i64, i64)> {
+    let log = storage::open_partition_log(topic_name, partition.partition_index).await?;
+    let found = match partition.timestamp {
+        t if t == EARLIEST_TIMESTAMP => Some((-1, log.log_start_offset().await?)),
+        // read_committed consumers cannot read past open transactions
+        t if t == LATEST_TIMESTAMP && isolation_level == READ_COMMITTED => Some((
+            -1,
+            storage::last_stable_offset(topic_name, partition.partition_index).await?,
+        )),
+        t if t == LATEST_TIMESTAMP => Some((-1, log.high_watermark().await?)),
+        t if t == MAX_TIMESTAMP && version >= 7 => log.max_timestamp().await?,
+        t => log.offset_for_timestamp(t).await?,
+    };
+    Ok(found.unwrap_or((-1, -1)))
+}
+
+async fn parse(req: &Request) -> anyhow::Result<ListOffsetsRequest> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    // followers are answered like consumers
+    let _replica_id = cursor.read_i32().await?;
+    let isolation_level = if version >= 2 {
+        cursor.read_i8().await?
+    } else {
+        0
+    };
+
+    let topics_length = cursor.read_array_length(flexible).await?;
+    let mut topics = Vec::new();
+    for _ in 0..topics_length {
+        let name = cursor.read_string(flexible).await?;
+        let partitions_length = cursor.read_array_length(flexible).await?;
+        let mut partitions = Vec::new();
+        for _ in 0..partitions_length {
+            let partition_index = cursor.read_i32().await?;
+            let current_leader_epoch = if version >= 4 {
+                cursor.read_i32().await?
+            } else {
+                -1
+            };
+            let timestamp = cursor.read_i64().await?;
+            cursor.skip_tagged_fields(flexible).await?;
+            partitions.push(ListOffsetsPartition {
+                partition_index,
+                current_leader_epoch,
+                timestamp,
+            });
+        }
+        cursor.skip_tagged_fields(flexible).await?;
+        topics.push(ListOffsetsTopic { name, partitions });
+    }
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(ListOffsetsRequest {
+        isolation_level,
+        topics,
+    })
+}
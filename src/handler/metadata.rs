@@ -0,0 +1,265 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    config,
+    custom_trait::{
+        cursor::ReadUUID,
+        wire::{ReadWire, WriteWire},
+    },
+    metadata::{
+        cluster::PartitionValueRecord,
+        image::{MetadataImage, TopicImage},
+    },
+    protocol::{request::Request, response::Response},
+    storage,
+};
+
+static UNKNOWN_TOPIC_OR_PARTITION: i16 = 3;
+static UNKNOWN_TOPIC_ID: i16 = 100;
+// Sentinel for "authorized operations were not requested"
+static AUTHORIZED_OPERATIONS_OMITTED: i32 = i32::MIN;
+
+#[derive(Debug)]
+pub struct MetadataRequest {
+    // None means every topic
+    pub topics: Option<Vec<MetadataRequestTopic>>,
+    pub include_cluster_authorized_operations: bool,
+    pub include_topic_authorized_operations: bool,
+}
+
+#[derive(Debug)]
+pub struct MetadataRequestTopic {
+    pub topic_id: uuid::Uuid,
+    pub name: Option<String>,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    cluster: &MetadataImage,
+    listener_name: &str,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 12 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    let requested: Vec<(Option<&TopicImage>, MetadataRequestTopic)> = match parsed.topics {
+        None => cluster
+            .topics()
+            .into_iter()
+            .map(|t| {
+                (
+                    Some(t),
+                    MetadataRequestTopic {
+                        topic_id: t.uuid,
+                        name: Some(t.name.clone()),
+                    },
+                )
+            })
+            .collect(),
+        Some(topics) => topics
+            .into_iter()
+            .map(|requested| {
+                let found = match &requested.name {
+                    Some(name) => cluster.topic(name),
+                    None => cluster.topic_by_id(&requested.topic_id),
+                };
+                (found, requested)
+            })
+            .collect(),
+    };
+
+    if version >= 3 {
+        res.body.put_i32(0); // throttle_time_ms
+    }
+
+    // brokers registered in the metadata log, or this one when there are none
+    let config = config::get();
+    let advertised = config.advertised_listener(listener_name);
+    let mut brokers: Vec<(i32, &str, i32, Option<&str>)> = cluster
+        .live_brokers()
+        .filter_map(|broker| {
+            let registration = &broker.registration;
+            let endpoint = registration
+                .endpoints
+                .iter()
+                .find(|endpoint| endpoint.name == listener_name)
+                .or(registration.endpoints.first())?;
+            Some((
+                registration.broker_id,
+                endpoint.host.as_str(),
+                endpoint.port as i32,
+                registration.rack.as_deref(),
+            ))
+        })
+        .collect();
+    if brokers.is_empty() {
+        brokers.push((
+            config.node_id,
+            advertised.host.as_str(),
+            advertised.port as i32,
+            None,
+        ));
+    }
+    res.body.write_array_length(flexible, brokers.len());
+    for (node_id, host, port, rack) in brokers {
+        res.body.put_i32(node_id); // node_id
+        res.body.write_string(flexible, host); // host
+        res.body.put_i32(port); // port
+        if version >= 1 {
+            res.body.write_nullable_string(flexible, rack); // rack
+        }
+        res.body.write_tagged_fields(flexible);
+    }
+
+    if version >= 2 {
+        let cluster_id = storage::read_meta_properties().await.remove("cluster.id");
+        let cluster_id = cluster_id.as_deref();
+        res.body.write_nullable_string(flexible, cluster_id); // cluster_id
+    }
+    if version >= 1 {
+        // this process also acts as the KRaft controller
+        res.body.put_i32(config.node_id); // controller_id
+    }
+
+    res.body.write_array_length(flexible, requested.len());
+    for (topic, requested) in requested {
+        match topic {
+            None => {
+                let error_code = match requested.name {
+                    Some(_) => UNKNOWN_TOPIC_OR_PARTITION,
+                    None => UNKNOWN_TOPIC_ID,
+                };
+                res.body.put_i16(error_code); // error_code
+                let name = requested.name.as_deref();
+                if version >= 12 {
+                    res.body.write_nullable_string(flexible, name); // name
+                } else {
+                    res.body.write_string(flexible, name.unwrap_or_default()); // name
+                }
+                if version >= 10 {
+                    res.body.put_slice(requested.topic_id.as_ref()); // topic_id
+                }
+                if version >= 1 {
+                    res.body.put_u8(0); // is_internal
+                }
+                res.body.write_array_length(flexible, 0); // partitions
+            }
+            Some(t) => {
+                res.body.put_i16(0); // error_code
+                res.body.write_string(flexible, &t.name); // name
+                if version >= 10 {
+                    res.body.put_slice(t.uuid.as_ref()); // topic_id
+                }
+                if version >= 1 {
+                    res.body.put_u8(t.name.starts_with("__") as u8); // is_internal
+                }
+
+                res.body.write_array_length(flexible, t.partitions.len());
+                for partition in t.partitions.values() {
+                    write_partition(&mut res.body, version, flexible, partition);
+                }
+            }
+        }
+        if version >= 8 {
+            let topic_authorized_operations = if parsed.include_topic_authorized_operations {
+                0x00000df8
+            } else {
+                AUTHORIZED_OPERATIONS_OMITTED
+            };
+            res.body.put_i32(topic_authorized_operations); // topic_authorized_operations
+        }
+        res.body.write_tagged_fields(flexible);
+    }
+
+    if (8..=10).contains(&version) {
+        let cluster_authorized_operations = if parsed.include_cluster_authorized_operations {
+            0
+        } else {
+            AUTHORIZED_OPERATIONS_OMITTED
+        };
+        res.body.put_i32(cluster_authorized_operations); // cluster_authorized_operations
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+fn write_partition(
+    body: &mut Vec<u8>,
+    version: u16,
+    flexible: bool,
+    partition: &PartitionValueRecord,
+) {
+    body.put_i16(0); // error_code
+    body.put_u32(partition.id); // partition_index
+    body.put_u32(partition.leader_id); // leader_id
+    if version >= 7 {
+        body.put_u32(partition.leader_epoch); // leader_epoch
+    }
+    body.write_array_length(flexible, partition.replica_nodes.len());
+    for replica_node in partition.replica_nodes.iter().copied() {
+        body.put_u32(replica_node);
+    }
+    body.write_array_length(flexible, partition.in_sync_replica_nodes.len());
+    for replica_node in partition.in_sync_replica_nodes.iter().copied() {
+        body.put_u32(replica_node);
+    }
+    if version >= 5 {
+        body.write_array_length(flexible, 0); // offline_replicas
+    }
+    body.write_tagged_fields(flexible);
+}
+
+async fn parse(req: &Request) -> anyhow::Result<MetadataRequest> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let topics_length = cursor.read_nullable_array_length(flexible).await?;
+    let mut topics = Vec::new();
+    for _ in 0..topics_length.unwrap_or(0) {
+        let topic_id = if version >= 10 {
+            cursor.read_uuid().await?
+        } else {
+            uuid::Uuid::nil()
+        };
+        let name = if version >= 10 {
+            cursor.read_nullable_string(flexible).await?
+        } else {
+            Some(cursor.read_string(flexible).await?)
+        };
+        cursor.skip_tagged_fields(flexible).await?;
+        topics.push(MetadataRequestTopic { topic_id, name });
+    }
+
+    if version >= 4 {
+        // topics are never created automatically
+        let _allow_auto_topic_creation = cursor.read_u8().await?;
+    }
+    let include_cluster_authorized_operations = if (8..=10).contains(&version) {
+        cursor.read_u8().await? != 0
+    } else {
+        false
+    };
+    let include_topic_authorized_operations = if version >= 8 {
+        cursor.read_u8().await? != 0
+    } else {
+        false
+    };
+    cursor.skip_tagged_fields(flexible).await?;
+
+    // v0 uses an empty array for "all topics", later versions use null
+    let all_topics = topics_length.is_none() || (version == 0 && topics.is_empty());
+    Ok(MetadataRequest {
+        topics: if all_topics { None } else { Some(topics) },
+        include_cluster_authorized_operations,
+        include_topic_authorized_operations,
+    })
+}
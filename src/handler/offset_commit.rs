@@ -0,0 +1,173 @@
+use std::{
+    io::Cursor,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    coordinator::{
+        group::{self, GroupCoordinator},
+        offsets::{self, CommittedOffset},
+    },
+    custom_trait::wire::{ReadWire, WriteWire},
+    metadata::image::MetadataImage,
+    protocol::{request::Request, response::Response},
+};
+
+#[derive(Debug)]
+pub struct OffsetCommitRequest {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    pub topics: Vec<OffsetCommitTopic>,
+}
+
+#[derive(Debug)]
+pub struct OffsetCommitTopic {
+    pub name: String,
+    pub partitions: Vec<OffsetCommitPartition>,
+}
+
+#[derive(Debug)]
+pub struct OffsetCommitPartition {
+    pub index: i32,
+    pub committed_offset: i64,
+    pub committed_leader_epoch: i32,
+    // only sent in v1, -1 means now
+    pub commit_timestamp: i64,
+    pub committed_metadata: Option<String>,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    cluster: &MetadataImage,
+    groups: &GroupCoordinator,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 9 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+    let group_error =
+        groups.validate_offset_commit(&parsed.group_id, &parsed.member_id, parsed.generation_id);
+    let topics = parsed.topics.iter().map(|topic| {
+        let partitions = topic.partitions.iter().map(|partition| {
+            let committed = CommittedOffset {
+                offset: partition.committed_offset,
+                leader_epoch: partition.committed_leader_epoch,
+                metadata: partition.committed_metadata.clone().unwrap_or_default(),
+                commit_timestamp: match partition.commit_timestamp {
+                    -1 => now,
+                    timestamp => timestamp,
+                },
+            };
+            (partition.index, committed)
+        });
+        (topic.name.as_str(), partitions.collect())
+    });
+    let (mut error_codes, commits) = offsets::validate_commits(cluster, group_error, topics);
+
+    // the commits are written together, so they fail together
+    let committed = groups
+        .offsets()
+        .commit(&parsed.group_id, commits, now)
+        .await;
+    if let Err(e) = committed {
+        eprintln!("failed to commit offsets of {}: {:#}", parsed.group_id, e);
+        for error_code in error_codes.iter_mut().flatten() {
+            if *error_code == 0 {
+                *error_code = group::COORDINATOR_NOT_AVAILABLE;
+            }
+        }
+    }
+
+    if version >= 3 {
+        res.body.put_i32(0); // throttle_time_ms
+    }
+    res.body.write_array_length(flexible, parsed.topics.len());
+    for (topic, topic_errors) in parsed.topics.iter().zip(error_codes) {
+        res.body.write_string(flexible, &topic.name); // name
+        res.body
+            .write_array_length(flexible, topic.partitions.len());
+        for (partition, error_code) in topic.partitions.iter().zip(topic_errors) {
+            res.body.put_i32(partition.index); // partition_index
+            res.body.put_i16(error_code); // error_code
+            res.body.write_tagged_fields(flexible);
+        }
+        res.body.write_tagged_fields(flexible);
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<OffsetCommitRequest> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let group_id = cursor.read_string(flexible).await?;
+    let (generation_id, member_id) = if version >= 1 {
+        (
+            cursor.read_i32().await?,
+            cursor.read_string(flexible).await?,
+        )
+    } else {
+        (-1, String::new())
+    };
+    if version >= 7 {
+        // static membership is not supported
+        let _group_instance_id = cursor.read_nullable_string(flexible).await?;
+    }
+    if (2..=4).contains(&version) {
+        // offsets are kept until deleted, whatever the client asks for
+        let _retention_time_ms = cursor.read_i64().await?;
+    }
+
+    let topics_length = cursor.read_array_length(flexible).await?;
+    let mut topics = Vec::new();
+    for _ in 0..topics_length {
+        let name = cursor.read_string(flexible).await?;
+        let partitions_length = cursor.read_array_length(flexible).await?;
+        let mut partitions = Vec::new();
+        for _ in 0..partitions_length {
+            let index = cursor.read_i32().await?;
+            let committed_offset = cursor.read_i64().await?;
+            let committed_leader_epoch = if version >= 6 {
+                cursor.read_i32().await?
+            } else {
+                -1
+            };
+            let commit_timestamp = if version == 1 {
+                cursor.read_i64().await?
+            } else {
+                -1
+            };
+            let committed_metadata = cursor.read_nullable_string(flexible).await?;
+            cursor.skip_tagged_fields(flexible).await?;
+            partitions.push(OffsetCommitPartition {
+                index,
+                committed_offset,
+                committed_leader_epoch,
+                commit_timestamp,
+                committed_metadata,
+            });
+        }
+        cursor.skip_tagged_fields(flexible).await?;
+        topics.push(OffsetCommitTopic { name, partitions });
+    }
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(OffsetCommitRequest {
+        group_id,
+        generation_id,
+        member_id,
+        topics,
+    })
+}
@@ -0,0 +1,221 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    coordinator::{
+        group::{self, GroupCoordinator},
+        offsets::{CommittedOffset, TopicPartition},
+    },
+    custom_trait::wire::{ReadWire, WriteWire},
+    protocol::{request::Request, response::Response},
+};
+
+#[derive(Debug)]
+pub struct OffsetFetchRequest {
+    // a single group before v8, a batch from v8 on
+    pub groups: Vec<OffsetFetchGroup>,
+    pub require_stable: bool,
+}
+
+#[derive(Debug)]
+pub struct OffsetFetchGroup {
+    pub group_id: String,
+    // None asks for every partition the group committed
+    pub topics: Option<Vec<(String, Vec<i32>)>>,
+}
+
+static UNSTABLE_OFFSET_COMMIT: i16 = 88;
+
+// Committed offsets by topic, None for partitions without a commit,
+// with the partition's error code
+type TopicOffsets = Vec<(String, Vec<(i32, Option<CommittedOffset>, i16)>)>;
+
+struct GroupOffsets {
+    error_code: i16,
+    topics: TopicOffsets,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    groups: &GroupCoordinator,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 9 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+    let results: Vec<GroupOffsets> = parsed
+        .groups
+        .iter()
+        .map(|group| fetch_offsets(groups, group, parsed.require_stable))
+        .collect();
+
+    if version >= 3 {
+        res.body.put_i32(0); // throttle_time_ms
+    }
+    if version < 8 {
+        let result = &results[0];
+        write_topics(res, version, flexible, result);
+        if version >= 2 {
+            res.body.put_i16(result.error_code); // error_code
+        }
+    } else {
+        res.body.write_array_length(flexible, results.len());
+        for (group, result) in parsed.groups.iter().zip(&results) {
+            res.body.write_string(flexible, &group.group_id); // group_id
+            write_topics(res, version, flexible, result);
+            res.body.put_i16(result.error_code); // error_code
+            res.body.write_tagged_fields(flexible);
+        }
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+fn fetch_offsets(
+    groups: &GroupCoordinator,
+    group: &OffsetFetchGroup,
+    require_stable: bool,
+) -> GroupOffsets {
+    let offsets = groups.offsets();
+    let mut topics: TopicOffsets = Vec::new();
+    match &group.topics {
+        Some(requested) => {
+            for (name, partitions) in requested {
+                let partitions = partitions
+                    .iter()
+                    .map(|&index| {
+                        let partition: TopicPartition = (name.clone(), index);
+                        // an open transaction may still change the offset
+                        if require_stable && offsets.has_pending(&group.group_id, &partition) {
+                            return (index, None, UNSTABLE_OFFSET_COMMIT);
+                        }
+                        (index, offsets.fetch(&group.group_id, &partition), 0)
+                    })
+                    .collect();
+                topics.push((name.clone(), partitions));
+            }
+        }
+        None => {
+            for ((name, index), committed) in offsets.group_offsets(&group.group_id) {
+                match topics.last_mut() {
+                    Some((last, partitions)) if *last == name => {
+                        partitions.push((index, Some(committed), 0))
+                    }
+                    _ => topics.push((name, vec![(index, Some(committed), 0)])),
+                }
+            }
+        }
+    }
+    GroupOffsets {
+        error_code: if group.group_id.is_empty() {
+            group::INVALID_GROUP_ID
+        } else {
+            0
+        },
+        topics,
+    }
+}
+
+fn write_topics(res: &mut Response<'_>, version: u16, flexible: bool, result: &GroupOffsets) {
+    res.body.write_array_length(flexible, result.topics.len());
+    for (name, partitions) in &result.topics {
+        res.body.write_string(flexible, name); // name
+        res.body.write_array_length(flexible, partitions.len());
+        for (index, committed, partition_error_code) in partitions {
+            res.body.put_i32(*index); // partition_index
+
+            // a partition without a commit gets offset -1 and no error
+            match committed {
+                Some(committed) => {
+                    res.body.put_i64(committed.offset); // committed_offset
+                    if version >= 5 {
+                        res.body.put_i32(committed.leader_epoch); // committed_leader_epoch
+                    }
+                    let metadata = Some(committed.metadata.as_str());
+                    res.body.write_nullable_string(flexible, metadata); // metadata
+                }
+                None => {
+                    res.body.put_i64(-1); // committed_offset
+                    if version >= 5 {
+                        res.body.put_i32(-1); // committed_leader_epoch
+                    }
+                    res.body.write_nullable_string(flexible, Some("")); // metadata
+                }
+            }
+            // before v2 group errors are reported per partition
+            let error_code = if version < 2 && result.error_code != 0 {
+                result.error_code
+            } else {
+                *partition_error_code
+            };
+            res.body.put_i16(error_code); // error_code
+            res.body.write_tagged_fields(flexible);
+        }
+        res.body.write_tagged_fields(flexible);
+    }
+}
+
+async fn parse(req: &Request) -> anyhow::Result<OffsetFetchRequest> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let mut groups = Vec::new();
+    if version < 8 {
+        let group_id = cursor.read_string(flexible).await?;
+        let topics = read_topics(&mut cursor, flexible).await?;
+        groups.push(OffsetFetchGroup { group_id, topics });
+    } else {
+        let groups_length = cursor.read_array_length(flexible).await?;
+        for _ in 0..groups_length {
+            let group_id = cursor.read_string(flexible).await?;
+            if version >= 9 {
+                // only checked by the consumer group protocol of KIP-848
+                let _member_id = cursor.read_nullable_string(flexible).await?;
+                let _member_epoch = cursor.read_i32().await?;
+            }
+            let topics = read_topics(&mut cursor, flexible).await?;
+            cursor.skip_tagged_fields(flexible).await?;
+            groups.push(OffsetFetchGroup { group_id, topics });
+        }
+    }
+    let require_stable = if version >= 7 {
+        cursor.read_u8().await? != 0
+    } else {
+        false
+    };
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(OffsetFetchRequest {
+        groups,
+        require_stable,
+    })
+}
+
+// The topics array is nullable from v2 on
+async fn read_topics(
+    cursor: &mut Cursor<&Vec<u8>>,
+    flexible: bool,
+) -> anyhow::Result<Option<Vec<(String, Vec<i32>)>>> {
+    let Some(topics_length) = cursor.read_nullable_array_length(flexible).await? else {
+        return Ok(None);
+    };
+    let mut topics = Vec::new();
+    for _ in 0..topics_length {
+        let name = cursor.read_string(flexible).await?;
+        let partitions_length = cursor.read_array_length(flexible).await?;
+        let mut partitions = Vec::new();
+        for _ in 0..partitions_length {
+            partitions.push(cursor.read_i32().await?);
+        }
+        cursor.skip_tagged_fields(flexible).await?;
+        topics.push((name, partitions));
+    }
+    Ok(Some(topics))
+}
@@ -0,0 +1,309 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    coordinator::transaction::{self, TransactionCoordinator},
+    custom_trait::wire::{ReadWire, WriteWire},
+    metadata::image::MetadataImage,
+    protocol::{request::Request, response::Response},
+    storage::{
+        self,
+        batch::{self, BatchError, BatchHeaders},
+        compression::CompressionType,
+        log::LogConfig,
+        producer_state::ProducerStateError,
+    },
+};
+
+static UNKNOWN_TOPIC_OR_PARTITION: i16 = 3;
+static INVALID_REQUIRED_ACKS: i16 = 21;
+static KAFKA_STORAGE_ERROR: i16 = 56;
+
+#[derive(Debug)]
+pub struct ProduceRequest {
+    pub transactional_id: Option<String>,
+    pub acks: i16,
+    pub topics: Vec<TopicData>,
+}
+
+#[derive(Debug)]
+pub struct TopicData {
+    pub name: String,
+    pub partitions: Vec<PartitionData>,
+}
+
+#[derive(Debug)]
+pub struct PartitionData {
+    pub index: i32,
+    pub records: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct PartitionResponse {
+    index: i32,
+    error_code: i16,
+    base_offset: i64,
+    log_append_time_ms: i64,
+    log_start_offset: i64,
+    error_message: Option<String>,
+}
+
+impl PartitionResponse {
+    fn error(index: i32, error_code: i16, error_message: Option<String>) -> Self {
+        PartitionResponse {
+            index,
+            error_code,
+            base_offset: -1,
+            log_append_time_ms: -1,
+            log_start_offset: -1,
+            error_message,
+        }
+    }
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    cluster: &MetadataImage,
+    transactions: &TransactionCoordinator,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if !(3..=11).contains(&version) {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    let mut responses = Vec::new();
+    for topic in parsed.topics {
+        let topic_record = cluster.topic(&topic.name);
+        let mut partition_responses = Vec::new();
+
+        for partition in topic.partitions {
+            if !matches!(parsed.acks, -1..=1) {
+                partition_responses.push(PartitionResponse::error(
+                    partition.index,
+                    INVALID_REQUIRED_ACKS,
+                    None,
+                ));
+                continue;
+            }
+
+            let partition_record = topic_record.and_then(|t| t.partition(partition.index));
+            let Some(partition_record) = partition_record else {
+                partition_responses.push(PartitionResponse::error(
+                    partition.index,
+                    UNKNOWN_TOPIC_OR_PARTITION,
+                    None,
+                ));
+                continue;
+            };
+
+            let records = partition.records.unwrap_or_default();
+            let topic_compression = cluster.topic_config(&topic.name, "compression.type");
+            let (mut records, headers) = match prepare_records(records, version, topic_compression)
+            {
+                Ok(prepared) => prepared,
+                Err(e) => {
+                    partition_responses.push(PartitionResponse::error(
+                        partition.index,
+                        e.error_code(),
+                        Some(e.to_string()),
+                    ));
+                    continue;
+                }
+            };
+
+            // transactional records only go to partitions added to the
+            // producer's open transaction
+            if let Some((_, header)) = headers.iter().find(|(_, h)| h.is_transactional()) {
+                let error_code = match &parsed.transactional_id {
+                    Some(transactional_id) => {
+                        let topic_partition = (topic.name.clone(), partition.index);
+                        transactions
+                            .validate_produce(
+                                transactional_id,
+                                header.producer_id,
+                                header.producer_epoch,
+                                &topic_partition,
+                            )
+                            .await
+                    }
+                    None => transaction::INVALID_TXN_STATE,
+                };
+                if error_code != 0 {
+                    partition_responses.push(PartitionResponse::error(
+                        partition.index,
+                        error_code,
+                        None,
+                    ));
+                    continue;
+                }
+            }
+
+            let appended = storage::append_partition_records(
+                &topic.name,
+                partition.index,
+                partition_record.leader_epoch as i32,
+                LogConfig::for_topic(cluster, &topic.name),
+                &mut records,
+                &headers,
+            )
+            .await;
+            partition_responses.push(match appended {
+                Ok(info) => PartitionResponse {
+                    index: partition.index,
+                    error_code: 0,
+                    base_offset: info.base_offset,
+                    log_append_time_ms: -1, // topics use CreateTime
+                    log_start_offset: info.log_start_offset,
+                    error_message: None,
+                },
+                Err(e) => {
+                    let error_code = match e.downcast_ref::<ProducerStateError>() {
+                        Some(e) => e.error_code(),
+                        None => KAFKA_STORAGE_ERROR,
+                    };
+                    PartitionResponse::error(partition.index, error_code, Some(e.to_string()))
+                }
+            });
+        }
+        responses.push((topic.name, partition_responses));
+    }
+
+    if parsed.acks == 0 {
+        res.suppressed = true;
+        return Ok(());
+    }
+
+    res.body.write_array_length(flexible, responses.len());
+    for (name, partition_responses) in responses {
+        res.body.write_string(flexible, &name);
+        res.body
+            .write_array_length(flexible, partition_responses.len());
+        for partition in partition_responses {
+            res.body.put_i32(partition.index); // index
+            res.body.put_i16(partition.error_code); // error_code
+            res.body.put_i64(partition.base_offset); // base_offset
+            res.body.put_i64(partition.log_append_time_ms); // log_append_time_ms
+            if version >= 5 {
+                res.body.put_i64(partition.log_start_offset); // log_start_offset
+            }
+            if version >= 8 {
+                res.body.write_array_length(flexible, 0); // record_errors
+                let error_message = partition.error_message.as_deref();
+                res.body.write_nullable_string(flexible, error_message); // error_message
+            }
+            res.body.write_tagged_fields(flexible);
+        }
+        res.body.write_tagged_fields(flexible);
+    }
+    res.body.put_i32(0); // throttle_time_ms
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+// Validates the single batch of a partition and re-encodes it with the topic's
+// `compression.type` unless it keeps the producer's codec.
+fn prepare_records(
+    records: Vec<u8>,
+    version: u16,
+    topic_compression: Option<&str>,
+) -> Result<(Vec<u8>, BatchHeaders), BatchError> {
+    let headers = batch::validate_batches(&records)?;
+    // like Kafka from v3 on, the first versions this broker takes
+    if headers.len() > 1 {
+        return Err(BatchError::MultipleBatches(headers.len()));
+    }
+    for (_, header) in &headers {
+        // markers are written by the transaction coordinator only
+        if header.is_control() {
+            return Err(BatchError::ControlBatch);
+        }
+        let compression = header.compression()?;
+        // clients may only send zstd from v7 on
+        if compression == CompressionType::Zstd && version < 7 {
+            return Err(BatchError::UnsupportedCompression(compression.id()));
+        }
+    }
+
+    let target = match topic_compression.map(str::parse::<CompressionType>) {
+        Some(Ok(target)) => target,
+        // "producer" keeps whatever codec the producer used
+        _ => return Ok((records, headers)),
+    };
+    if headers
+        .iter()
+        .all(|(_, header)| header.compression().ok() == Some(target))
+    {
+        return Ok((records, headers));
+    }
+    let records = batch::recompress_batches(&records, |codec| codec != target, target)?;
+    let headers = batch::validate_batches(&records)?;
+    Ok((records, headers))
+}
+
+async fn parse(req: &Request) -> anyhow::Result<ProduceRequest> {
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let transactional_id = cursor.read_nullable_string(flexible).await?;
+    let acks = cursor.read_i16().await?;
+    // records are written before the response, there are no replicas
+    // to wait for
+    let _timeout_ms = cursor.read_i32().await?;
+
+    let topic_length = cursor.read_array_length(flexible).await?;
+    let mut topics = Vec::new();
+    for _ in 0..topic_length {
+        let name = cursor.read_string(flexible).await?;
+        let partitions_length = cursor.read_array_length(flexible).await?;
+        let mut partitions = Vec::new();
+        for _ in 0..partitions_length {
+            let index = cursor.read_i32().await?;
+            let records = cursor.read_nullable_bytes(flexible).await?;
+            cursor.skip_tagged_fields(flexible).await?;
+            partitions.push(PartitionData { index, records });
+        }
+        cursor.skip_tagged_fields(flexible).await?;
+        topics.push(TopicData { name, partitions });
+    }
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(ProduceRequest {
+        transactional_id,
+        acks,
+        topics,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::batch::ControlMarker;
+
+    #[test]
+    fn rejects_control_batches_from_clients() {
+        let marker = batch::build_control_batch(ControlMarker::Commit, 7, 1, 0, 1000);
+        let err = prepare_records(marker, 11, None).unwrap_err();
+        assert!(matches!(err, BatchError::ControlBatch));
+        assert_eq!(err.error_code(), batch::INVALID_RECORD);
+    }
+
+    #[test]
+    fn takes_one_batch_per_partition() -> anyhow::Result<()> {
+        let single = batch::build_batch(&[(None, Some(b"a"))], 1000);
+        let (_, headers) = prepare_records(single.clone(), 3, None)?;
+        assert_eq!(headers.len(), 1);
+
+        let mut double = single.clone();
+        double.extend(&single);
+        let err = prepare_records(double, 3, None).unwrap_err();
+        assert!(matches!(err, BatchError::MultipleBatches(2)));
+        assert_eq!(err.error_code(), batch::INVALID_RECORD);
+        Ok(())
+    }
+}
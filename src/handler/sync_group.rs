@@ -0,0 +1,78 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    coordinator::group::{GroupCoordinator, SyncGroup},
+    custom_trait::wire::{ReadWire, WriteWire},
+    protocol::{request::Request, response::Response},
+};
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    groups: &GroupCoordinator,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 5 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+    let result = groups.sync_group(parsed).await;
+
+    if version >= 1 {
+        res.body.put_i32(0); // throttle_time_ms
+    }
+    res.body.put_i16(result.error_code); // error_code
+    if version >= 5 {
+        let protocol_type = result.protocol_type.as_deref();
+        res.body.write_nullable_string(flexible, protocol_type); // protocol_type
+        let protocol_name = result.protocol_name.as_deref();
+        res.body.write_nullable_string(flexible, protocol_name); // protocol_name
+    }
+    res.body.write_bytes(flexible, &result.assignment); // assignment
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<SyncGroup> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let group_id = cursor.read_string(flexible).await?;
+    let generation_id = cursor.read_i32().await?;
+    let member_id = cursor.read_string(flexible).await?;
+    if version >= 3 {
+        let _group_instance_id = cursor.read_nullable_string(flexible).await?;
+    }
+    let (protocol_type, protocol_name) = if version >= 5 {
+        (
+            cursor.read_nullable_string(flexible).await?,
+            cursor.read_nullable_string(flexible).await?,
+        )
+    } else {
+        (None, None)
+    };
+    let assignments_length = cursor.read_array_length(flexible).await?;
+    let mut assignments = Vec::new();
+    for _ in 0..assignments_length {
+        let member_id = cursor.read_string(flexible).await?;
+        let assignment = cursor.read_bytes(flexible).await?;
+        cursor.skip_tagged_fields(flexible).await?;
+        assignments.push((member_id, assignment));
+    }
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(SyncGroup {
+        group_id,
+        generation_id,
+        member_id,
+        protocol_type,
+        protocol_name,
+        assignments,
+    })
+}
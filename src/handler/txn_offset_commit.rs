@@ -0,0 +1,190 @@
+use std::{
+    io::Cursor,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    coordinator::{
+        group::{self, GroupCoordinator},
+        offsets::{self, CommittedOffset},
+        transaction::TransactionCoordinator,
+    },
+    custom_trait::wire::{ReadWire, WriteWire},
+    metadata::image::MetadataImage,
+    protocol::{request::Request, response::Response},
+};
+
+#[derive(Debug)]
+pub struct TxnOffsetCommitRequest {
+    pub transactional_id: String,
+    pub group_id: String,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    // the member committing from v3 on, -1 and empty before
+    pub generation_id: i32,
+    pub member_id: String,
+    pub topics: Vec<TxnOffsetCommitTopic>,
+}
+
+#[derive(Debug)]
+pub struct TxnOffsetCommitTopic {
+    pub name: String,
+    pub partitions: Vec<TxnOffsetCommitPartition>,
+}
+
+#[derive(Debug)]
+pub struct TxnOffsetCommitPartition {
+    pub index: i32,
+    pub committed_offset: i64,
+    pub committed_leader_epoch: i32,
+    pub committed_metadata: Option<String>,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    cluster: &MetadataImage,
+    groups: &GroupCoordinator,
+    transactions: &TransactionCoordinator,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 3 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+    let mut request_error = transactions
+        .validate_offset_commit(
+            &parsed.transactional_id,
+            parsed.producer_id,
+            parsed.producer_epoch,
+        )
+        .await;
+    // producers without a group membership commit with generation -1
+    if request_error == 0 && (parsed.generation_id >= 0 || !parsed.member_id.is_empty()) {
+        request_error = groups.validate_offset_commit(
+            &parsed.group_id,
+            &parsed.member_id,
+            parsed.generation_id,
+        );
+    } else if request_error == 0 && parsed.group_id.is_empty() {
+        request_error = group::INVALID_GROUP_ID;
+    }
+
+    let topics = parsed.topics.iter().map(|topic| {
+        let partitions = topic.partitions.iter().map(|partition| {
+            let committed = CommittedOffset {
+                offset: partition.committed_offset,
+                leader_epoch: partition.committed_leader_epoch,
+                metadata: partition.committed_metadata.clone().unwrap_or_default(),
+                commit_timestamp: now,
+            };
+            (partition.index, committed)
+        });
+        (topic.name.as_str(), partitions.collect())
+    });
+    let (mut error_codes, commits) = offsets::validate_commits(cluster, request_error, topics);
+
+    // visible to fetches once the transaction commits
+    let committed = groups
+        .offsets()
+        .commit_transactional(
+            &parsed.group_id,
+            parsed.producer_id,
+            parsed.producer_epoch,
+            commits,
+            now,
+        )
+        .await;
+    if let Err(e) = committed {
+        eprintln!(
+            "failed to commit offsets of {} in a transaction: {:#}",
+            parsed.group_id, e
+        );
+        for error_code in error_codes.iter_mut().flatten() {
+            if *error_code == 0 {
+                *error_code = group::COORDINATOR_NOT_AVAILABLE;
+            }
+        }
+    }
+
+    res.body.put_i32(0); // throttle_time_ms
+    res.body.write_array_length(flexible, parsed.topics.len());
+    for (topic, topic_errors) in parsed.topics.iter().zip(error_codes) {
+        res.body.write_string(flexible, &topic.name); // name
+        res.body
+            .write_array_length(flexible, topic.partitions.len());
+        for (partition, error_code) in topic.partitions.iter().zip(topic_errors) {
+            res.body.put_i32(partition.index); // partition_index
+            res.body.put_i16(error_code); // error_code
+            res.body.write_tagged_fields(flexible);
+        }
+        res.body.write_tagged_fields(flexible);
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<TxnOffsetCommitRequest> {
+    let version = req.request_api_version;
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let transactional_id = cursor.read_string(flexible).await?;
+    let group_id = cursor.read_string(flexible).await?;
+    let producer_id = cursor.read_i64().await?;
+    let producer_epoch = cursor.read_i16().await?;
+    let (generation_id, member_id) = if version >= 3 {
+        let generation_id = cursor.read_i32().await?;
+        let member_id = cursor.read_string(flexible).await?;
+        // static membership is not supported
+        let _group_instance_id = cursor.read_nullable_string(flexible).await?;
+        (generation_id, member_id)
+    } else {
+        (-1, String::new())
+    };
+
+    let topics_length = cursor.read_array_length(flexible).await?;
+    let mut topics = Vec::new();
+    for _ in 0..topics_length {
+        let name = cursor.read_string(flexible).await?;
+        let partitions_length = cursor.read_array_length(flexible).await?;
+        let mut partitions = Vec::new();
+        for _ in 0..partitions_length {
+            let index = cursor.read_i32().await?;
+            let committed_offset = cursor.read_i64().await?;
+            let committed_leader_epoch = if version >= 2 {
+                cursor.read_i32().await?
+            } else {
+                -1
+            };
+            let committed_metadata = cursor.read_nullable_string(flexible).await?;
+            cursor.skip_tagged_fields(flexible).await?;
+            partitions.push(TxnOffsetCommitPartition {
+                index,
+                committed_offset,
+                committed_leader_epoch,
+                committed_metadata,
+            });
+        }
+        cursor.skip_tagged_fields(flexible).await?;
+        topics.push(TxnOffsetCommitTopic { name, partitions });
+    }
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(TxnOffsetCommitRequest {
+        transactional_id,
+        group_id,
+        producer_id,
+        producer_epoch,
+        generation_id,
+        member_id,
+        topics,
+    })
+}
@@ -0,0 +1,133 @@
+use std::io::Cursor;
+
+use bytes::BufMut;
+use tokio::io::AsyncReadExt;
+
+use crate::{
+    coordinator::transaction::TransactionCoordinator,
+    custom_trait::wire::{ReadWire, WriteWire},
+    protocol::{request::Request, response::Response},
+    storage::{batch::ControlMarker, producer_state::ProducerStateError},
+};
+
+static KAFKA_STORAGE_ERROR: i16 = 56;
+
+#[derive(Debug)]
+pub struct WriteTxnMarkersRequest {
+    pub markers: Vec<WritableTxnMarker>,
+}
+
+#[derive(Debug)]
+pub struct WritableTxnMarker {
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub transaction_result: bool,
+    pub topics: Vec<(String, Vec<i32>)>,
+    pub coordinator_epoch: i32,
+}
+
+pub async fn handle<'a>(
+    req: &Request,
+    res: &mut Response<'a>,
+    transactions: &TransactionCoordinator,
+) -> anyhow::Result<()> {
+    let version = req.request_api_version;
+    if version > 1 {
+        res.body.put_u16(35);
+        return Ok(());
+    }
+    let flexible = req.is_flexible();
+    let parsed = parse(req).await?;
+
+    let mut results = Vec::new();
+    for marker in &parsed.markers {
+        let control_marker = if marker.transaction_result {
+            ControlMarker::Commit
+        } else {
+            ControlMarker::Abort
+        };
+        let mut topic_results = Vec::new();
+        for (name, indexes) in &marker.topics {
+            let mut partition_results = Vec::new();
+            for index in indexes {
+                let written = transactions
+                    .write_marker(
+                        marker.producer_id,
+                        marker.producer_epoch,
+                        control_marker,
+                        marker.coordinator_epoch,
+                        &(name.clone(), *index),
+                    )
+                    .await;
+                let error_code = match written {
+                    Ok(error_code) => error_code,
+                    Err(e) => match e.downcast_ref::<ProducerStateError>() {
+                        Some(e) => e.error_code(),
+                        None => {
+                            eprintln!("failed to write marker to {}-{}: {:#}", name, index, e);
+                            KAFKA_STORAGE_ERROR
+                        }
+                    },
+                };
+                partition_results.push((*index, error_code));
+            }
+            topic_results.push((name, partition_results));
+        }
+        results.push((marker.producer_id, topic_results));
+    }
+
+    res.body.write_array_length(flexible, results.len());
+    for (producer_id, topic_results) in results {
+        res.body.put_i64(producer_id); // producer_id
+        res.body.write_array_length(flexible, topic_results.len());
+        for (name, partition_results) in topic_results {
+            res.body.write_string(flexible, name); // name
+            res.body
+                .write_array_length(flexible, partition_results.len());
+            for (index, error_code) in partition_results {
+                res.body.put_i32(index); // partition_index
+                res.body.put_i16(error_code); // error_code
+                res.body.write_tagged_fields(flexible);
+            }
+            res.body.write_tagged_fields(flexible);
+        }
+        res.body.write_tagged_fields(flexible);
+    }
+    res.body.write_tagged_fields(flexible);
+    Ok(())
+}
+
+async fn parse(req: &Request) -> anyhow::Result<WriteTxnMarkersRequest> {
+    let flexible = req.is_flexible();
+    let mut cursor = Cursor::new(&req.data);
+
+    let markers_length = cursor.read_array_length(flexible).await?;
+    let mut markers = Vec::new();
+    for _ in 0..markers_length {
+        let producer_id = cursor.read_i64().await?;
+        let producer_epoch = cursor.read_i16().await?;
+        let transaction_result = cursor.read_u8().await? != 0;
+        let mut topics = Vec::new();
+        for _ in 0..cursor.read_array_length(flexible).await? {
+            let name = cursor.read_string(flexible).await?;
+            let mut partition_indexes = Vec::new();
+            for _ in 0..cursor.read_array_length(flexible).await? {
+                partition_indexes.push(cursor.read_i32().await?);
+            }
+            cursor.skip_tagged_fields(flexible).await?;
+            topics.push((name, partition_indexes));
+        }
+        let coordinator_epoch = cursor.read_i32().await?;
+        cursor.skip_tagged_fields(flexible).await?;
+        markers.push(WritableTxnMarker {
+            producer_id,
+            producer_epoch,
+            transaction_result,
+            topics,
+            coordinator_epoch,
+        });
+    }
+    cursor.skip_tagged_fields(flexible).await?;
+
+    Ok(WriteTxnMarkersRequest { markers })
+}
@@ -5,13 +5,21 @@ use std::{
 
 use bytes::{buf, Buf, BufMut};
 
+mod config;
+mod coordinator;
 mod custom_trait;
 mod handler;
 mod metadata;
 mod protocol;
+mod storage;
 
-use metadata::cluster::{Cluster, ClusterSummary};
+use coordinator::{
+    fetch_session::FetchSessionCache, group::GroupCoordinator, transaction::TransactionCoordinator,
+};
+use metadata::image::MetadataImage;
+use metadata::tailer::{self, SharedImage};
 use protocol::{
+    frame::FrameReader,
     request::{Request, RequestError},
     response::Response,
 };
@@ -23,65 +31,271 @@ async fn main() -> tokio::io::Result<()> {
     // You can use print statements as follows for debugging, they'll be visible when running tests.
     println!("Logs from your program will appear here!");
 
-    let cluster_metadata = Arc::new(match metadata::cluster::parse_metadata_cluster().await {
-        Ok(res) => res,
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match config::ServerConfig::from_args(&args) {
+        Ok(config) => config::init(config),
         Err(e) => {
+            eprintln!("invalid configuration: {:#}", e);
+            process::exit(1);
+        }
+    }
+
+    let corrupt_batch_policy = config::get().corrupt_metadata_batches;
+
+    let metadata_read = match metadata::cluster::parse_metadata_cluster(corrupt_batch_policy).await
+    {
+        Ok(res) => res,
+        Err(_) => {
             println!("error parsing metadata - first try");
             tokio::time::sleep(Duration::from_millis(100)).await;
-            let second_try = metadata::cluster::parse_metadata_cluster().await;
+            let second_try = metadata::cluster::parse_metadata_cluster(corrupt_batch_policy).await;
             if second_try.is_err() {
                 process::exit(1);
             }
             second_try.unwrap()
         }
-    });
+    };
+    let cluster_metadata = Arc::new(SharedImage::new(
+        MetadataImage::from_read(&metadata_read),
+        metadata_read.next_offset,
+        corrupt_batch_policy,
+    ));
+    tokio::spawn(tailer::tail(cluster_metadata.clone()));
+    // partitions of topics deleted before the last shutdown
+    if let Err(e) = storage::remove_deleted_partitions().await {
+        eprintln!("failed to clean up deleted partitions: {}", e);
+    }
+
+    let groups = match GroupCoordinator::load().await {
+        Ok(groups) => Arc::new(groups),
+        Err(e) => {
+            eprintln!("error loading committed offsets: {:#}", e);
+            process::exit(1);
+        }
+    };
+    tokio::spawn(groups.clone().run_expiration());
+    let transactions =
+        match TransactionCoordinator::load(cluster_metadata.clone(), groups.clone()).await {
+            Ok(transactions) => Arc::new(transactions),
+            Err(e) => {
+                eprintln!("error loading transaction states: {:#}", e);
+                process::exit(1);
+            }
+        };
+    tokio::spawn(transactions.clone().run_expiration());
+    let fetch_sessions = Arc::new(FetchSessionCache::new(
+        config::get().fetch_session_cache_slots,
+        Duration::from_millis(config::get().fetch_session_eviction_ms),
+    ));
 
-    // Uncomment this block to pass the first stage
-    //
-    let listener = TcpListener::bind("127.0.0.1:9092").await?;
+    // bind every listener before serving any, so a taken port fails startup
+    let mut listeners = Vec::new();
+    for listener in &config::get().listeners {
+        let bound = TcpListener::bind(listener.bind_address()).await?;
+        listeners.push((listener.name.clone(), bound));
+    }
+    let mut accept_loops = tokio::task::JoinSet::new();
+    for (listener_name, listener) in listeners {
+        accept_loops.spawn(accept_connections(
+            listener_name,
+            listener,
+            cluster_metadata.clone(),
+            groups.clone(),
+            transactions.clone(),
+            fetch_sessions.clone(),
+        ));
+    }
+    while let Some(result) = accept_loops.join_next().await {
+        result??;
+    }
+    Ok(())
+}
+
+async fn accept_connections(
+    listener_name: String,
+    listener: TcpListener,
+    cluster_metadata: Arc<SharedImage>,
+    groups: Arc<GroupCoordinator>,
+    transactions: Arc<TransactionCoordinator>,
+    fetch_sessions: Arc<FetchSessionCache>,
+) -> tokio::io::Result<()> {
     loop {
         let (stream, _) = listener.accept().await?;
         let cloned = cluster_metadata.clone();
+        let groups = groups.clone();
+        let transactions = transactions.clone();
+        let fetch_sessions = fetch_sessions.clone();
+        let listener_name = listener_name.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream, &cloned).await {
+            let handled = handle_connection(
+                stream,
+                &listener_name,
+                &cloned,
+                &groups,
+                &transactions,
+                &fetch_sessions,
+            )
+            .await;
+            if let Err(e) = handled {
                 eprintln!("Error handling client: {}", e);
             }
         });
     }
 }
 
+// `listener_name` is the listener the connection came in on, it picks the
+// address clients are told to use
 async fn handle_connection(
     mut stream: TcpStream,
-    cluster_metadata: &Cluster,
+    listener_name: &str,
+    shared_metadata: &SharedImage,
+    groups: &GroupCoordinator,
+    transactions: &TransactionCoordinator,
+    fetch_sessions: &FetchSessionCache,
 ) -> tokio::io::Result<()> {
+    let mut frames = FrameReader::new(config::get().socket_request_max_bytes);
     loop {
-        let request = match Request::new(&mut stream).await {
+        let request = match Request::new(&mut stream, &mut frames).await {
             Ok(r) => r,
             Err(RequestError::ClientDisconnected) => {
                 break;
             }
-            Err(RequestError::IoError(err)) => {
+            Err(err) => {
+                eprintln!("Closing connection: {}", err);
                 break;
             }
         };
         request.log();
 
         let mut response = Response::build_from_request(&request);
+        // one image for the whole request, even if the log moves on meanwhile
+        let cluster_metadata = shared_metadata.snapshot();
 
         match request.request_api_key {
+            0 => {
+                handler::produce::handle(&request, &mut response, &cluster_metadata, transactions)
+                    .await
+                    .unwrap();
+            }
             1 => {
-                handler::fetch::handle(&request, &mut response, cluster_metadata)
+                handler::fetch::handle(&request, &mut response, &cluster_metadata, fetch_sessions)
+                    .await
+                    .unwrap();
+            }
+            2 => {
+                handler::list_offsets::handle(&request, &mut response, &cluster_metadata)
+                    .await
+                    .unwrap();
+            }
+            3 => {
+                handler::metadata::handle(
+                    &request,
+                    &mut response,
+                    &cluster_metadata,
+                    listener_name,
+                )
+                .await
+                .unwrap();
+            }
+            8 => {
+                handler::offset_commit::handle(&request, &mut response, &cluster_metadata, groups)
+                    .await
+                    .unwrap();
+            }
+            9 => {
+                handler::offset_fetch::handle(&request, &mut response, groups)
+                    .await
+                    .unwrap();
+            }
+            10 => {
+                handler::find_coordinator::handle(&request, &mut response, listener_name)
+                    .await
+                    .unwrap();
+            }
+            11 => {
+                handler::join_group::handle(&request, &mut response, groups)
+                    .await
+                    .unwrap();
+            }
+            12 => {
+                handler::heartbeat::handle(&request, &mut response, groups)
+                    .await
+                    .unwrap();
+            }
+            13 => {
+                handler::leave_group::handle(&request, &mut response, groups)
+                    .await
+                    .unwrap();
+            }
+            14 => {
+                handler::sync_group::handle(&request, &mut response, groups)
                     .await
                     .unwrap();
             }
             18 => {
                 handler::api_version::handle(&request, &mut response);
             }
+            19 => {
+                handler::create_topics::handle(&request, &mut response, shared_metadata)
+                    .await
+                    .unwrap();
+            }
+            20 => {
+                handler::delete_topics::handle(&request, &mut response, shared_metadata)
+                    .await
+                    .unwrap();
+            }
+            22 => {
+                handler::init_producer_id::handle(&request, &mut response, transactions)
+                    .await
+                    .unwrap();
+            }
+            24 => {
+                handler::add_partitions_to_txn::handle(
+                    &request,
+                    &mut response,
+                    &cluster_metadata,
+                    transactions,
+                )
+                .await
+                .unwrap();
+            }
+            25 => {
+                handler::add_offsets_to_txn::handle(&request, &mut response, transactions)
+                    .await
+                    .unwrap();
+            }
+            26 => {
+                handler::end_txn::handle(&request, &mut response, transactions)
+                    .await
+                    .unwrap();
+            }
+            27 => {
+                handler::write_txn_markers::handle(&request, &mut response, transactions)
+                    .await
+                    .unwrap();
+            }
+            28 => {
+                handler::txn_offset_commit::handle(
+                    &request,
+                    &mut response,
+                    &cluster_metadata,
+                    groups,
+                    transactions,
+                )
+                .await
+                .unwrap();
+            }
+            37 => {
+                handler::create_partitions::handle(&request, &mut response, shared_metadata)
+                    .await
+                    .unwrap();
+            }
             75 => {
                 handler::describe_topic_partitions::handle(
                     &request,
                     &mut response,
-                    cluster_metadata,
+                    &cluster_metadata,
                 )
                 .await
                 .unwrap();
@@ -1,7 +1,16 @@
-use crate::custom_trait::cursor::{AsyncReadVarint, ReadUUID};
-use std::{any, fs::File, io::Cursor, path::Path};
+use crate::custom_trait::{
+    cursor::{AsyncReadVarint, ReadUUID},
+    wire::ReadWire,
+};
+use std::{
+    any,
+    fs::File,
+    io::Cursor,
+    path::{Path, PathBuf},
+};
 
 use crate::protocol::response;
+use crate::storage::{self, batch, compression::CompressionType, log::Log};
 use anyhow::Ok;
 use bytes::{Buf, BufMut};
 use tokio::io::AsyncReadExt;
@@ -11,61 +20,132 @@ pub type Cluster = Vec<Batch>;
 #[derive(Clone, Debug)]
 pub struct Batch {
     pub batch_offset: u64,
-    pub batch_length: u32,
     pub partition_leader_epoch: u32,
-    pub magic_byte: u8,
-    pub crc: u32,
-    pub attributes: u16,
     pub last_offset_delta: u32,
-    pub base_timestamp: u64,
-    pub max_timestamp: u64,
-    pub producer_id: i64,
-    pub producer_epoch: i16,
-    pub base_sequence: i32,
-    pub record_batch_length: u32,
     pub records: Vec<Record>,
 }
 #[derive(Clone, Debug)]
 pub struct Record {
-    pub record_length: i64,
-    pub attributes: u8,
-    pub timestamp_delta: i64,
-    pub offset_delta: i64,
-    pub key_length: i64,
-    pub key: Option<Vec<u8>>,
-    pub value_length: i64,
-    pub value: Value,
-}
-
-#[derive(Clone, Debug)]
-pub struct Value {
-    pub frame_version: u8,
-    pub type_: u8,
-    pub version: u8,
     pub value: ValueRecord,
-    pub tagged_fields: u64,
 }
+
+/// A KRaft metadata record, keyed by its record type.
+// records are decoded in full to document their layout, the image does not
+// apply every field
+#[allow(dead_code)]
 #[derive(Clone, Debug)]
 pub enum ValueRecord {
-    FeatureValue(FeatureValueRecord),
-    TopicValue(TopicValueRecord),
-    PartitionValue(PartitionValueRecord),
+    RegisterBrokerValue(RegisterBrokerValueRecord), // 0
+    UnregisterBrokerValue(UnregisterBrokerValueRecord), // 1
+    TopicValue(TopicValueRecord),                   // 2
+    PartitionValue(PartitionValueRecord),           // 3
+    ConfigValue(ConfigValueRecord),                 // 4
+    PartitionChangeValue(PartitionChangeValueRecord), // 5
+    AccessControlEntryValue(AccessControlEntryValueRecord), // 6
+    RemoveAccessControlEntryValue(RemoveAccessControlEntryValueRecord), // 7
+    FenceBrokerValue(BrokerEpochValueRecord),       // 8
+    UnfenceBrokerValue(BrokerEpochValueRecord),     // 9
+    RemoveTopicValue(RemoveTopicValueRecord),       // 10
+    UserScramCredentialValue(UserScramCredentialValueRecord), // 11
+    FeatureValue(FeatureValueRecord),               // 12
+    ClientQuotaValue(ClientQuotaValueRecord),       // 14
+    ProducerIdsValue(ProducerIdsValueRecord),       // 15
+    BrokerRegistrationChangeValue(BrokerRegistrationChangeValueRecord), // 17
+    NoOpValue,                                      // 20
+    RemoveUserScramCredentialValue(RemoveUserScramCredentialValueRecord), // 22
+    BeginTransactionValue(BeginTransactionValueRecord), // 23
+    EndTransactionValue,                            // 24
+    // a record of a control batch
+    Control(ControlRecord),
     Unknown,
 }
+
+/// A control record, keyed by the type in the record key rather than the
+/// value.
+// decoded in full to document the layout of each control record
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub enum ControlRecord {
+    LeaderChange { leader_id: i32 },                      // 2
+    SnapshotHeader { last_contained_log_timestamp: i64 }, // 3
+    SnapshotFooter,                                       // 4
+    Other(i16),
+}
+
+// every field is decoded to follow the record layout, the image keeps only
+// what Metadata and DescribeCluster answer with
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct RegisterBrokerValueRecord {
+    pub broker_id: i32,
+    pub is_migrating_zk_broker: bool,
+    pub incarnation_id: uuid::Uuid,
+    pub broker_epoch: i64,
+    pub endpoints: Vec<BrokerEndpoint>,
+    pub features: Vec<BrokerFeature>,
+    pub rack: Option<String>,
+    pub fenced: bool,
+    pub in_controlled_shutdown: bool,
+    pub log_dirs: Vec<uuid::Uuid>,
+}
+
+// security_protocol is part of the layout, every listener is plaintext
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct BrokerEndpoint {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    pub security_protocol: i16,
+}
+
+// part of the RegisterBrokerRecord layout, features are not negotiated
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct BrokerFeature {
+    pub name: String,
+    pub min_supported_version: i16,
+    pub max_supported_version: i16,
+}
+
+#[derive(Clone, Debug)]
+pub struct UnregisterBrokerValueRecord {
+    pub broker_id: i32,
+}
+
+/// Body of FenceBrokerRecord and UnfenceBrokerRecord.
+#[derive(Clone, Debug)]
+pub struct BrokerEpochValueRecord {
+    pub id: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct BrokerRegistrationChangeValueRecord {
+    pub broker_id: i32,
+    // 1 fences, -1 unfences, 0 leaves it as is
+    pub fenced: i8,
+    // 1 means the broker entered controlled shutdown, 0 no change
+    pub in_controlled_shutdown: i8,
+    pub log_dirs: Option<Vec<uuid::Uuid>>,
+}
+
 #[derive(Clone, Debug)]
 pub struct FeatureValueRecord {
-    pub name_length: i64,
     pub name: String,
     pub feature_level: i16,
 }
 
 #[derive(Clone, Debug)]
 pub struct TopicValueRecord {
-    pub name_length: u64,
     pub name: String,
     pub uuid: uuid::Uuid,
 }
 
+#[derive(Clone, Debug)]
+pub struct RemoveTopicValueRecord {
+    pub topic_uuid: uuid::Uuid,
+}
+
 #[derive(Clone, Debug)]
 pub struct PartitionValueRecord {
     pub id: u32,
@@ -74,77 +154,325 @@ pub struct PartitionValueRecord {
     pub leader_epoch: u32,
     pub replica_nodes: Vec<u32>,
     pub in_sync_replica_nodes: Vec<u32>,
+    pub removing_replica_nodes: Vec<u32>,
+    pub adding_replica_nodes: Vec<u32>,
+    pub leader_recovery_state: i8,
+    pub partition_epoch: i32,
+    pub directories: Vec<uuid::Uuid>,
 }
 
-pub async fn parse_metadata_cluster() -> anyhow::Result<Cluster> {
-    let path = Path::new("/tmp/kraft-combined-logs/__cluster_metadata-0/00000000000000000000.log");
-    let content = tokio::fs::read(path).await?;
-    let mut cursor = Cursor::new(&content);
+/// Changes to a partition; fields that are `None` keep their value.
+#[derive(Clone, Debug)]
+pub struct PartitionChangeValueRecord {
+    pub id: u32,
+    pub topic_uuid: uuid::Uuid,
+    pub in_sync_replica_nodes: Option<Vec<u32>>,
+    // -1 means no leader
+    pub leader_id: Option<i32>,
+    pub replica_nodes: Option<Vec<u32>>,
+    pub removing_replica_nodes: Option<Vec<u32>>,
+    pub adding_replica_nodes: Option<Vec<u32>>,
+    pub leader_recovery_state: Option<i8>,
+    pub directories: Option<Vec<uuid::Uuid>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProducerIdsValueRecord {
+    pub broker_id: i32,
+    pub broker_epoch: i64,
+    pub next_producer_id: i64,
+}
 
+// ACLs are not enforced, the record is decoded to document its layout
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct AccessControlEntryValueRecord {
+    pub id: uuid::Uuid,
+    pub resource_type: i8,
+    pub resource_name: String,
+    pub pattern_type: i8,
+    pub principal: String,
+    pub host: String,
+    pub operation: i8,
+    pub permission_type: i8,
+}
+
+// ACLs are not enforced, the record is decoded to document its layout
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct RemoveAccessControlEntryValueRecord {
+    pub id: uuid::Uuid,
+}
+
+// quotas are not enforced, the record is decoded to document its layout
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct ClientQuotaValueRecord {
+    // (entity type, entity name), a None name is the default entity
+    pub entity: Vec<(String, Option<String>)>,
+    pub key: String,
+    pub value: f64,
+    pub remove: bool,
+}
+
+// SASL is not supported, the record is decoded to document its layout
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct UserScramCredentialValueRecord {
+    pub name: String,
+    pub mechanism: i8,
+    pub salt: Vec<u8>,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+    pub iterations: i32,
+}
+
+// SASL is not supported, the record is decoded to document its layout
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct RemoveUserScramCredentialValueRecord {
+    pub name: String,
+    pub mechanism: i8,
+}
+
+#[derive(Clone, Debug)]
+pub struct BeginTransactionValueRecord {
+    pub name: Option<String>,
+}
+
+/// What to do with a metadata log batch whose CRC does not match.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CorruptBatchPolicy {
+    /// Leave the batch out and keep loading the ones after it
+    Skip,
+    /// Load nothing from the corrupt batch on, like Kafka's log recovery
+    #[default]
+    Stop,
+}
+
+impl std::str::FromStr for CorruptBatchPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(CorruptBatchPolicy::Skip),
+            "stop" => Ok(CorruptBatchPolicy::Stop),
+            _ => Err(anyhow::anyhow!("expected skip or stop, got {s}")),
+        }
+    }
+}
+
+// Resource type of topic configs in a ConfigRecord
+pub static TOPIC_RESOURCE_TYPE: i8 = 2;
+
+#[derive(Clone, Debug)]
+pub struct ConfigValueRecord {
+    pub resource_type: i8,
+    pub resource_name: String,
+    pub name: String,
+    // None removes the config
+    pub value: Option<String>,
+}
+
+static SNAPSHOT_SUFFIX: &str = ".checkpoint";
+
+/// Metadata batches read from the log, and the offset the next read
+/// continues from.
+#[derive(Debug)]
+pub struct MetadataRead {
+    pub batches: Cluster,
+    pub next_offset: i64,
+    // base offset of the corrupt batch that stopped the read under
+    // `CorruptBatchPolicy::Stop`
+    pub stopped_at: Option<i64>,
+}
+
+/// Loads the latest snapshot, if there is one, then the log batches after
+/// it. Without a snapshot the whole log is read.
+pub async fn parse_metadata_cluster(policy: CorruptBatchPolicy) -> anyhow::Result<MetadataRead> {
+    let log = storage::open_metadata_log().await?;
+    let (mut cluster, start_offset) = match load_latest_snapshot(&log.dir).await? {
+        Some(snapshot) => (snapshot.batches, snapshot.next_offset),
+        None => (Vec::new(), log.log_start_offset().await?),
+    };
+    let read = read_metadata_batches(&log, start_offset, policy).await?;
+    cluster.extend(read.batches);
+    Ok(MetadataRead {
+        batches: cluster,
+        next_offset: read.next_offset,
+        stopped_at: read.stopped_at,
+    })
+}
+
+// `<end offset>-<epoch>.checkpoint`, where the end offset is the first log
+// offset the snapshot does not contain
+fn parse_snapshot_name(path: &Path) -> Option<(i64, i32)> {
+    let name = path.file_name()?.to_str()?.strip_suffix(SNAPSHOT_SUFFIX)?;
+    let (offset, epoch) = name.split_once('-')?;
+    Some((offset.parse().ok()?, epoch.parse().ok()?))
+}
+
+// Loads the newest complete snapshot in `dir`. Its batches carry their own
+// offsets starting at 0; `next_offset` is where the log takes over.
+async fn load_latest_snapshot(dir: &Path) -> anyhow::Result<Option<MetadataRead>> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        entries => entries?,
+    };
+    let mut snapshots: Vec<((i64, i32), PathBuf)> = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if let Some(id) = parse_snapshot_name(&path) {
+            snapshots.push((id, path));
+        }
+    }
+    snapshots.sort_by_key(|(id, _)| *id);
+
+    for ((end_offset, _), path) in snapshots.into_iter().rev() {
+        let content = tokio::fs::read(&path).await?;
+        let mut batches = Vec::new();
+        let mut snapshot_offset = 0;
+        // a corrupt or truncated snapshot is not used at all
+        let parsed = parse_batches(
+            &content,
+            CorruptBatchPolicy::Stop,
+            &mut batches,
+            &mut snapshot_offset,
+        )
+        .await
+        .is_ok_and(|stopped_at| stopped_at.is_none());
+        if !parsed || !is_complete_snapshot(&batches) {
+            println!("skipping incomplete snapshot {}", path.display());
+            continue;
+        }
+        return Ok(Some(MetadataRead {
+            batches,
+            next_offset: end_offset,
+            stopped_at: None,
+        }));
+    }
+    Ok(None)
+}
+
+// A snapshot opens with a header control record and ends with a footer one
+fn is_complete_snapshot(batches: &Cluster) -> bool {
+    let control = |record: Option<&Record>| match record.map(|r| &r.value) {
+        Some(ValueRecord::Control(control)) => Some(control.clone()),
+        _ => None,
+    };
+    let first = batches.first().and_then(|b| b.records.first());
+    let last = batches.last().and_then(|b| b.records.last());
+    matches!(control(first), Some(ControlRecord::SnapshotHeader { .. }))
+        && matches!(control(last), Some(ControlRecord::SnapshotFooter))
+}
+
+/// Reads the batches appended to the metadata log at or after
+/// `next_offset`, across segments.
+pub async fn read_metadata_from(
+    next_offset: i64,
+    policy: CorruptBatchPolicy,
+) -> anyhow::Result<MetadataRead> {
+    let log = storage::open_metadata_log().await?;
+    read_metadata_batches(&log, next_offset, policy).await
+}
+
+async fn read_metadata_batches(
+    log: &Log,
+    mut next_offset: i64,
+    policy: CorruptBatchPolicy,
+) -> anyhow::Result<MetadataRead> {
     let mut cluster: Vec<Batch> = Vec::new();
+    let mut stopped_at = None;
+    while stopped_at.is_none() {
+        // a read stops at the end of a segment, so keep going until nothing is left
+        let read = log.read(next_offset, usize::MAX, true).await?;
+        let Some(content) = read.records.filter(|records| !records.is_empty()) else {
+            break;
+        };
+        stopped_at = parse_batches(&content, policy, &mut cluster, &mut next_offset).await?;
+    }
+    Ok(MetadataRead {
+        batches: cluster,
+        next_offset,
+        stopped_at,
+    })
+}
+
+// Parses the batches in `content`, moving `next_offset` past each one.
+// Returns the base offset of the corrupt batch that stopped the read, if
+// one did; reporting it is left to the caller.
+async fn parse_batches(
+    content: &Vec<u8>,
+    policy: CorruptBatchPolicy,
+    cluster: &mut Cluster,
+    next_offset: &mut i64,
+) -> anyhow::Result<Option<i64>> {
+    let mut cursor = Cursor::new(content);
     while cursor.has_remaining() {
+        let position = cursor.position() as usize;
         let base_offset = cursor.read_u64().await?;
         let batch_length = cursor.read_u32().await?;
 
         let mut single_batch_buf = vec![0u8; batch_length as usize];
         cursor.read_exact(&mut single_batch_buf).await?;
-        let mut single_batch_cursor = Cursor::new(&single_batch_buf);
-        let batch = parse_single_batch(&mut single_batch_cursor, base_offset, batch_length).await?;
+
+        let batch_bytes = &content[position..cursor.position() as usize];
+        let header = batch::BatchHeader::parse(batch_bytes)?;
+        if let Err(e) = batch::verify_crc(batch_bytes, &header) {
+            match policy {
+                CorruptBatchPolicy::Skip => {
+                    println!(
+                        "skipping corrupt metadata batch at offset {}: {}",
+                        base_offset, e
+                    );
+                    *next_offset = header.next_offset();
+                    continue;
+                }
+                CorruptBatchPolicy::Stop => return Ok(Some(base_offset as i64)),
+            }
+        }
+
+        let records = &single_batch_buf[batch::BATCH_HEADER_SIZE - batch::BATCH_LENGTH_OFFSET..];
+        let batch = parse_single_batch(records, &header).await?;
 
         cluster.push(batch);
+        *next_offset = header.next_offset();
     }
-    Ok(cluster)
+    Ok(None)
 }
 
-async fn parse_single_batch(
-    cursor: &mut Cursor<&Vec<u8>>,
-    batch_offset: u64,
-    batch_length: u32,
-) -> anyhow::Result<Batch> {
-    let partition_leader_epoch = cursor.read_u32().await?;
-    let magic_byte = cursor.read_u8().await?;
-    let crc = cursor.read_u32().await?;
-    let attributes = cursor.read_u16().await?;
-    let last_offset_delta = cursor.read_u32().await?;
-    let base_timestamp = cursor.read_u64().await?;
-    let max_timestamp = cursor.read_u64().await?;
-    let producer_id = cursor.read_i64().await?;
-    let producer_epoch = cursor.read_i16().await?;
-    let base_sequence = cursor.read_i32().await?;
-    let record_batch_length = cursor.read_u32().await?;
+// Decodes the records that follow `header`
+async fn parse_single_batch(records: &[u8], header: &batch::BatchHeader) -> anyhow::Result<Batch> {
+    // the records of a compressed batch are one compressed blob
+    let compression = header.compression()?;
+    let records_buf = compression.decompress(records)?;
+    let mut records_cursor = Cursor::new(&records_buf);
+    let control = header.is_control();
 
     let mut records: Vec<Record> = Vec::new();
 
-    for _ in 0..record_batch_length {
-        let record_length = cursor.async_read_varint().await?; // 1 byte
+    for _ in 0..header.records_count {
+        let record_length = records_cursor.async_read_varint().await?; // 1 byte
         let mut record_buf = vec![0u8; record_length as usize];
-        cursor.read_exact(&mut record_buf).await?;
+        records_cursor.read_exact(&mut record_buf).await?;
         let mut record_cursor = Cursor::new(&record_buf);
-        let record = parse_record(&mut record_cursor, record_length).await?;
+        let record = parse_record(&mut record_cursor, control).await?;
         records.push(record);
     }
     Ok(Batch {
-        partition_leader_epoch,
-        magic_byte,
-        crc,
-        attributes,
-        last_offset_delta,
-        base_timestamp,
-        max_timestamp,
-        producer_id,
-        producer_epoch,
-        base_sequence,
-        record_batch_length,
-        batch_length,
-        batch_offset,
+        batch_offset: header.base_offset as u64,
+        partition_leader_epoch: header.partition_leader_epoch as u32,
+        last_offset_delta: header.last_offset_delta as u32,
         records,
     })
 }
 
-async fn parse_record(cursor: &mut Cursor<&Vec<u8>>, record_length: i64) -> anyhow::Result<Record> {
-    let attributes = cursor.read_u8().await?;
-    let timestamp_delta = cursor.async_read_varint().await?;
-    let offset_delta = cursor.async_read_varint().await?;
+async fn parse_record(cursor: &mut Cursor<&Vec<u8>>, control: bool) -> anyhow::Result<Record> {
+    // metadata records are applied in offset order, their attributes,
+    // timestamp and offset deltas do not matter
+    let _attributes = cursor.read_u8().await?;
+    let _timestamp_delta = cursor.async_read_varint().await?;
+    let _offset_delta = cursor.async_read_varint().await?;
     let key_length = cursor.async_read_varint().await?;
     let key = match key_length {
         -1 => None,
@@ -162,40 +490,193 @@ async fn parse_record(cursor: &mut Cursor<&Vec<u8>>, record_length: i64) -> anyh
 
     let mut value_cursor = Cursor::new(&value_buf);
 
-    let value = parse_value(&mut value_cursor).await?;
+    let value = match &key {
+        Some(key) if control => parse_control_value(key, &mut value_cursor).await?,
+        _ => parse_value(&mut value_cursor).await?,
+    };
 
     let header_array_count = cursor.async_read_uvarint().await?;
     if header_array_count > 0 {
         cursor.advance(header_array_count as usize);
     }
-    Ok(Record {
-        record_length,
-        attributes,
-        timestamp_delta,
-        offset_delta,
-        key_length,
-        key,
-        value_length,
-        value,
-    })
+    Ok(Record { value })
 }
 
-async fn parse_value(cursor: &mut Cursor<&Vec<u8>>) -> anyhow::Result<Value> {
-    let frame_version = cursor.read_u8().await?;
+// The key of a control record holds its version and type
+async fn parse_control_value(
+    key: &[u8],
+    cursor: &mut Cursor<&Vec<u8>>,
+) -> anyhow::Result<ValueRecord> {
+    let mut key = key;
+    if key.remaining() < 4 {
+        anyhow::bail!("control record key too short");
+    }
+    let _key_version = key.get_i16();
+    let type_ = key.get_i16();
+    let _value_version = cursor.read_i16().await?;
+    let control = match type_ {
+        2 => ControlRecord::LeaderChange {
+            leader_id: cursor.read_i32().await?,
+        },
+        3 => ControlRecord::SnapshotHeader {
+            last_contained_log_timestamp: cursor.read_i64().await?,
+        },
+        4 => ControlRecord::SnapshotFooter,
+        _ => ControlRecord::Other(type_),
+    };
+    Ok(ValueRecord::Control(control))
+}
+
+pub(super) async fn parse_value(cursor: &mut Cursor<&Vec<u8>>) -> anyhow::Result<ValueRecord> {
+    let _frame_version = cursor.read_u8().await?;
     let type_ = cursor.read_u8().await?;
     let version = cursor.read_u8().await?;
-    let value: ValueRecord = match type_ {
+    let mut value: ValueRecord = match type_ {
+        0 => ValueRecord::RegisterBrokerValue(parse_register_broker_record(cursor, version).await?),
+        1 => ValueRecord::UnregisterBrokerValue(UnregisterBrokerValueRecord {
+            broker_id: read_broker_id(cursor).await?,
+        }),
         2 => ValueRecord::TopicValue(parse_topic_record(cursor).await?),
-        3 => ValueRecord::PartitionValue(parse_partition_record(cursor).await?),
+        3 => ValueRecord::PartitionValue(parse_partition_record(cursor, version).await?),
+        4 => ValueRecord::ConfigValue(parse_config_record(cursor).await?),
+        5 => ValueRecord::PartitionChangeValue(PartitionChangeValueRecord {
+            id: cursor.read_u32().await?,
+            topic_uuid: cursor.read_uuid().await?,
+            in_sync_replica_nodes: None,
+            leader_id: None,
+            replica_nodes: None,
+            removing_replica_nodes: None,
+            adding_replica_nodes: None,
+            leader_recovery_state: None,
+            directories: None,
+        }),
+        6 => ValueRecord::AccessControlEntryValue(parse_access_control_entry_record(cursor).await?),
+        7 => ValueRecord::RemoveAccessControlEntryValue(RemoveAccessControlEntryValueRecord {
+            id: cursor.read_uuid().await?,
+        }),
+        8 | 9 => {
+            let record = BrokerEpochValueRecord {
+                id: read_broker_id(cursor).await?,
+            };
+            if type_ == 8 {
+                ValueRecord::FenceBrokerValue(record)
+            } else {
+                ValueRecord::UnfenceBrokerValue(record)
+            }
+        }
+        10 => ValueRecord::RemoveTopicValue(RemoveTopicValueRecord {
+            topic_uuid: cursor.read_uuid().await?,
+        }),
+        11 => {
+            ValueRecord::UserScramCredentialValue(parse_user_scram_credential_record(cursor).await?)
+        }
+        12 => ValueRecord::FeatureValue(parse_feature_level_record(cursor).await?),
+        14 => ValueRecord::ClientQuotaValue(parse_client_quota_record(cursor).await?),
+        15 => ValueRecord::ProducerIdsValue(ProducerIdsValueRecord {
+            broker_id: cursor.read_i32().await?,
+            broker_epoch: cursor.read_i64().await?,
+            next_producer_id: cursor.read_i64().await?,
+        }),
+        17 => ValueRecord::BrokerRegistrationChangeValue(BrokerRegistrationChangeValueRecord {
+            broker_id: read_broker_id(cursor).await?,
+            fenced: 0,
+            in_controlled_shutdown: 0,
+            log_dirs: None,
+        }),
+        20 => ValueRecord::NoOpValue,
+        22 => ValueRecord::RemoveUserScramCredentialValue(RemoveUserScramCredentialValueRecord {
+            name: cursor.read_string(true).await?,
+            mechanism: cursor.read_i8().await?,
+        }),
+        // every field is a tagged field
+        23 => ValueRecord::BeginTransactionValue(BeginTransactionValueRecord { name: None }),
+        24 => ValueRecord::EndTransactionValue,
         _ => ValueRecord::Unknown,
     };
-    let tagged_fields = cursor.async_read_uvarint().await?;
-    Ok(Value {
-        frame_version,
-        tagged_fields,
-        type_,
-        value,
-        version,
+    if matches!(value, ValueRecord::Unknown) {
+        // the layout of unknown records is unknown, so are their tags
+        return Ok(value);
+    }
+
+    for (tag, data) in cursor.read_tagged_fields().await? {
+        let mut tag_cursor = Cursor::new(&data);
+        // tags this broker does not know come from newer record versions
+        // and are skipped
+        match &mut value {
+            ValueRecord::PartitionValue(record) => {
+                read_partition_tag(record, tag, &mut tag_cursor).await?
+            }
+            ValueRecord::PartitionChangeValue(record) => {
+                read_partition_change_tag(record, tag, &mut tag_cursor).await?
+            }
+            ValueRecord::BrokerRegistrationChangeValue(record) => {
+                read_broker_registration_change_tag(record, tag, &mut tag_cursor).await?
+            }
+            ValueRecord::BeginTransactionValue(record) => {
+                read_begin_transaction_tag(record, tag, &mut tag_cursor).await?
+            }
+            _ => {}
+        }
+    }
+    Ok(value)
+}
+
+async fn parse_register_broker_record(
+    cursor: &mut Cursor<&Vec<u8>>,
+    version: u8,
+) -> anyhow::Result<RegisterBrokerValueRecord> {
+    let broker_id = cursor.read_i32().await?;
+    let is_migrating_zk_broker = if version >= 2 {
+        cursor.read_u8().await? != 0
+    } else {
+        false
+    };
+    let incarnation_id = cursor.read_uuid().await?;
+    let broker_epoch = cursor.read_i64().await?;
+
+    let mut endpoints = Vec::new();
+    for _ in 0..cursor.read_array_length(true).await? {
+        endpoints.push(BrokerEndpoint {
+            name: cursor.read_string(true).await?,
+            host: cursor.read_string(true).await?,
+            port: cursor.read_u16().await?,
+            security_protocol: cursor.read_i16().await?,
+        });
+        cursor.skip_tagged_fields(true).await?;
+    }
+    let mut features = Vec::new();
+    for _ in 0..cursor.read_array_length(true).await? {
+        features.push(BrokerFeature {
+            name: cursor.read_string(true).await?,
+            min_supported_version: cursor.read_i16().await?,
+            max_supported_version: cursor.read_i16().await?,
+        });
+        cursor.skip_tagged_fields(true).await?;
+    }
+
+    let rack = cursor.read_nullable_string(true).await?;
+    let fenced = cursor.read_u8().await? != 0;
+    let in_controlled_shutdown = if version >= 1 {
+        cursor.read_u8().await? != 0
+    } else {
+        false
+    };
+    let log_dirs = if version >= 3 {
+        read_uuid_array(cursor).await?
+    } else {
+        vec![]
+    };
+    Ok(RegisterBrokerValueRecord {
+        broker_id,
+        is_migrating_zk_broker,
+        incarnation_id,
+        broker_epoch,
+        endpoints,
+        features,
+        rack,
+        fenced,
+        in_controlled_shutdown,
+        log_dirs,
     })
 }
 
@@ -210,7 +691,6 @@ async fn parse_topic_record(cursor: &mut Cursor<&Vec<u8>>) -> anyhow::Result<Top
     };
     let topic_uuid = cursor.read_uuid().await?;
     Ok(TopicValueRecord {
-        name_length: topic_name_length,
         name: topic_name,
         uuid: topic_uuid,
     })
@@ -218,35 +698,22 @@ async fn parse_topic_record(cursor: &mut Cursor<&Vec<u8>>) -> anyhow::Result<Top
 
 async fn parse_partition_record(
     cursor: &mut Cursor<&Vec<u8>>,
+    version: u8,
 ) -> anyhow::Result<PartitionValueRecord> {
     let partition_id = cursor.read_u32().await?;
     let topic_uuid = cursor.read_uuid().await?;
-
-    let mut replica_nodes = vec![];
-    let replica_array_length = cursor.async_read_uvarint().await?;
-    for _ in 0..replica_array_length - 1 {
-        replica_nodes.push(cursor.read_u32().await?);
-    }
-    let mut in_sync_replica_nodes = vec![];
-    let in_sync_replica_array_length = cursor.async_read_uvarint().await?;
-    for _ in 0..in_sync_replica_array_length - 1 {
-        in_sync_replica_nodes.push(cursor.read_u32().await?);
-    }
-    let removing_replica_array_length = cursor.async_read_uvarint().await?;
-    for _ in 0..removing_replica_array_length - 1 {
-        let _replica_id = cursor.read_u32().await?;
-    }
-    let adding_replica_array_length = cursor.async_read_uvarint().await?;
-    for _ in 0..adding_replica_array_length - 1 {
-        let _replica_id = cursor.read_u32().await?;
-    }
+    let replica_nodes = read_node_array(cursor).await?;
+    let in_sync_replica_nodes = read_node_array(cursor).await?;
+    let removing_replica_nodes = read_node_array(cursor).await?;
+    let adding_replica_nodes = read_node_array(cursor).await?;
     let leader_id = cursor.read_u32().await?;
     let leader_epoch = cursor.read_u32().await?;
-    let _partition_epoch = cursor.read_u32().await?;
-    let directories_array_length = cursor.async_read_uvarint().await?;
-    for _ in 0..directories_array_length - 1 {
-        let _directory = cursor.read_uuid().await?;
-    }
+    let partition_epoch = cursor.read_i32().await?;
+    let directories = if version >= 1 {
+        read_uuid_array(cursor).await?
+    } else {
+        vec![]
+    };
     Ok(PartitionValueRecord {
         id: partition_id,
         topic_uuid,
@@ -254,50 +721,397 @@ async fn parse_partition_record(
         leader_epoch,
         replica_nodes,
         in_sync_replica_nodes,
+        removing_replica_nodes,
+        adding_replica_nodes,
+        leader_recovery_state: 0,
+        partition_epoch,
+        directories,
     })
 }
 
-pub trait ClusterSummary {
-    fn partitions(&self) -> Vec<&PartitionValueRecord>;
-    fn topics(&self) -> Vec<&TopicValueRecord>;
-    async fn get_partition_record_from_file(
-        &self,
-        topic_name: &str,
-        partition_index: i32,
-    ) -> anyhow::Result<Vec<u8>>;
-}
-
-impl ClusterSummary for Cluster {
-    fn partitions(&self) -> Vec<&PartitionValueRecord> {
-        self.iter()
-            .flat_map(|batch| batch.records.iter())
-            .filter_map(|record| match &record.value.value {
-                ValueRecord::PartitionValue(partition) => Some(partition),
-                _ => None,
-            })
-            .collect::<Vec<&PartitionValueRecord>>()
+// PartitionRecord keeps its leader recovery state in tag 0
+async fn read_partition_tag(
+    record: &mut PartitionValueRecord,
+    tag: u64,
+    cursor: &mut Cursor<&Vec<u8>>,
+) -> anyhow::Result<()> {
+    if tag != 0 {
+        return Ok(());
     }
+    record.leader_recovery_state = cursor.read_i8().await?;
+    Ok(())
+}
 
-    fn topics(&self) -> Vec<&TopicValueRecord> {
-        self.iter()
-            .flat_map(|batch| batch.records.iter())
-            .filter_map(|record| match &record.value.value {
-                ValueRecord::TopicValue(topic) => Some(topic),
-                _ => None,
-            })
-            .collect::<Vec<&TopicValueRecord>>()
+// Every changed field of a PartitionChangeRecord is a tagged field
+async fn read_partition_change_tag(
+    record: &mut PartitionChangeValueRecord,
+    tag: u64,
+    cursor: &mut Cursor<&Vec<u8>>,
+) -> anyhow::Result<()> {
+    match tag {
+        0 => record.in_sync_replica_nodes = Some(read_node_array(cursor).await?),
+        1 => record.leader_id = Some(cursor.read_i32().await?),
+        2 => record.replica_nodes = Some(read_node_array(cursor).await?),
+        3 => record.removing_replica_nodes = Some(read_node_array(cursor).await?),
+        4 => record.adding_replica_nodes = Some(read_node_array(cursor).await?),
+        5 => record.leader_recovery_state = Some(cursor.read_i8().await?),
+        8 => record.directories = Some(read_uuid_array(cursor).await?),
+        _ => {}
     }
+    Ok(())
+}
+
+async fn read_broker_registration_change_tag(
+    record: &mut BrokerRegistrationChangeValueRecord,
+    tag: u64,
+    cursor: &mut Cursor<&Vec<u8>>,
+) -> anyhow::Result<()> {
+    match tag {
+        0 => record.fenced = cursor.read_i8().await?,
+        1 => record.in_controlled_shutdown = cursor.read_i8().await?,
+        2 => record.log_dirs = Some(read_uuid_array(cursor).await?),
+        _ => {}
+    }
+    Ok(())
+}
+
+// BeginTransactionRecord keeps its name in tag 0
+async fn read_begin_transaction_tag(
+    record: &mut BeginTransactionValueRecord,
+    tag: u64,
+    cursor: &mut Cursor<&Vec<u8>>,
+) -> anyhow::Result<()> {
+    if tag != 0 {
+        return Ok(());
+    }
+    record.name = cursor.read_nullable_string(true).await?;
+    Ok(())
+}
 
-    async fn get_partition_record_from_file(
-        &self,
-        topic_name: &str,
-        partition_index: i32,
-    ) -> anyhow::Result<Vec<u8>> {
-        let path = format!(
-            "/tmp/kraft-combined-logs/{}-{}/00000000000000000000.log",
-            topic_name, partition_index
+async fn parse_config_record(cursor: &mut Cursor<&Vec<u8>>) -> anyhow::Result<ConfigValueRecord> {
+    let resource_type = cursor.read_i8().await?;
+    let resource_name = cursor.read_string(true).await?;
+    let name = cursor.read_string(true).await?;
+    let value = cursor.read_nullable_string(true).await?;
+    Ok(ConfigValueRecord {
+        resource_type,
+        resource_name,
+        name,
+        value,
+    })
+}
+
+async fn parse_feature_level_record(
+    cursor: &mut Cursor<&Vec<u8>>,
+) -> anyhow::Result<FeatureValueRecord> {
+    let name = cursor.read_string(true).await?;
+    let feature_level = cursor.read_i16().await?;
+    Ok(FeatureValueRecord {
+        name,
+        feature_level,
+    })
+}
+
+async fn parse_access_control_entry_record(
+    cursor: &mut Cursor<&Vec<u8>>,
+) -> anyhow::Result<AccessControlEntryValueRecord> {
+    Ok(AccessControlEntryValueRecord {
+        id: cursor.read_uuid().await?,
+        resource_type: cursor.read_i8().await?,
+        resource_name: cursor.read_string(true).await?,
+        pattern_type: cursor.read_i8().await?,
+        principal: cursor.read_string(true).await?,
+        host: cursor.read_string(true).await?,
+        operation: cursor.read_i8().await?,
+        permission_type: cursor.read_i8().await?,
+    })
+}
+
+async fn parse_client_quota_record(
+    cursor: &mut Cursor<&Vec<u8>>,
+) -> anyhow::Result<ClientQuotaValueRecord> {
+    let mut entity = Vec::new();
+    for _ in 0..cursor.read_array_length(true).await? {
+        let entity_type = cursor.read_string(true).await?;
+        let entity_name = cursor.read_nullable_string(true).await?;
+        cursor.skip_tagged_fields(true).await?;
+        entity.push((entity_type, entity_name));
+    }
+    Ok(ClientQuotaValueRecord {
+        entity,
+        key: cursor.read_string(true).await?,
+        value: cursor.read_f64().await?,
+        remove: cursor.read_u8().await? != 0,
+    })
+}
+
+async fn parse_user_scram_credential_record(
+    cursor: &mut Cursor<&Vec<u8>>,
+) -> anyhow::Result<UserScramCredentialValueRecord> {
+    Ok(UserScramCredentialValueRecord {
+        name: cursor.read_string(true).await?,
+        mechanism: cursor.read_i8().await?,
+        salt: cursor.read_nullable_bytes(true).await?.unwrap_or_default(),
+        stored_key: cursor.read_nullable_bytes(true).await?.unwrap_or_default(),
+        server_key: cursor.read_nullable_bytes(true).await?.unwrap_or_default(),
+        iterations: cursor.read_i32().await?,
+    })
+}
+
+// Reads a broker id and the broker epoch after it. Records are replayed in
+// log order, so the epoch is not checked.
+async fn read_broker_id(cursor: &mut Cursor<&Vec<u8>>) -> anyhow::Result<i32> {
+    let broker_id = cursor.read_i32().await?;
+    let _broker_epoch = cursor.read_i64().await?;
+    Ok(broker_id)
+}
+
+async fn read_node_array(cursor: &mut Cursor<&Vec<u8>>) -> anyhow::Result<Vec<u32>> {
+    let mut nodes = Vec::new();
+    for _ in 0..cursor.read_array_length(true).await? {
+        nodes.push(cursor.read_u32().await?);
+    }
+    Ok(nodes)
+}
+
+async fn read_uuid_array(cursor: &mut Cursor<&Vec<u8>>) -> anyhow::Result<Vec<uuid::Uuid>> {
+    let mut uuids = Vec::new();
+    for _ in 0..cursor.read_array_length(true).await? {
+        uuids.push(cursor.read_uuid().await?);
+    }
+    Ok(uuids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::custom_trait::{cursor::WriteVarint, wire::WriteWire};
+
+    #[test]
+    fn parses_snapshot_file_names() {
+        let name = |name: &str| parse_snapshot_name(Path::new(name));
+        assert_eq!(
+            name("00000000000000000042-0000000003.checkpoint"),
+            Some((42, 3))
         );
-        let file = tokio::fs::read(path).await?;
-        Ok(file)
+        assert_eq!(
+            name("00000000000000000042-0000000003.checkpoint.part"),
+            None
+        );
+        assert_eq!(name("00000000000000000000.log"), None);
+    }
+
+    #[tokio::test]
+    async fn decodes_tagged_partition_change_fields() -> anyhow::Result<()> {
+        let topic_uuid = uuid::Uuid::new_v4();
+        let mut data = vec![1, 5, 0]; // frame version, type, version
+        data.put_u32(3); // partition id
+        data.put_slice(topic_uuid.as_bytes());
+        data.write_uvarint(3); // tagged fields
+        data.write_uvarint(0); // isr
+        data.write_uvarint(9);
+        data.write_array_length(true, 2);
+        data.put_u32(1);
+        data.put_u32(2);
+        data.write_uvarint(1); // leader
+        data.write_uvarint(4);
+        data.put_i32(2);
+        data.write_uvarint(42); // unknown tag
+        data.write_uvarint(1);
+        data.put_u8(7);
+
+        // the unknown tag is skipped
+        let value = parse_all(&data).await?;
+        let ValueRecord::PartitionChangeValue(change) = value else {
+            panic!("expected a partition change, got {:?}", value);
+        };
+        assert_eq!(change.id, 3);
+        assert_eq!(change.topic_uuid, topic_uuid);
+        assert_eq!(change.in_sync_replica_nodes, Some(vec![1, 2]));
+        assert_eq!(change.leader_id, Some(2));
+        assert_eq!(change.replica_nodes, None);
+        Ok(())
+    }
+
+    // Parses `data` as a whole record value
+    async fn parse_all(data: &Vec<u8>) -> anyhow::Result<ValueRecord> {
+        let mut cursor = Cursor::new(data);
+        let value = parse_value(&mut cursor).await?;
+        assert_eq!(cursor.position() as usize, data.len());
+        Ok(value)
+    }
+
+    #[tokio::test]
+    async fn decodes_register_broker_records_of_each_version() -> anyhow::Result<()> {
+        let incarnation_id = uuid::Uuid::new_v4();
+        let log_dir = uuid::Uuid::new_v4();
+        for version in 0..=3 {
+            let mut data = vec![1, 0, version]; // frame version, type, version
+            data.put_i32(2); // broker_id
+            if version >= 2 {
+                data.put_u8(1); // is_migrating_zk_broker
+            }
+            data.put_slice(incarnation_id.as_bytes());
+            data.put_i64(10); // broker_epoch
+            data.write_array_length(true, 1); // endpoints
+            data.write_string(true, "PLAINTEXT");
+            data.write_string(true, "localhost");
+            data.put_u16(9092);
+            data.put_i16(0);
+            data.write_tagged_fields(true);
+            data.write_array_length(true, 1); // features
+            data.write_string(true, "metadata.version");
+            data.put_i16(1);
+            data.put_i16(20);
+            data.write_tagged_fields(true);
+            data.write_nullable_string(true, Some("rack-a")); // rack
+            data.put_u8(1); // fenced
+            if version >= 1 {
+                data.put_u8(1); // in_controlled_shutdown
+            }
+            if version >= 3 {
+                data.write_array_length(true, 1); // log_dirs
+                data.put_slice(log_dir.as_bytes());
+            }
+            data.write_tagged_fields(true);
+
+            let value = parse_all(&data).await?;
+            let ValueRecord::RegisterBrokerValue(broker) = value else {
+                panic!("expected a broker registration, got {:?}", value);
+            };
+            assert_eq!((broker.broker_id, broker.broker_epoch), (2, 10));
+            assert_eq!(broker.incarnation_id, incarnation_id);
+            assert_eq!(broker.is_migrating_zk_broker, version >= 2);
+            assert_eq!(broker.endpoints[0].host, "localhost");
+            assert_eq!(broker.endpoints[0].port, 9092);
+            assert_eq!(broker.features[0].max_supported_version, 20);
+            assert_eq!(broker.rack.as_deref(), Some("rack-a"));
+            assert!(broker.fenced);
+            assert_eq!(broker.in_controlled_shutdown, version >= 1);
+            let log_dirs = if version >= 3 { vec![log_dir] } else { vec![] };
+            assert_eq!(broker.log_dirs, log_dirs);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn decodes_topic_and_partition_records() -> anyhow::Result<()> {
+        let topic_uuid = uuid::Uuid::new_v4();
+        let mut data = vec![1, 2, 0]; // frame version, type, version
+        data.write_string(true, "orders");
+        data.put_slice(topic_uuid.as_bytes());
+        data.write_tagged_fields(true);
+        let value = parse_all(&data).await?;
+        let ValueRecord::TopicValue(topic) = value else {
+            panic!("expected a topic, got {:?}", value);
+        };
+        assert_eq!((topic.name.as_str(), topic.uuid), ("orders", topic_uuid));
+
+        let directory = uuid::Uuid::new_v4();
+        for version in 0..=1 {
+            let mut data = vec![1, 3, version]; // frame version, type, version
+            data.put_u32(4); // partition id
+            data.put_slice(topic_uuid.as_bytes());
+            for nodes in [&[1, 2][..], &[1], &[], &[3]] {
+                data.write_array_length(true, nodes.len());
+                for node in nodes {
+                    data.put_u32(*node);
+                }
+            }
+            data.put_u32(1); // leader
+            data.put_u32(6); // leader_epoch
+            data.put_i32(9); // partition_epoch
+            if version >= 1 {
+                data.write_array_length(true, 1); // directories
+                data.put_slice(directory.as_bytes());
+            }
+            data.write_uvarint(2); // tagged fields
+            data.write_uvarint(0); // leader_recovery_state
+            data.write_uvarint(1);
+            data.put_i8(1);
+            data.write_uvarint(42); // unknown tag
+            data.write_uvarint(1);
+            data.put_u8(7);
+
+            let value = parse_all(&data).await?;
+            let ValueRecord::PartitionValue(partition) = value else {
+                panic!("expected a partition, got {:?}", value);
+            };
+            assert_eq!((partition.id, partition.topic_uuid), (4, topic_uuid));
+            assert_eq!(partition.replica_nodes, vec![1, 2]);
+            assert_eq!(partition.in_sync_replica_nodes, vec![1]);
+            assert_eq!(partition.adding_replica_nodes, vec![3]);
+            assert_eq!((partition.leader_id, partition.leader_epoch), (1, 6));
+            assert_eq!(partition.partition_epoch, 9);
+            assert_eq!(partition.leader_recovery_state, 1);
+            let directories = if version >= 1 {
+                vec![directory]
+            } else {
+                vec![]
+            };
+            assert_eq!(partition.directories, directories);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn decodes_config_remove_topic_and_producer_ids_records() -> anyhow::Result<()> {
+        for value in [Some("compact"), None] {
+            let mut data = vec![1, 4, 0]; // frame version, type, version
+            data.put_i8(TOPIC_RESOURCE_TYPE);
+            data.write_string(true, "orders");
+            data.write_string(true, "cleanup.policy");
+            data.write_nullable_string(true, value);
+            data.write_tagged_fields(true);
+            let parsed = parse_all(&data).await?;
+            let ValueRecord::ConfigValue(config) = parsed else {
+                panic!("expected a config, got {:?}", parsed);
+            };
+            assert_eq!(config.resource_type, TOPIC_RESOURCE_TYPE);
+            assert_eq!(config.resource_name, "orders");
+            assert_eq!(config.name, "cleanup.policy");
+            assert_eq!(config.value.as_deref(), value);
+        }
+
+        let topic_uuid = uuid::Uuid::new_v4();
+        let mut data = vec![1, 10, 0]; // frame version, type, version
+        data.put_slice(topic_uuid.as_bytes());
+        data.write_tagged_fields(true);
+        let value = parse_all(&data).await?;
+        assert!(matches!(value, ValueRecord::RemoveTopicValue(r) if r.topic_uuid == topic_uuid));
+
+        let mut data = vec![1, 15, 0]; // frame version, type, version
+        data.put_i32(1); // broker_id
+        data.put_i64(10); // broker_epoch
+        data.put_i64(2000); // next_producer_id
+        data.write_tagged_fields(true);
+        let value = parse_all(&data).await?;
+        let ValueRecord::ProducerIdsValue(producer_ids) = value else {
+            panic!("expected producer ids, got {:?}", value);
+        };
+        assert_eq!(producer_ids.broker_id, 1);
+        assert_eq!(producer_ids.broker_epoch, 10);
+        assert_eq!(producer_ids.next_producer_id, 2000);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn decodes_begin_transaction_names_from_tag_0() -> anyhow::Result<()> {
+        let mut data = vec![1, 23, 0]; // frame version, type, version
+        data.write_uvarint(1); // tagged fields
+        data.write_uvarint(0); // name
+        data.write_uvarint(6);
+        data.write_nullable_string(true, Some("scram"));
+        let value = parse_all(&data).await?;
+        let ValueRecord::BeginTransactionValue(begin) = value else {
+            panic!("expected a transaction begin, got {:?}", value);
+        };
+        assert_eq!(begin.name.as_deref(), Some("scram"));
+
+        // the name is optional
+        let data = vec![1, 23, 0, 0];
+        let value = parse_all(&data).await?;
+        assert!(matches!(value, ValueRecord::BeginTransactionValue(b) if b.name.is_none()));
+        Ok(())
     }
 }
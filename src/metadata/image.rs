@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::cluster::{
+    Batch, MetadataRead, PartitionValueRecord, RegisterBrokerValueRecord, ValueRecord,
+    TOPIC_RESOURCE_TYPE,
+};
+
+/// The state of the cluster after replaying the metadata log in order,
+/// indexed for lookups by the handlers.
+#[derive(Clone, Debug, Default)]
+pub struct MetadataImage {
+    topics: HashMap<uuid::Uuid, TopicImage>,
+    topic_ids: HashMap<String, uuid::Uuid>,
+    brokers: BTreeMap<i32, BrokerImage>,
+    features: HashMap<String, i16>,
+    // (resource type, resource name) -> config name -> value
+    configs: HashMap<(i8, String), HashMap<String, String>>,
+    next_producer_id: i64,
+    // last metadata log offset replayed into the image
+    offset: Option<i64>,
+    // partition leader epoch of the last batch, the controller's epoch
+    leader_epoch: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct TopicImage {
+    pub name: String,
+    pub uuid: uuid::Uuid,
+    pub partitions: BTreeMap<u32, PartitionValueRecord>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BrokerImage {
+    pub registration: RegisterBrokerValueRecord,
+    pub fenced: bool,
+    pub in_controlled_shutdown: bool,
+}
+
+impl TopicImage {
+    pub fn partition(&self, partition_index: i32) -> Option<&PartitionValueRecord> {
+        u32::try_from(partition_index)
+            .ok()
+            .and_then(|index| self.partitions.get(&index))
+    }
+}
+
+impl MetadataImage {
+    pub fn from_batches(batches: &[Batch]) -> MetadataImage {
+        let mut image = MetadataImage::default();
+        for batch in batches {
+            image.apply_batch(batch);
+        }
+        image
+    }
+
+    /// Builds the image of a snapshot and the log after it. Snapshot batches
+    /// number their records from 0, so the image offset comes from where
+    /// the read ended instead.
+    pub fn from_read(read: &MetadataRead) -> MetadataImage {
+        let mut image = MetadataImage::from_batches(&read.batches);
+        if !read.batches.is_empty() {
+            image.offset = Some(read.next_offset - 1);
+        }
+        image
+    }
+
+    pub fn apply_batch(&mut self, batch: &Batch) {
+        for record in &batch.records {
+            self.replay(&record.value);
+        }
+        self.offset = Some(batch.batch_offset as i64 + batch.last_offset_delta as i64);
+        self.leader_epoch = batch.partition_leader_epoch as i32;
+    }
+
+    /// Applies one record on top of the current state.
+    pub fn replay(&mut self, record: &ValueRecord) {
+        match record {
+            ValueRecord::RegisterBrokerValue(registration) => {
+                self.brokers.insert(
+                    registration.broker_id,
+                    BrokerImage {
+                        registration: registration.clone(),
+                        fenced: registration.fenced,
+                        in_controlled_shutdown: registration.in_controlled_shutdown,
+                    },
+                );
+            }
+            ValueRecord::UnregisterBrokerValue(record) => {
+                self.brokers.remove(&record.broker_id);
+            }
+            ValueRecord::FenceBrokerValue(record) => {
+                if let Some(broker) = self.brokers.get_mut(&record.id) {
+                    broker.fenced = true;
+                }
+            }
+            ValueRecord::UnfenceBrokerValue(record) => {
+                if let Some(broker) = self.brokers.get_mut(&record.id) {
+                    broker.fenced = false;
+                }
+            }
+            ValueRecord::BrokerRegistrationChangeValue(change) => {
+                if let Some(broker) = self.brokers.get_mut(&change.broker_id) {
+                    if change.fenced != 0 {
+                        broker.fenced = change.fenced > 0;
+                    }
+                    if change.in_controlled_shutdown > 0 {
+                        broker.in_controlled_shutdown = true;
+                    }
+                    if let Some(log_dirs) = &change.log_dirs {
+                        broker.registration.log_dirs = log_dirs.clone();
+                    }
+                }
+            }
+            ValueRecord::TopicValue(topic) => {
+                self.topic_ids.insert(topic.name.clone(), topic.uuid);
+                self.topics.insert(
+                    topic.uuid,
+                    TopicImage {
+                        name: topic.name.clone(),
+                        uuid: topic.uuid,
+                        partitions: BTreeMap::new(),
+                    },
+                );
+            }
+            ValueRecord::RemoveTopicValue(removed) => {
+                if let Some(topic) = self.topics.remove(&removed.topic_uuid) {
+                    if self.topic_ids.get(&topic.name) == Some(&removed.topic_uuid) {
+                        self.topic_ids.remove(&topic.name);
+                    }
+                    // configs die with the topic
+                    self.configs.remove(&(TOPIC_RESOURCE_TYPE, topic.name));
+                }
+            }
+            ValueRecord::PartitionValue(partition) => {
+                if let Some(topic) = self.topics.get_mut(&partition.topic_uuid) {
+                    topic.partitions.insert(partition.id, partition.clone());
+                }
+            }
+            ValueRecord::PartitionChangeValue(change) => {
+                let partition = self
+                    .topics
+                    .get_mut(&change.topic_uuid)
+                    .and_then(|topic| topic.partitions.get_mut(&change.id));
+                let Some(partition) = partition else {
+                    return;
+                };
+                if let Some(isr) = &change.in_sync_replica_nodes {
+                    partition.in_sync_replica_nodes = isr.clone();
+                }
+                if let Some(leader_id) = change.leader_id {
+                    // a new leader, or losing it (-1), starts a new leader epoch
+                    partition.leader_id = leader_id as u32;
+                    partition.leader_epoch += 1;
+                }
+                if let Some(replicas) = &change.replica_nodes {
+                    partition.replica_nodes = replicas.clone();
+                }
+                if let Some(removing) = &change.removing_replica_nodes {
+                    partition.removing_replica_nodes = removing.clone();
+                }
+                if let Some(adding) = &change.adding_replica_nodes {
+                    partition.adding_replica_nodes = adding.clone();
+                }
+                if let Some(state) = change.leader_recovery_state {
+                    partition.leader_recovery_state = state;
+                }
+                if let Some(directories) = &change.directories {
+                    partition.directories = directories.clone();
+                }
+                partition.partition_epoch += 1;
+            }
+            ValueRecord::ConfigValue(config) => {
+                let key = (config.resource_type, config.resource_name.clone());
+                match &config.value {
+                    Some(value) => {
+                        self.configs
+                            .entry(key)
+                            .or_default()
+                            .insert(config.name.clone(), value.clone());
+                    }
+                    None => {
+                        if let Some(configs) = self.configs.get_mut(&key) {
+                            configs.remove(&config.name);
+                        }
+                    }
+                }
+            }
+            ValueRecord::FeatureValue(feature) => {
+                // level 0 means the feature is disabled
+                if feature.feature_level == 0 {
+                    self.features.remove(&feature.name);
+                } else {
+                    self.features
+                        .insert(feature.name.clone(), feature.feature_level);
+                }
+            }
+            ValueRecord::ProducerIdsValue(record) => {
+                self.next_producer_id = record.next_producer_id;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn topic(&self, name: &str) -> Option<&TopicImage> {
+        self.topic_ids
+            .get(name)
+            .and_then(|uuid| self.topics.get(uuid))
+    }
+
+    pub fn topic_by_id(&self, uuid: &uuid::Uuid) -> Option<&TopicImage> {
+        self.topics.get(uuid)
+    }
+
+    /// Every topic, sorted by name.
+    pub fn topics(&self) -> Vec<&TopicImage> {
+        let mut topics: Vec<&TopicImage> = self.topics.values().collect();
+        topics.sort_by(|a, b| a.name.cmp(&b.name));
+        topics
+    }
+
+    /// Registered brokers that are not fenced, by broker id.
+    pub fn live_brokers(&self) -> impl Iterator<Item = &BrokerImage> {
+        self.brokers.values().filter(|broker| !broker.fenced)
+    }
+
+    pub fn topic_config(&self, topic_name: &str, name: &str) -> Option<&str> {
+        self.configs
+            .get(&(TOPIC_RESOURCE_TYPE, topic_name.to_string()))
+            .and_then(|configs| configs.get(name))
+            .map(String::as_str)
+    }
+
+    pub fn next_producer_id(&self) -> i64 {
+        self.next_producer_id
+    }
+
+    /// Offset of the last metadata log record in the image, `None` before
+    /// any batch is applied.
+    pub fn offset(&self) -> Option<i64> {
+        self.offset
+    }
+
+    pub fn leader_epoch(&self) -> i32 {
+        self.leader_epoch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::cluster::{
+        PartitionChangeValueRecord, RemoveTopicValueRecord, TopicValueRecord,
+    };
+
+    #[test]
+    fn replays_partition_changes_and_topic_removal() {
+        let uuid = uuid::Uuid::from_u128(1);
+        let mut image = MetadataImage::default();
+        image.replay(&ValueRecord::TopicValue(TopicValueRecord {
+            name: "foo".to_string(),
+            uuid,
+        }));
+        image.replay(&ValueRecord::PartitionValue(PartitionValueRecord {
+            id: 0,
+            topic_uuid: uuid,
+            leader_id: 1,
+            leader_epoch: 0,
+            replica_nodes: vec![1, 2],
+            in_sync_replica_nodes: vec![1, 2],
+            removing_replica_nodes: vec![],
+            adding_replica_nodes: vec![],
+            leader_recovery_state: 0,
+            partition_epoch: 0,
+            directories: vec![],
+        }));
+        image.replay(&ValueRecord::PartitionChangeValue(
+            PartitionChangeValueRecord {
+                id: 0,
+                topic_uuid: uuid,
+                in_sync_replica_nodes: Some(vec![2]),
+                leader_id: Some(2),
+                replica_nodes: None,
+                removing_replica_nodes: None,
+                adding_replica_nodes: None,
+                leader_recovery_state: None,
+                directories: None,
+            },
+        ));
+
+        let partition = image.topic("foo").and_then(|t| t.partition(0)).unwrap();
+        assert_eq!(partition.leader_id, 2);
+        assert_eq!(partition.leader_epoch, 1);
+        assert_eq!(partition.partition_epoch, 1);
+        assert_eq!(partition.in_sync_replica_nodes, vec![2]);
+        assert_eq!(partition.replica_nodes, vec![1, 2]);
+
+        image.replay(&ValueRecord::RemoveTopicValue(RemoveTopicValueRecord {
+            topic_uuid: uuid,
+        }));
+        assert!(image.topic("foo").is_none());
+        assert!(image.topic_by_id(&uuid).is_none());
+    }
+}
@@ -1 +1,4 @@
 pub mod cluster;
+pub mod image;
+pub mod tailer;
+pub mod writer;
@@ -0,0 +1,99 @@
+use std::{
+    sync::{Arc, OnceLock, RwLock},
+    time::Duration,
+};
+
+use super::{
+    cluster::{self, CorruptBatchPolicy},
+    image::MetadataImage,
+};
+
+// How often the metadata log is checked for new batches
+static POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The current metadata image, shared between connections. New batches
+/// produce a new image that replaces the old one whole, so a request that
+/// took a snapshot keeps seeing the same state until it is done.
+#[derive(Debug)]
+pub struct SharedImage {
+    current: RwLock<Arc<MetadataImage>>,
+    // where the next read of the log starts, held while batches are applied
+    // so each one is applied once
+    next_offset: tokio::sync::Mutex<i64>,
+    policy: CorruptBatchPolicy,
+    // the corrupt batch the log stopped being applied at, under
+    // `CorruptBatchPolicy::Stop`; nothing after it is applied again
+    stopped_at: OnceLock<i64>,
+    // held by the broker's own metadata writes, see `super::writer`
+    pub(super) write_lock: tokio::sync::Mutex<()>,
+}
+
+impl SharedImage {
+    pub fn new(image: MetadataImage, next_offset: i64, policy: CorruptBatchPolicy) -> SharedImage {
+        SharedImage {
+            current: RwLock::new(Arc::new(image)),
+            next_offset: tokio::sync::Mutex::new(next_offset),
+            policy,
+            stopped_at: OnceLock::new(),
+            write_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<MetadataImage> {
+        self.current
+            .read()
+            .expect("metadata image lock poisoned")
+            .clone()
+    }
+
+    fn publish(&self, image: MetadataImage) {
+        *self.current.write().expect("metadata image lock poisoned") = Arc::new(image);
+    }
+
+    /// Applies the batches appended to the metadata log since the last
+    /// call, including the ones in newly rolled segments. Fails without
+    /// reading once a corrupt batch has stopped the log.
+    pub async fn catch_up(&self) -> anyhow::Result<()> {
+        let mut next_offset = self.next_offset.lock().await;
+        self.check_stopped()?;
+        let read = cluster::read_metadata_from(*next_offset, self.policy).await?;
+        *next_offset = read.next_offset;
+        if let Some(offset) = read.stopped_at {
+            let _ = self.stopped_at.set(offset);
+        }
+
+        if !read.batches.is_empty() {
+            let mut image = MetadataImage::clone(&self.snapshot());
+            for batch in &read.batches {
+                image.apply_batch(batch);
+            }
+            println!("metadata image now at offset {:?}", image.offset());
+            self.publish(image);
+        }
+        self.check_stopped()
+    }
+
+    fn check_stopped(&self) -> anyhow::Result<()> {
+        match self.stopped_at.get() {
+            Some(offset) => Err(anyhow::anyhow!(
+                "metadata log stopped at the corrupt batch at offset {offset}, \
+                 restart with --corrupt-metadata-batches=skip to load past it"
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Follows the metadata log, applying appended batches to `shared`. Gives
+/// up at a corrupt batch, since every later poll would stop there again.
+pub async fn tail(shared: Arc<SharedImage>) {
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        if let Err(e) = shared.catch_up().await {
+            println!("error tailing metadata: {}", e);
+            if shared.stopped_at.get().is_some() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::BufMut;
+use tokio::sync::MutexGuard;
+
+use crate::{
+    custom_trait::wire::WriteWire,
+    storage::{self, batch},
+};
+
+use super::{cluster::ValueRecord, image::MetadataImage, tailer::SharedImage};
+
+// Frame version every metadata record starts with
+static FRAME_VERSION: u8 = 1;
+
+/// Exclusive access to the metadata log for the broker's own changes. A
+/// handler validates against [`MetadataWriter::image`] and appends while
+/// holding the writer, so no other write lands in between.
+pub struct MetadataWriter<'a> {
+    shared: &'a SharedImage,
+    image: Arc<MetadataImage>,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl SharedImage {
+    /// Waits for other writers, then catches up with the log so the image
+    /// includes every change made so far.
+    pub async fn writer(&self) -> anyhow::Result<MetadataWriter<'_>> {
+        let guard = self.write_lock.lock().await;
+        self.catch_up().await?;
+        Ok(MetadataWriter {
+            shared: self,
+            image: self.snapshot(),
+            _guard: guard,
+        })
+    }
+}
+
+impl MetadataWriter<'_> {
+    pub fn image(&self) -> &MetadataImage {
+        &self.image
+    }
+
+    /// Appends the records to the metadata log in one batch and applies
+    /// them, so they are visible once this returns.
+    pub async fn append(&mut self, records: &[ValueRecord]) -> anyhow::Result<()> {
+        let values = records
+            .iter()
+            .map(encode_record)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let records: Vec<batch::KeyValue> = values
+            .iter()
+            .map(|value| (None, Some(value.as_slice())))
+            .collect();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let mut records = batch::build_batch(&records, now);
+        let headers = batch::read_batch_headers(&records);
+        storage::append_metadata_records(self.image.leader_epoch(), &mut records, &headers).await?;
+        self.shared.catch_up().await?;
+        self.image = self.shared.snapshot();
+        Ok(())
+    }
+}
+
+/// Encodes a record in the layout `cluster::parse_value` reads. Only the
+/// records the broker writes itself are supported.
+pub fn encode_record(record: &ValueRecord) -> anyhow::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    data.put_u8(FRAME_VERSION); // frame version
+    match record {
+        ValueRecord::TopicValue(topic) => {
+            data.put_u8(2); // type
+            data.put_u8(0); // version
+            data.write_string(true, &topic.name); // name
+            data.put_slice(topic.uuid.as_bytes()); // topic_id
+        }
+        ValueRecord::PartitionValue(partition) => {
+            data.put_u8(3); // type
+            data.put_u8(0); // version
+            data.put_u32(partition.id); // partition_id
+            data.put_slice(partition.topic_uuid.as_bytes()); // topic_id
+            for nodes in [
+                &partition.replica_nodes,
+                &partition.in_sync_replica_nodes,
+                &partition.removing_replica_nodes,
+                &partition.adding_replica_nodes,
+            ] {
+                data.write_array_length(true, nodes.len());
+                for node in nodes {
+                    data.put_u32(*node);
+                }
+            }
+            data.put_u32(partition.leader_id); // leader
+            data.put_u32(partition.leader_epoch); // leader_epoch
+            data.put_i32(partition.partition_epoch); // partition_epoch
+        }
+        ValueRecord::ConfigValue(config) => {
+            data.put_u8(4); // type
+            data.put_u8(0); // version
+            data.put_i8(config.resource_type); // resource_type
+            data.write_string(true, &config.resource_name); // resource_name
+            data.write_string(true, &config.name); // name
+            data.write_nullable_string(true, config.value.as_deref()); // value
+        }
+        ValueRecord::RemoveTopicValue(removed) => {
+            data.put_u8(10); // type
+            data.put_u8(0); // version
+            data.put_slice(removed.topic_uuid.as_bytes()); // topic_id
+        }
+        ValueRecord::ProducerIdsValue(producer_ids) => {
+            data.put_u8(15); // type
+            data.put_u8(0); // version
+            data.put_i32(producer_ids.broker_id); // broker_id
+            data.put_i64(producer_ids.broker_epoch); // broker_epoch
+            data.put_i64(producer_ids.next_producer_id); // next_producer_id
+        }
+        _ => anyhow::bail!("cannot encode metadata record {:?}", record),
+    }
+    data.write_tagged_fields(true);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::metadata::cluster::{
+        self, PartitionValueRecord, RemoveTopicValueRecord, TopicValueRecord,
+    };
+
+    #[tokio::test]
+    async fn encodes_records_the_parser_reads() -> anyhow::Result<()> {
+        let uuid = uuid::Uuid::new_v4();
+        let topic = encode_record(&ValueRecord::TopicValue(TopicValueRecord {
+            name: "foo".to_string(),
+            uuid,
+        }))?;
+        let parsed = cluster::parse_value(&mut Cursor::new(&topic)).await?;
+        let ValueRecord::TopicValue(parsed) = parsed else {
+            panic!("expected a topic, got {:?}", parsed);
+        };
+        assert_eq!((parsed.name.as_str(), parsed.uuid), ("foo", uuid));
+
+        let partition = encode_record(&ValueRecord::PartitionValue(PartitionValueRecord {
+            id: 2,
+            topic_uuid: uuid,
+            leader_id: 1,
+            leader_epoch: 0,
+            replica_nodes: vec![1, 2],
+            in_sync_replica_nodes: vec![1],
+            removing_replica_nodes: vec![],
+            adding_replica_nodes: vec![],
+            leader_recovery_state: 0,
+            partition_epoch: 0,
+            directories: vec![],
+        }))?;
+        let parsed = cluster::parse_value(&mut Cursor::new(&partition)).await?;
+        let ValueRecord::PartitionValue(parsed) = parsed else {
+            panic!("expected a partition, got {:?}", parsed);
+        };
+        assert_eq!(parsed.id, 2);
+        assert_eq!(parsed.replica_nodes, vec![1, 2]);
+        assert_eq!(parsed.in_sync_replica_nodes, vec![1]);
+        assert_eq!(parsed.leader_id, 1);
+
+        let removed = encode_record(&ValueRecord::RemoveTopicValue(RemoveTopicValueRecord {
+            topic_uuid: uuid,
+        }))?;
+        let parsed = cluster::parse_value(&mut Cursor::new(&removed)).await?;
+        assert!(matches!(parsed, ValueRecord::RemoveTopicValue(r) if r.topic_uuid == uuid));
+        Ok(())
+    }
+}
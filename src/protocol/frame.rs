@@ -0,0 +1,157 @@
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::request::RequestError;
+use crate::config;
+
+static MESSAGE_SIZE_LENGTH: usize = 4;
+static READ_CHUNK_SIZE: usize = 4096;
+
+/// Splits a byte stream into length-prefixed Kafka frames.
+///
+/// Bytes read past the end of a frame stay buffered and are used for the
+/// next frame, so pipelined requests on the same connection are not lost.
+pub struct FrameReader {
+    buffer: BytesMut,
+    max_request_size: usize,
+}
+
+impl FrameReader {
+    pub fn new(max_request_size: usize) -> Self {
+        FrameReader {
+            buffer: BytesMut::with_capacity(READ_CHUNK_SIZE),
+            max_request_size,
+        }
+    }
+
+    /// Reads one frame and returns its payload without the 4-byte size prefix.
+    pub async fn read_frame<R>(&mut self, stream: &mut R) -> Result<Bytes, RequestError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        loop {
+            if let Some(frame) = self.try_split_frame()? {
+                return Ok(frame);
+            }
+
+            self.buffer.reserve(READ_CHUNK_SIZE);
+            let read_size = stream
+                .read_buf(&mut self.buffer)
+                .await
+                .map_err(RequestError::IoError)?;
+
+            if read_size == 0 {
+                if self.buffer.is_empty() {
+                    return Err(RequestError::ClientDisconnected);
+                }
+                return Err(RequestError::IoError(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "connection closed with {} bytes of an incomplete request",
+                        self.buffer.len()
+                    ),
+                )));
+            }
+        }
+    }
+
+    fn try_split_frame(&mut self) -> Result<Option<Bytes>, RequestError> {
+        if self.buffer.len() < MESSAGE_SIZE_LENGTH {
+            return Ok(None);
+        }
+
+        let message_size = (&self.buffer[..MESSAGE_SIZE_LENGTH]).get_u32() as usize;
+        if message_size > self.max_request_size {
+            return Err(RequestError::RequestTooLarge {
+                size: message_size,
+                max: self.max_request_size,
+            });
+        }
+
+        if self.buffer.len() < MESSAGE_SIZE_LENGTH + message_size {
+            // Avoid growing the buffer a chunk at a time for big requests
+            self.buffer
+                .reserve(MESSAGE_SIZE_LENGTH + message_size - self.buffer.len());
+            return Ok(None);
+        }
+
+        self.buffer.advance(MESSAGE_SIZE_LENGTH);
+        Ok(Some(self.buffer.split_to(message_size).freeze()))
+    }
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        FrameReader::new(config::get().socket_request_max_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut data = (payload.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[tokio::test]
+    async fn reads_pipelined_frames() -> anyhow::Result<()> {
+        let mut data = frame(b"first");
+        data.extend(frame(b"second"));
+        let mut stream = data.as_slice();
+
+        let mut reader = FrameReader::default();
+        assert_eq!(reader.read_frame(&mut stream).await?, &b"first"[..]);
+        assert_eq!(reader.read_frame(&mut stream).await?, &b"second"[..]);
+        assert!(matches!(
+            reader.read_frame(&mut stream).await,
+            Err(RequestError::ClientDisconnected)
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reads_fragmented_frame() -> anyhow::Result<()> {
+        let payload = vec![7u8; 10_000];
+        let data = frame(&payload);
+        let (mut client, mut server) = tokio::io::duplex(64);
+
+        let writer = tokio::spawn(async move {
+            for chunk in data.chunks(100) {
+                client.write_all(chunk).await.unwrap();
+            }
+        });
+
+        let mut reader = FrameReader::default();
+        assert_eq!(reader.read_frame(&mut server).await?, payload);
+        writer.await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_frame() {
+        let data = frame(&[0u8; 32]);
+        let mut stream = data.as_slice();
+
+        let mut reader = FrameReader::new(16);
+        assert!(matches!(
+            reader.read_frame(&mut stream).await,
+            Err(RequestError::RequestTooLarge { size: 32, max: 16 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_truncated_frame() {
+        let data = frame(b"payload");
+        let mut stream = &data[..6];
+
+        let mut reader = FrameReader::default();
+        assert!(matches!(
+            reader.read_frame(&mut stream).await,
+            Err(RequestError::IoError(_))
+        ));
+    }
+}
@@ -1,14 +1,10 @@
 use core::fmt;
-use std::error::Error;
-use std::io::Read;
-
-use bytes::Buf;
-use tokio::io::{self, AsyncReadExt};
-use tokio::{
-    self,
-    io::{AsyncBufRead, BufReader},
-    net::TcpStream,
-};
+
+use bytes::{Buf, Bytes};
+use tokio::io::{self, AsyncRead};
+
+use super::frame::FrameReader;
+
 #[derive(Debug)]
 pub struct Request {
     pub message_size: u32,
@@ -24,6 +20,8 @@ pub struct Request {
 pub enum RequestError {
     ClientDisconnected,
     IoError(io::Error),
+    RequestTooLarge { size: usize, max: usize },
+    MalformedHeader(String),
 }
 
 impl fmt::Display for RequestError {
@@ -31,45 +29,81 @@ impl fmt::Display for RequestError {
         match *self {
             RequestError::ClientDisconnected => write!(f, "Client disconnected"),
             RequestError::IoError(ref e) => write!(f, "IO error: {}", e),
+            RequestError::RequestTooLarge { size, max } => write!(
+                f,
+                "Request of {} bytes exceeds the maximum request size of {} bytes",
+                size, max
+            ),
+            RequestError::MalformedHeader(ref reason) => {
+                write!(f, "Malformed request header: {}", reason)
+            }
         }
     }
 }
 
-impl Request {
-    pub async fn new(stream: &mut TcpStream) -> Result<Request, RequestError> {
-        let mut buffer: [u8; 1024] = [0; 1024];
+impl std::error::Error for RequestError {}
 
-        let mut reader = BufReader::new(stream);
+impl Request {
+    pub async fn new<R>(stream: &mut R, frames: &mut FrameReader) -> Result<Request, RequestError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let frame = frames.read_frame(stream).await?;
+        Request::from_frame(frame)
+    }
 
-        let read_size = reader
-            .read(&mut buffer)
-            .await
-            .map_err(RequestError::IoError)?;
+    /// Parses the request header from a frame read by [`FrameReader`].
+    pub fn from_frame(frame: Bytes) -> Result<Request, RequestError> {
+        let message_size = frame.len() as u32;
+        let mut request = frame;
 
-        if read_size == 0 {
-            return Err(RequestError::ClientDisconnected);
+        if request.remaining() < 10 {
+            return Err(RequestError::MalformedHeader(format!(
+                "expected at least 10 bytes, got {}",
+                request.remaining()
+            )));
         }
-
-        let mut request = buffer.as_slice();
-
-        let message_size: u32 = request.get_u32();
         let request_api_key = request.get_u16();
         let request_api_version = request.get_u16();
         let correlation_id = request.get_u32();
 
-        let client_id_length = request.get_u16() as usize;
-        let client_id_byte = request.copy_to_bytes(client_id_length);
-        request.get_u8(); // tag buffer
+        // client_id is a nullable string, -1 means null
+        let client_id_length = request.get_i16();
+        let client_id = if client_id_length < 0 {
+            String::new()
+        } else {
+            let client_id_length = client_id_length as usize;
+            if request.remaining() < client_id_length {
+                return Err(RequestError::MalformedHeader(format!(
+                    "client_id length {} exceeds remaining {} bytes",
+                    client_id_length,
+                    request.remaining()
+                )));
+            }
+            let client_id_byte = request.copy_to_bytes(client_id_length);
+            String::from_utf8(client_id_byte.to_vec())
+                .map_err(|e| RequestError::MalformedHeader(e.to_string()))?
+        };
+        if is_flexible(request_api_key, request_api_version) {
+            skip_header_tagged_fields(&mut request)?;
+        }
 
         Ok(Request {
             message_size,
             request_api_key,
             request_api_version,
             correlation_id,
-            data: request.chunk().to_vec(),
-            client_id: String::from_utf8(client_id_byte.to_vec()).unwrap(),
+            data: request.to_vec(),
+            client_id,
         })
     }
+
+    /// Whether the request (and its response) use the flexible encoding
+    /// with compact types and tagged fields.
+    pub fn is_flexible(&self) -> bool {
+        is_flexible(self.request_api_key, self.request_api_version)
+    }
+
     pub fn log(&self) {
         println!("[REQUEST] message_size: {}", self.message_size);
         println!("[REQUEST] request_api_key: {}", self.request_api_key);
@@ -82,3 +116,69 @@ impl Request {
         // println!("[REQUEST] data: {:?}", self.data);
     }
 }
+
+/// First version of each API that uses the flexible encoding.
+fn first_flexible_version(api_key: u16) -> Option<u16> {
+    match api_key {
+        0 => Some(9),  // Produce
+        1 => Some(12), // Fetch
+        2 => Some(6),  // ListOffsets
+        3 => Some(9),  // Metadata
+        8 => Some(8),  // OffsetCommit
+        9 => Some(6),  // OffsetFetch
+        10 => Some(3), // FindCoordinator
+        11 => Some(6), // JoinGroup
+        12 => Some(4), // Heartbeat
+        13 => Some(4), // LeaveGroup
+        14 => Some(4), // SyncGroup
+        18 => Some(3), // ApiVersions
+        19 => Some(5), // CreateTopics
+        20 => Some(4), // DeleteTopics
+        22 => Some(2), // InitProducerId
+        24 => Some(3), // AddPartitionsToTxn
+        25 => Some(3), // AddOffsetsToTxn
+        26 => Some(3), // EndTxn
+        27 => Some(1), // WriteTxnMarkers
+        28 => Some(3), // TxnOffsetCommit
+        37 => Some(2), // CreatePartitions
+        75 => Some(0), // DescribeTopicPartitions
+        _ => None,
+    }
+}
+
+pub fn is_flexible(api_key: u16, api_version: u16) -> bool {
+    first_flexible_version(api_key).is_some_and(|first| api_version >= first)
+}
+
+fn skip_header_tagged_fields(request: &mut Bytes) -> Result<(), RequestError> {
+    let tagged_fields_count = read_header_uvarint(request)?;
+    for _ in 0..tagged_fields_count {
+        let _tag = read_header_uvarint(request)?;
+        let size = read_header_uvarint(request)? as usize;
+        if request.remaining() < size {
+            return Err(RequestError::MalformedHeader(
+                "tagged field exceeds request size".to_string(),
+            ));
+        }
+        request.advance(size);
+    }
+    Ok(())
+}
+
+fn read_header_uvarint(request: &mut Bytes) -> Result<u64, RequestError> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        if !request.has_remaining() || shift > 63 {
+            return Err(RequestError::MalformedHeader(
+                "truncated varint in tagged fields".to_string(),
+            ));
+        }
+        let byte = request.get_u8();
+        value |= ((byte & 0x7F) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
@@ -7,7 +7,6 @@ use tokio::{
 
 use super::request::Request;
 
-static MESSAGE_SIZE_OFFSET: usize = 4;
 static CORRELATION_ID_SIZE_OFFSET: u32 = 4;
 static TAG_BUFFER_SIZE_OFFSET: u32 = 1;
 
@@ -15,27 +14,39 @@ pub struct Response<'a> {
     pub correlation_id: u32,
     pub body: Vec<u8>,
     pub request: &'a Request,
+    /// Set when the client expects no response, e.g. Produce with acks=0.
+    pub suppressed: bool,
 }
 
 impl<'a> Response<'a> {
     pub fn build_from_request(res: &'a Request) -> Self {
-        return Response {
+        Response {
             correlation_id: res.correlation_id,
             body: vec![],
             request: res,
-        };
+            suppressed: false,
+        }
     }
     pub fn message_size(&self) -> u32 {
-        return CORRELATION_ID_SIZE_OFFSET
-            + if self.request.request_api_key == 18 {
-                0
-            } else {
+        CORRELATION_ID_SIZE_OFFSET
+            + if self.has_tag_buffer() {
                 TAG_BUFFER_SIZE_OFFSET
+            } else {
+                0
             }
-            + self.body.len() as u32;
+            + self.body.len() as u32
+    }
+
+    // ApiVersions always answers with response header v0 so that clients
+    // can parse it before knowing which versions are supported.
+    fn has_tag_buffer(&self) -> bool {
+        self.request.request_api_key != 18 && self.request.is_flexible()
     }
 
     pub async fn send(&self, stream: &mut TcpStream) -> Result<(), Box<dyn Error>> {
+        if self.suppressed {
+            return Ok(());
+        }
         self.log().await;
         let mut writer = BufWriter::new(stream);
 
@@ -46,8 +57,7 @@ impl<'a> Response<'a> {
         writer.write_all(&self.correlation_id.to_be_bytes()).await?;
 
         // Write the tag buffer
-        if self.request.request_api_key != 18 {
-            // skip if apiVersion
+        if self.has_tag_buffer() {
             writer.write_u8(0).await?;
         }
 
@@ -0,0 +1,621 @@
+use std::io::Cursor;
+
+use bytes::{Buf, BufMut};
+
+use crate::custom_trait::cursor::{ReadVarint, WriteVarint};
+
+use super::compression::CompressionType;
+
+/// Size of the fixed part of a v2 record batch, from `base_offset` up to
+/// and including `records_count`.
+pub const BATCH_HEADER_SIZE: usize = 61;
+
+// Size of `base_offset` + `batch_length`, which `batch_length` does not cover
+pub const BATCH_LENGTH_OFFSET: usize = 12;
+
+static PARTITION_LEADER_EPOCH_POSITION: usize = 12;
+
+// The CRC covers everything from `attributes` to the end of the batch
+static CRC_POSITION: usize = 17;
+static CRC_START: usize = 21;
+
+// Bits of the batch attributes
+pub const COMPRESSION_CODEC_MASK: i16 = 0x07;
+pub const TIMESTAMP_TYPE_MASK: i16 = 0x08;
+pub const TRANSACTIONAL_MASK: i16 = 0x10;
+pub const CONTROL_MASK: i16 = 0x20;
+
+// Schema versions of the key and value of a transaction marker record
+static CONTROL_KEY_VERSION: i16 = 0;
+static END_TXN_MARKER_VERSION: i16 = 0;
+
+/// Kafka error codes a malformed record batch maps to.
+pub const CORRUPT_MESSAGE: i16 = 2;
+pub const UNSUPPORTED_FOR_MESSAGE_FORMAT: i16 = 43;
+pub const UNSUPPORTED_COMPRESSION_TYPE: i16 = 76;
+pub const INVALID_RECORD: i16 = 87;
+
+#[derive(Debug, thiserror::Error)]
+pub enum BatchError {
+    #[error("record batch truncated: need {needed} bytes, have {available}")]
+    Truncated { needed: usize, available: usize },
+    #[error("unsupported record batch magic {0}")]
+    UnsupportedMagic(i8),
+    #[error("invalid record batch length {0}")]
+    InvalidLength(i32),
+    #[error("records count {records_count} does not match last offset delta {last_offset_delta}")]
+    InvalidRecordsCount {
+        records_count: i32,
+        last_offset_delta: i32,
+    },
+    #[error("record batch crc {stored:#010x} does not match computed {computed:#010x}")]
+    CrcMismatch { stored: u32, computed: u32 },
+    #[error("unsupported compression codec {0}")]
+    UnsupportedCompression(i16),
+    #[error("invalid compressed records: {0}")]
+    Compression(std::io::Error),
+    #[error("no record batches in request")]
+    Empty,
+    #[error("clients are not allowed to write control batches")]
+    ControlBatch,
+    #[error("produce requests must hold exactly one record batch per partition, got {0}")]
+    MultipleBatches(usize),
+}
+
+impl BatchError {
+    pub fn error_code(&self) -> i16 {
+        match self {
+            BatchError::UnsupportedMagic(_) => UNSUPPORTED_FOR_MESSAGE_FORMAT,
+            BatchError::UnsupportedCompression(_) => UNSUPPORTED_COMPRESSION_TYPE,
+            BatchError::InvalidRecordsCount { .. }
+            | BatchError::Empty
+            | BatchError::ControlBatch
+            | BatchError::MultipleBatches(_) => INVALID_RECORD,
+            BatchError::Truncated { .. }
+            | BatchError::InvalidLength(_)
+            | BatchError::CrcMismatch { .. }
+            | BatchError::Compression(_) => CORRUPT_MESSAGE,
+        }
+    }
+}
+
+/// Record batch headers with the position of each batch.
+pub type BatchHeaders = Vec<(usize, BatchHeader)>;
+
+/// The fixed header of a v2 record batch, read straight from its bytes
+/// without decoding the records.
+// mirrors the header field for field, partition_leader_epoch is only ever
+// rewritten in place
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct BatchHeader {
+    pub base_offset: i64,
+    pub batch_length: i32,
+    pub partition_leader_epoch: i32,
+    pub magic: i8,
+    pub crc: u32,
+    pub attributes: i16,
+    pub last_offset_delta: i32,
+    pub base_timestamp: i64,
+    pub max_timestamp: i64,
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub base_sequence: i32,
+    pub records_count: i32,
+}
+
+impl BatchHeader {
+    pub fn parse(mut buf: &[u8]) -> Result<BatchHeader, BatchError> {
+        if buf.len() < BATCH_HEADER_SIZE {
+            return Err(BatchError::Truncated {
+                needed: BATCH_HEADER_SIZE,
+                available: buf.len(),
+            });
+        }
+        Ok(BatchHeader {
+            base_offset: buf.get_i64(),
+            batch_length: buf.get_i32(),
+            partition_leader_epoch: buf.get_i32(),
+            magic: buf.get_i8(),
+            crc: buf.get_u32(),
+            attributes: buf.get_i16(),
+            last_offset_delta: buf.get_i32(),
+            base_timestamp: buf.get_i64(),
+            max_timestamp: buf.get_i64(),
+            producer_id: buf.get_i64(),
+            producer_epoch: buf.get_i16(),
+            base_sequence: buf.get_i32(),
+            records_count: buf.get_i32(),
+        })
+    }
+
+    /// Total size of the batch on disk, including offset and length.
+    pub fn size(&self) -> usize {
+        self.batch_length as usize + BATCH_LENGTH_OFFSET
+    }
+
+    pub fn last_offset(&self) -> i64 {
+        self.base_offset + self.last_offset_delta as i64
+    }
+
+    pub fn next_offset(&self) -> i64 {
+        self.last_offset() + 1
+    }
+
+    pub fn compression(&self) -> Result<CompressionType, BatchError> {
+        let id = self.attributes & COMPRESSION_CODEC_MASK;
+        CompressionType::from_id(id).ok_or(BatchError::UnsupportedCompression(id))
+    }
+
+    pub fn is_transactional(&self) -> bool {
+        self.attributes & TRANSACTIONAL_MASK != 0
+    }
+
+    pub fn is_control(&self) -> bool {
+        self.attributes & CONTROL_MASK != 0
+    }
+}
+
+/// The outcome of a transaction a control batch marks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlMarker {
+    Abort,
+    Commit,
+}
+
+impl ControlMarker {
+    fn control_type(self) -> i16 {
+        match self {
+            ControlMarker::Abort => 0,
+            ControlMarker::Commit => 1,
+        }
+    }
+}
+
+/// Computes the CRC32C of a complete batch, as stored in its `crc` field.
+pub fn compute_crc(batch: &[u8]) -> u32 {
+    crc32c::crc32c(&batch[CRC_START..])
+}
+
+/// Checks the stored CRC of a complete batch against its contents.
+pub fn verify_crc(batch: &[u8], header: &BatchHeader) -> Result<(), BatchError> {
+    let computed = compute_crc(&batch[..header.size()]);
+    if computed != header.crc {
+        return Err(BatchError::CrcMismatch {
+            stored: header.crc,
+            computed,
+        });
+    }
+    Ok(())
+}
+
+/// Rewrites the `crc` field of a complete batch from its contents.
+pub fn set_crc(batch: &mut [u8]) {
+    let crc = compute_crc(batch);
+    (&mut batch[CRC_POSITION..CRC_START]).put_u32(crc);
+}
+
+/// Returns the records of a complete batch, decompressed.
+pub fn decompress_records(batch: &[u8]) -> Result<Vec<u8>, BatchError> {
+    let header = BatchHeader::parse(batch)?;
+    let records = batch
+        .get(BATCH_HEADER_SIZE..header.size())
+        .ok_or(BatchError::Truncated {
+            needed: header.size(),
+            available: batch.len(),
+        })?;
+    header
+        .compression()?
+        .decompress(records)
+        .map_err(BatchError::Compression)
+}
+
+/// Re-encodes the records of a complete batch with `compression`, fixing
+/// up the attributes, length and CRC.
+pub fn recompress(batch: &[u8], compression: CompressionType) -> Result<Vec<u8>, BatchError> {
+    let header = BatchHeader::parse(batch)?;
+    if header.compression()? == compression {
+        return Ok(batch[..header.size()].to_vec());
+    }
+    let records = compression
+        .compress(&decompress_records(batch)?)
+        .map_err(BatchError::Compression)?;
+
+    let mut converted = batch[..BATCH_HEADER_SIZE].to_vec();
+    converted.extend(records);
+    let batch_length = (converted.len() - BATCH_LENGTH_OFFSET) as i32;
+    (&mut converted[8..BATCH_LENGTH_OFFSET]).put_i32(batch_length);
+    let attributes = (header.attributes & !COMPRESSION_CODEC_MASK) | compression.id();
+    (&mut converted[CRC_START..CRC_START + 2]).put_i16(attributes);
+    set_crc(&mut converted);
+    Ok(converted)
+}
+
+/// Re-encodes every batch in `records` whose codec matches `filter` with
+/// `compression`, leaving the others as they are.
+pub fn recompress_batches(
+    records: &[u8],
+    filter: impl Fn(CompressionType) -> bool,
+    compression: CompressionType,
+) -> Result<Vec<u8>, BatchError> {
+    let mut converted = Vec::with_capacity(records.len());
+    for (position, header) in read_batch_headers(records) {
+        let batch = &records[position..position + header.size()];
+        if filter(header.compression()?) {
+            converted.extend(recompress(batch, compression)?);
+        } else {
+            converted.extend_from_slice(batch);
+        }
+    }
+    Ok(converted)
+}
+
+/// Returns `(timestamp, offset)` of every record in a batch, or `None`
+/// when the batch is malformed.
+pub fn record_timestamps(batch: &[u8]) -> Option<Vec<(i64, i64)>> {
+    let records = read_records(batch)?;
+    Some(
+        records
+            .iter()
+            .map(|record| (record.timestamp, record.offset))
+            .collect(),
+    )
+}
+
+/// A record decoded from a batch.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub offset: i64,
+    pub timestamp: i64,
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+}
+
+/// The key and value of a record to build, `None` for null.
+pub type KeyValue<'a> = (Option<&'a [u8]>, Option<&'a [u8]>);
+
+/// Builds an uncompressed batch of `(key, value)` records, all with
+/// `timestamp`, for data the broker writes itself. Offsets start at 0 and
+/// are assigned on append.
+pub fn build_batch(records: &[KeyValue], timestamp: i64) -> Vec<u8> {
+    encode_batch(records, timestamp, 0, -1, -1)
+}
+
+/// Like [`build_batch`], for records written on behalf of a transactional
+/// producer. They stay pending until a control batch ends the transaction.
+pub fn build_transactional_batch(
+    records: &[KeyValue],
+    timestamp: i64,
+    producer_id: i64,
+    producer_epoch: i16,
+) -> Vec<u8> {
+    encode_batch(
+        records,
+        timestamp,
+        TRANSACTIONAL_MASK,
+        producer_id,
+        producer_epoch,
+    )
+}
+
+/// Builds the control batch that commits or aborts the producer's
+/// transaction in a partition.
+pub fn build_control_batch(
+    marker: ControlMarker,
+    producer_id: i64,
+    producer_epoch: i16,
+    coordinator_epoch: i32,
+    timestamp: i64,
+) -> Vec<u8> {
+    let mut key = Vec::new();
+    key.put_i16(CONTROL_KEY_VERSION); // version
+    key.put_i16(marker.control_type()); // type
+    let mut value = Vec::new();
+    value.put_i16(END_TXN_MARKER_VERSION); // version
+    value.put_i32(coordinator_epoch); // coordinator_epoch
+    encode_batch(
+        &[(Some(&key), Some(&value))],
+        timestamp,
+        TRANSACTIONAL_MASK | CONTROL_MASK,
+        producer_id,
+        producer_epoch,
+    )
+}
+
+/// Reads the marker of a complete control batch, `None` when it is not one.
+pub fn read_control_marker(batch: &[u8]) -> Option<ControlMarker> {
+    let header = BatchHeader::parse(batch).ok()?;
+    if !header.is_control() {
+        return None;
+    }
+    let key = read_records(batch)?.into_iter().next()?.key?;
+    match key.get(2..4)? {
+        [0, 0] => Some(ControlMarker::Abort),
+        [0, 1] => Some(ControlMarker::Commit),
+        _ => None,
+    }
+}
+
+fn encode_batch(
+    records: &[KeyValue],
+    timestamp: i64,
+    attributes: i16,
+    producer_id: i64,
+    producer_epoch: i16,
+) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for (offset_delta, (key, value)) in records.iter().enumerate() {
+        let mut record = Vec::new();
+        record.put_i8(0); // attributes
+        record.write_varint(0); // timestamp delta
+        record.write_varint(offset_delta as i64); // offset delta
+        for field in [key, value] {
+            match field {
+                Some(bytes) => {
+                    record.write_varint(bytes.len() as i64);
+                    record.put_slice(bytes);
+                }
+                None => record.write_varint(-1),
+            }
+        }
+        record.write_varint(0); // headers
+        encoded.write_varint(record.len() as i64);
+        encoded.extend(record);
+    }
+
+    let mut batch = Vec::with_capacity(BATCH_HEADER_SIZE + encoded.len());
+    batch.put_i64(0); // base offset
+    batch.put_i32((BATCH_HEADER_SIZE - BATCH_LENGTH_OFFSET + encoded.len()) as i32); // batch length
+    batch.put_i32(0); // partition leader epoch
+    batch.put_i8(2); // magic
+    batch.put_u32(0); // crc
+    batch.put_i16(attributes); // attributes
+    batch.put_i32(records.len() as i32 - 1); // last offset delta
+    batch.put_i64(timestamp); // base timestamp
+    batch.put_i64(timestamp); // max timestamp
+    batch.put_i64(producer_id); // producer id
+    batch.put_i16(producer_epoch); // producer epoch
+    batch.put_i32(-1); // base sequence
+    batch.put_i32(records.len() as i32); // records count
+    batch.extend(encoded);
+    set_crc(&mut batch);
+    batch
+}
+
+/// Decodes every record of a complete batch, or returns `None` when the
+/// batch is malformed.
+pub fn read_records(batch: &[u8]) -> Option<Vec<Record>> {
+    let header = BatchHeader::parse(batch).ok()?;
+    let log_append_time = header.attributes & TIMESTAMP_TYPE_MASK != 0;
+
+    let records = decompress_records(batch).ok()?;
+    let mut cursor = Cursor::new(&records);
+    let mut decoded = Vec::new();
+    for _ in 0..header.records_count {
+        let length = cursor.read_varint().ok()?;
+        let record_start = cursor.position();
+        cursor.set_position(record_start + 1); // attributes
+        let timestamp_delta = cursor.read_varint().ok()?;
+        let offset_delta = cursor.read_varint().ok()?;
+        let mut fields = [None, None];
+        for field in &mut fields {
+            let field_length = cursor.read_varint().ok()?;
+            if field_length >= 0 {
+                let start = cursor.position() as usize;
+                let bytes = records.get(start..start + field_length as usize)?;
+                *field = Some(bytes.to_vec());
+                cursor.set_position((start + field_length as usize) as u64);
+            }
+        }
+        let [key, value] = fields;
+        decoded.push(Record {
+            offset: header.base_offset + offset_delta,
+            timestamp: if log_append_time {
+                header.max_timestamp
+            } else {
+                header.base_timestamp + timestamp_delta
+            },
+            key,
+            value,
+        });
+        cursor.set_position(record_start + length as u64);
+    }
+    Some(decoded)
+}
+
+/// Returns the position and header of every complete batch in `log`.
+/// A partially written batch at the end is ignored.
+pub fn read_batch_headers(log: &[u8]) -> BatchHeaders {
+    let mut headers = Vec::new();
+    let mut position = 0;
+    while let Ok(header) = BatchHeader::parse(&log[position..]) {
+        if header.batch_length < 0 || position + header.size() > log.len() {
+            break;
+        }
+        let size = header.size();
+        headers.push((position, header));
+        position += size;
+    }
+    headers
+}
+
+/// Checks the framing, checksums and compression of the record batches a
+/// client sent and returns their headers.
+pub fn validate_batches(records: &[u8]) -> Result<BatchHeaders, BatchError> {
+    let mut headers = Vec::new();
+    let mut position = 0;
+    while position < records.len() {
+        let header = BatchHeader::parse(&records[position..])?;
+        if header.magic != 2 {
+            return Err(BatchError::UnsupportedMagic(header.magic));
+        }
+        if (header.batch_length as i64) < (BATCH_HEADER_SIZE - BATCH_LENGTH_OFFSET) as i64 {
+            return Err(BatchError::InvalidLength(header.batch_length));
+        }
+        if position + header.size() > records.len() {
+            return Err(BatchError::Truncated {
+                needed: header.size(),
+                available: records.len() - position,
+            });
+        }
+        if header.records_count <= 0 || header.records_count != header.last_offset_delta + 1 {
+            return Err(BatchError::InvalidRecordsCount {
+                records_count: header.records_count,
+                last_offset_delta: header.last_offset_delta,
+            });
+        }
+        verify_crc(&records[position..], &header)?;
+        if header.compression()? != CompressionType::None {
+            // a payload that does not decompress would fail every consumer
+            decompress_records(&records[position..])?;
+        }
+        let size = header.size();
+        headers.push((position, header));
+        position += size;
+    }
+    if headers.is_empty() {
+        return Err(BatchError::Empty);
+    }
+    Ok(headers)
+}
+
+/// Overwrites `base_offset` of the batch starting at `batch`. The field is
+/// not covered by the CRC, so the batch stays valid.
+pub fn set_base_offset(batch: &mut [u8], base_offset: i64) {
+    (&mut batch[..8]).put_i64(base_offset);
+}
+
+/// Overwrites `partition_leader_epoch`, which is also outside the CRC.
+pub fn set_partition_leader_epoch(batch: &mut [u8], epoch: i32) {
+    (&mut batch[PARTITION_LEADER_EPOCH_POSITION..PARTITION_LEADER_EPOCH_POSITION + 4])
+        .put_i32(epoch);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch_bytes() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.put_i64(0); // base offset
+        data.put_i32(80 - BATCH_LENGTH_OFFSET as i32); // batch length
+        data.put_i32(0); // partition leader epoch
+        data.put_i8(2); // magic
+        data.put_u32(0); // crc
+        data.put_i16(0); // attributes
+        data.put_i32(0); // last offset delta
+        data.put_i64(0); // base timestamp
+        data.put_i64(0); // max timestamp
+        data.put_i64(-1); // producer id
+        data.put_i16(-1); // producer epoch
+        data.put_i32(-1); // base sequence
+        data.put_i32(1); // records count
+        data.resize(80, 0);
+        set_crc(&mut data);
+        data
+    }
+
+    #[test]
+    fn rejects_batches_with_a_bad_crc() {
+        let mut data = batch_bytes();
+        assert!(validate_batches(&data).is_ok());
+
+        // offset and leader epoch are outside the crc
+        set_base_offset(&mut data, 42);
+        set_partition_leader_epoch(&mut data, 7);
+        assert!(validate_batches(&data).is_ok());
+
+        data[70] ^= 0xff;
+        let err = validate_batches(&data).unwrap_err();
+        assert!(matches!(err, BatchError::CrcMismatch { .. }));
+        assert_eq!(err.error_code(), CORRUPT_MESSAGE);
+    }
+
+    // two records with a timestamp delta, values repeated so codecs shrink them
+    fn records_batch() -> Vec<u8> {
+        let mut records = Vec::new();
+        for (offset_delta, timestamp_delta) in [(0u8, 0u8), (1, 10)] {
+            let value = vec![b'v'; 40];
+            let mut record = vec![0, timestamp_delta * 2, offset_delta * 2, 1];
+            record.push(value.len() as u8 * 2);
+            record.extend(value);
+            record.push(0); // headers
+            records.push(record.len() as u8 * 2);
+            records.extend(record);
+        }
+
+        let mut data = Vec::new();
+        data.put_i64(0); // base offset
+        data.put_i32((BATCH_HEADER_SIZE - BATCH_LENGTH_OFFSET + records.len()) as i32); // batch length
+        data.put_i32(0); // partition leader epoch
+        data.put_i8(2); // magic
+        data.put_u32(0); // crc
+        data.put_i16(0); // attributes
+        data.put_i32(1); // last offset delta
+        data.put_i64(1000); // base timestamp
+        data.put_i64(1010); // max timestamp
+        data.put_i64(-1); // producer id
+        data.put_i16(-1); // producer epoch
+        data.put_i32(-1); // base sequence
+        data.put_i32(2); // records count
+        data.extend(records);
+        set_crc(&mut data);
+        data
+    }
+
+    #[test]
+    fn round_trips_batches_through_every_codec() -> Result<(), BatchError> {
+        let original = records_batch();
+        for compression in [
+            CompressionType::Gzip,
+            CompressionType::Snappy,
+            CompressionType::Lz4,
+            CompressionType::Zstd,
+        ] {
+            let compressed = recompress(&original, compression)?;
+            let (_, header) = &validate_batches(&compressed)?[0];
+            assert_eq!(header.compression()?, compression);
+            assert_eq!(
+                record_timestamps(&compressed),
+                Some(vec![(1000, 0), (1010, 1)])
+            );
+            assert_eq!(recompress(&compressed, CompressionType::None)?, original);
+        }
+
+        let mixed = [
+            original.clone(),
+            recompress(&original, CompressionType::Zstd)?,
+        ]
+        .concat();
+        let converted = recompress_batches(
+            &mixed,
+            |c| c == CompressionType::Zstd,
+            CompressionType::None,
+        )?;
+        assert_eq!(converted, [original.clone(), original].concat());
+        Ok(())
+    }
+
+    #[test]
+    fn decodes_the_batches_it_builds() -> Result<(), BatchError> {
+        let mut data = build_batch(&[(Some(b"k"), Some(b"v")), (Some(b"k"), None)], 1000);
+        validate_batches(&data)?;
+        set_base_offset(&mut data, 5);
+        let records = read_records(&data).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].offset, 5);
+        assert_eq!(records[0].timestamp, 1000);
+        assert_eq!(records[0].value.as_deref(), Some(&b"v"[..]));
+        assert_eq!(records[1].offset, 6);
+        assert_eq!(records[1].key.as_deref(), Some(&b"k"[..]));
+        assert_eq!(records[1].value, None);
+        assert_eq!(read_control_marker(&data), None);
+
+        let marker = build_control_batch(ControlMarker::Commit, 7, 1, 0, 1000);
+        let header = validate_batches(&marker)?.remove(0).1;
+        assert!(header.is_control() && header.is_transactional());
+        assert_eq!((header.producer_id, header.producer_epoch), (7, 1));
+        assert_eq!(read_control_marker(&marker), Some(ControlMarker::Commit));
+        Ok(())
+    }
+}
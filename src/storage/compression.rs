@@ -0,0 +1,170 @@
+use std::io::{self, Read, Write};
+
+use bytes::{Buf, BufMut};
+
+// snappy-java's stream format, which the Java client writes
+static XERIAL_MAGIC: &[u8] = b"\x82SNAPPY\x00";
+static XERIAL_HEADER_SIZE: usize = 16;
+static XERIAL_BLOCK_SIZE: usize = 32 * 1024;
+
+/// The codec in the low three bits of a record batch's attributes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionType {
+    None,
+    Gzip,
+    Snappy,
+    Lz4,
+    Zstd,
+}
+
+impl CompressionType {
+    pub fn from_id(id: i16) -> Option<CompressionType> {
+        match id {
+            0 => Some(CompressionType::None),
+            1 => Some(CompressionType::Gzip),
+            2 => Some(CompressionType::Snappy),
+            3 => Some(CompressionType::Lz4),
+            4 => Some(CompressionType::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn id(self) -> i16 {
+        match self {
+            CompressionType::None => 0,
+            CompressionType::Gzip => 1,
+            CompressionType::Snappy => 2,
+            CompressionType::Lz4 => 3,
+            CompressionType::Zstd => 4,
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            CompressionType::None => Ok(data.to_vec()),
+            CompressionType::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            CompressionType::Snappy => snappy_compress(data),
+            CompressionType::Lz4 => {
+                // Kafka's reader only handles independent blocks
+                let frame_info = lz4_flex::frame::FrameInfo::new()
+                    .block_mode(lz4_flex::frame::BlockMode::Independent)
+                    .block_size(lz4_flex::frame::BlockSize::Max64KB);
+                let mut encoder =
+                    lz4_flex::frame::FrameEncoder::with_frame_info(frame_info, Vec::new());
+                encoder.write_all(data)?;
+                encoder.finish().map_err(io::Error::other)
+            }
+            CompressionType::Zstd => zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        match self {
+            CompressionType::None => decompressed.extend_from_slice(data),
+            CompressionType::Gzip => {
+                flate2::read::GzDecoder::new(data).read_to_end(&mut decompressed)?;
+            }
+            CompressionType::Snappy => decompressed = snappy_decompress(data)?,
+            CompressionType::Lz4 => {
+                lz4_flex::frame::FrameDecoder::new(data).read_to_end(&mut decompressed)?;
+            }
+            CompressionType::Zstd => decompressed = zstd::decode_all(data)?,
+        }
+        Ok(decompressed)
+    }
+}
+
+impl std::str::FromStr for CompressionType {
+    type Err = anyhow::Error;
+
+    /// Parses the codec names used by `compression.type`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uncompressed" | "none" => Ok(CompressionType::None),
+            "gzip" => Ok(CompressionType::Gzip),
+            "snappy" => Ok(CompressionType::Snappy),
+            "lz4" => Ok(CompressionType::Lz4),
+            "zstd" => Ok(CompressionType::Zstd),
+            _ => Err(anyhow::anyhow!("unknown compression type {s}")),
+        }
+    }
+}
+
+// Writes the xerial framing: a header, then length prefixed raw snappy blocks
+fn snappy_compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut compressed = Vec::new();
+    compressed.put_slice(XERIAL_MAGIC);
+    compressed.put_i32(1); // version
+    compressed.put_i32(1); // compatible version
+    let mut encoder = snap::raw::Encoder::new();
+    for block in data.chunks(XERIAL_BLOCK_SIZE) {
+        let block = encoder.compress_vec(block).map_err(io::Error::other)?;
+        compressed.put_i32(block.len() as i32);
+        compressed.put_slice(&block);
+    }
+    Ok(compressed)
+}
+
+// Reads both the xerial framing and a single raw snappy block
+fn snappy_decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decoder = snap::raw::Decoder::new();
+    if !data.starts_with(XERIAL_MAGIC) {
+        return decoder.decompress_vec(data).map_err(io::Error::other);
+    }
+
+    let mut blocks = data.get(XERIAL_HEADER_SIZE..).unwrap_or_default();
+    let mut decompressed = Vec::new();
+    while blocks.has_remaining() {
+        if blocks.remaining() < 4 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let length = blocks.get_i32() as usize;
+        let block = blocks
+            .get(..length)
+            .ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        decompressed.extend(decoder.decompress_vec(block).map_err(io::Error::other)?);
+        blocks.advance(length);
+    }
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_every_codec() -> io::Result<()> {
+        let data: Vec<u8> = (0..100_000u32)
+            .flat_map(|n| (n % 251).to_be_bytes())
+            .collect();
+        for codec in [
+            CompressionType::None,
+            CompressionType::Gzip,
+            CompressionType::Snappy,
+            CompressionType::Lz4,
+            CompressionType::Zstd,
+        ] {
+            let compressed = codec.compress(&data)?;
+            assert_eq!(codec.decompress(&compressed)?, data, "{:?}", codec);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reads_raw_snappy() -> io::Result<()> {
+        let compressed = snap::raw::Encoder::new()
+            .compress_vec(b"raw snappy block")
+            .map_err(io::Error::other)?;
+        assert_eq!(
+            CompressionType::Snappy.decompress(&compressed)?,
+            b"raw snappy block"
+        );
+        Ok(())
+    }
+}
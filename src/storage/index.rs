@@ -0,0 +1,471 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use bytes::{Buf, BufMut};
+use memmap2::Mmap;
+
+use super::batch::{self, BatchHeader};
+
+/// Same default as Kafka's `index.interval.bytes`: a new index entry is
+/// added once this many log bytes were written since the previous one.
+pub const INDEX_INTERVAL_BYTES: usize = 4096;
+
+static OFFSET_ENTRY_SIZE: usize = 8;
+static TIME_ENTRY_SIZE: usize = 12;
+// version, producer_id, first_offset, last_offset and last_stable_offset
+static TXN_ENTRY_SIZE: usize = 34;
+static TXN_INDEX_VERSION: i16 = 0;
+
+pub fn offset_index_path(segment_path: &Path) -> PathBuf {
+    segment_path.with_extension("index")
+}
+
+pub fn time_index_path(segment_path: &Path) -> PathBuf {
+    segment_path.with_extension("timeindex")
+}
+
+pub fn txn_index_path(segment_path: &Path) -> PathBuf {
+    segment_path.with_extension("txnindex")
+}
+
+/// A memory-mapped file of fixed size entries. Empty files cannot be
+/// mapped, so they are represented by `None`.
+struct MappedEntries {
+    mmap: Option<Mmap>,
+    entry_size: usize,
+}
+
+impl MappedEntries {
+    fn open(path: &Path, entry_size: usize) -> io::Result<MappedEntries> {
+        let file = File::open(path)?;
+        let mmap = if file.metadata()?.len() == 0 {
+            None
+        } else {
+            // Safety: index files are only appended to, never truncated in
+            // place; rebuilds replace them with a rename.
+            Some(unsafe { Mmap::map(&file)? })
+        };
+        Ok(MappedEntries { mmap, entry_size })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        self.mmap.as_deref().unwrap_or_default()
+    }
+
+    // A concurrent append may leave a partial entry at the end, ignore it
+    fn len(&self) -> usize {
+        self.bytes().len() / self.entry_size
+    }
+
+    fn entry(&self, n: usize) -> &[u8] {
+        &self.bytes()[n * self.entry_size..(n + 1) * self.entry_size]
+    }
+
+    fn is_aligned(&self) -> bool {
+        self.bytes().len() % self.entry_size == 0
+    }
+}
+
+/// Maps offsets to positions of the batches containing them, like Kafka's
+/// `.index` files. Each entry holds the last offset of a batch relative to
+/// the segment base offset and the batch's position in the segment.
+pub struct OffsetIndex {
+    base_offset: i64,
+    entries: MappedEntries,
+}
+
+impl OffsetIndex {
+    pub fn open(path: &Path, base_offset: i64) -> io::Result<OffsetIndex> {
+        Ok(OffsetIndex {
+            base_offset,
+            entries: MappedEntries::open(path, OFFSET_ENTRY_SIZE)?,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn entry(&self, n: usize) -> (i64, usize) {
+        let mut entry = self.entries.entry(n);
+        let relative_offset = entry.get_i32();
+        let position = entry.get_u32();
+        (self.base_offset + relative_offset as i64, position as usize)
+    }
+
+    pub fn last_entry(&self) -> Option<(i64, usize)> {
+        (!self.is_empty()).then(|| self.entry(self.len() - 1))
+    }
+
+    /// Returns the position to start scanning from to find `target_offset`:
+    /// the largest entry with an offset not above it, or the segment start.
+    pub fn lookup(&self, target_offset: i64) -> usize {
+        let count = self.partition_point(|offset| offset < target_offset);
+        if count == 0 {
+            0
+        } else {
+            self.entry(count - 1).1
+        }
+    }
+
+    fn partition_point(&self, pred: impl Fn(i64) -> bool) -> usize {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if pred(self.entry(mid).0) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    fn is_valid(&self, log_size: usize) -> bool {
+        if !self.entries.is_aligned() {
+            return false;
+        }
+        let mut previous: Option<(i64, usize)> = None;
+        for n in 0..self.len() {
+            let (offset, position) = self.entry(n);
+            if position >= log_size || offset < self.base_offset {
+                return false;
+            }
+            if let Some((previous_offset, previous_position)) = previous {
+                if offset <= previous_offset || position <= previous_position {
+                    return false;
+                }
+            }
+            previous = Some((offset, position));
+        }
+        true
+    }
+}
+
+/// Maps timestamps to offsets, like Kafka's `.timeindex` files. Each entry
+/// holds the largest timestamp seen so far and the offset of the batch that
+/// carried it, relative to the segment base offset.
+pub struct TimeIndex {
+    base_offset: i64,
+    entries: MappedEntries,
+}
+
+impl TimeIndex {
+    pub fn open(path: &Path, base_offset: i64) -> io::Result<TimeIndex> {
+        Ok(TimeIndex {
+            base_offset,
+            entries: MappedEntries::open(path, TIME_ENTRY_SIZE)?,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn entry(&self, n: usize) -> (i64, i64) {
+        let mut entry = self.entries.entry(n);
+        let timestamp = entry.get_i64();
+        let relative_offset = entry.get_i32();
+        (timestamp, self.base_offset + relative_offset as i64)
+    }
+
+    pub fn last_entry(&self) -> Option<(i64, i64)> {
+        (!self.is_empty()).then(|| self.entry(self.len() - 1))
+    }
+
+    /// Returns the offset to start scanning from to find the first record
+    /// with a timestamp at or after `timestamp`.
+    pub fn lookup(&self, timestamp: i64) -> i64 {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.entry(mid).0 < timestamp {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        if low == 0 {
+            self.base_offset
+        } else {
+            self.entry(low - 1).1
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        if !self.entries.is_aligned() {
+            return false;
+        }
+        (1..self.len()).all(|n| {
+            let (previous_timestamp, previous_offset) = self.entry(n - 1);
+            let (timestamp, offset) = self.entry(n);
+            timestamp > previous_timestamp && offset > previous_offset
+        })
+    }
+}
+
+/// Decides which batches get an index entry while a segment is written.
+#[derive(Default)]
+pub struct IndexWriter {
+    pub offset_entries: Vec<u8>,
+    pub time_entries: Vec<u8>,
+    bytes_since_last_entry: usize,
+    // largest timestamp of all batches so far and the offset it belongs to
+    max_timestamp: Option<(i64, i64)>,
+    last_time_entry: Option<i64>,
+}
+
+impl IndexWriter {
+    /// Resumes after the existing entries of a segment's indexes.
+    /// `tail_max_timestamp` covers the batches after the last offset index
+    /// entry, which the time index may not reflect yet.
+    pub fn resume(
+        offset_index: &OffsetIndex,
+        time_index: &TimeIndex,
+        log_size: usize,
+        tail_max_timestamp: Option<(i64, i64)>,
+    ) -> Self {
+        let indexed_position = offset_index.last_entry().map_or(0, |(_, p)| p);
+        let last_time_entry = time_index.last_entry();
+        IndexWriter {
+            bytes_since_last_entry: log_size - indexed_position,
+            max_timestamp: last_time_entry.max(tail_max_timestamp),
+            last_time_entry: last_time_entry.map(|(timestamp, _)| timestamp),
+            ..IndexWriter::default()
+        }
+    }
+
+    pub fn append(&mut self, base_offset: i64, position: usize, header: &BatchHeader) {
+        if self
+            .max_timestamp
+            .map_or(true, |(max, _)| header.max_timestamp > max)
+        {
+            self.max_timestamp = Some((header.max_timestamp, header.last_offset()));
+        }
+
+        if self.bytes_since_last_entry > INDEX_INTERVAL_BYTES {
+            let relative_offset = (header.last_offset() - base_offset) as i32;
+            self.offset_entries.put_i32(relative_offset);
+            self.offset_entries.put_u32(position as u32);
+
+            if let Some((max_timestamp, max_timestamp_offset)) = self.max_timestamp {
+                if self
+                    .last_time_entry
+                    .map_or(true, |last| max_timestamp > last)
+                {
+                    self.time_entries.put_i64(max_timestamp);
+                    self.time_entries
+                        .put_i32((max_timestamp_offset - base_offset) as i32);
+                    self.last_time_entry = Some(max_timestamp);
+                }
+            }
+            self.bytes_since_last_entry = 0;
+        }
+        self.bytes_since_last_entry += header.size();
+    }
+}
+
+/// A transaction that was aborted, as listed in Kafka's `.txnindex`
+/// files. A segment's transaction index holds the transactions whose abort
+/// marker is in the segment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AbortedTxn {
+    pub producer_id: i64,
+    pub first_offset: i64,
+    // offset of the abort marker
+    pub last_offset: i64,
+    pub last_stable_offset: i64,
+}
+
+/// Reads the transaction index of a segment, empty when it has none.
+pub fn read_aborted_txns(segment_path: &Path) -> io::Result<Vec<AbortedTxn>> {
+    let data = match std::fs::read(txn_index_path(segment_path)) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    // a partial entry at the end is from an interrupted append
+    let mut aborted = Vec::new();
+    for mut entry in data.chunks_exact(TXN_ENTRY_SIZE) {
+        if entry.get_i16() != TXN_INDEX_VERSION {
+            continue;
+        }
+        aborted.push(AbortedTxn {
+            producer_id: entry.get_i64(),
+            first_offset: entry.get_i64(),
+            last_offset: entry.get_i64(),
+            last_stable_offset: entry.get_i64(),
+        });
+    }
+    Ok(aborted)
+}
+
+/// Appends aborted transactions to a segment's transaction index.
+pub fn append_aborted_txns(segment_path: &Path, aborted: &[AbortedTxn]) -> io::Result<()> {
+    let mut entries = Vec::new();
+    for txn in aborted {
+        entries.put_i16(TXN_INDEX_VERSION); // version
+        entries.put_i64(txn.producer_id); // producer_id
+        entries.put_i64(txn.first_offset); // first_offset
+        entries.put_i64(txn.last_offset); // last_offset
+        entries.put_i64(txn.last_stable_offset); // last_stable_offset
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(txn_index_path(segment_path))?;
+    // drop a partial entry left by an interrupted append
+    let size = file.metadata()?.len();
+    file.set_len(size - size % TXN_ENTRY_SIZE as u64)?;
+    file.write_all(&entries)
+}
+
+/// Opens the indexes of a segment without checking their entries,
+/// returning `None` when they are missing.
+pub fn open(segment_path: &Path, base_offset: i64) -> io::Result<Option<(OffsetIndex, TimeIndex)>> {
+    let offset_index = match OffsetIndex::open(&offset_index_path(segment_path), base_offset) {
+        Ok(index) => index,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let time_index = match TimeIndex::open(&time_index_path(segment_path), base_offset) {
+        Ok(index) => index,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(Some((offset_index, time_index)))
+}
+
+/// Opens the indexes of a segment and checks every entry against the log,
+/// returning `None` when they are missing or corrupt and have to be rebuilt.
+pub fn open_checked(
+    segment_path: &Path,
+    base_offset: i64,
+    log_size: usize,
+) -> io::Result<Option<(OffsetIndex, TimeIndex)>> {
+    let Some((offset_index, time_index)) = open(segment_path, base_offset)? else {
+        return Ok(None);
+    };
+    if !offset_index.is_valid(log_size) || !time_index.is_valid() {
+        eprintln!("indexes of {} are corrupt", segment_path.display());
+        return Ok(None);
+    }
+    Ok(Some((offset_index, time_index)))
+}
+
+/// Recreates both indexes by scanning the whole segment. Callers must keep
+/// appends out while this runs.
+pub fn rebuild(segment_path: &Path, base_offset: i64) -> io::Result<(OffsetIndex, TimeIndex)> {
+    let log = std::fs::read(segment_path)?;
+    let mut writer = IndexWriter::default();
+    for (position, header) in batch::read_batch_headers(&log) {
+        writer.append(base_offset, position, &header);
+    }
+    replace_file(&offset_index_path(segment_path), &writer.offset_entries)?;
+    replace_file(&time_index_path(segment_path), &writer.time_entries)?;
+    Ok((
+        OffsetIndex::open(&offset_index_path(segment_path), base_offset)?,
+        TimeIndex::open(&time_index_path(segment_path), base_offset)?,
+    ))
+}
+
+/// Appends entries produced by an [`IndexWriter`] to a segment's indexes.
+pub fn append_entries(segment_path: &Path, writer: &IndexWriter) -> io::Result<()> {
+    for (path, entries) in [
+        (offset_index_path(segment_path), &writer.offset_entries),
+        (time_index_path(segment_path), &writer.time_entries),
+    ] {
+        if entries.is_empty() {
+            continue;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(entries)?;
+    }
+    Ok(())
+}
+
+// Readers may have the old file mapped, so write a new file and swap it in
+fn replace_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension(format!(
+        "{}.tmp",
+        path.extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default()
+    ));
+    std::fs::write(&tmp_path, content)?;
+    std::fs::rename(tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch_bytes(base_offset: i64, records: i32, timestamp: i64, size: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.put_i64(base_offset);
+        data.put_i32((size - batch::BATCH_LENGTH_OFFSET) as i32);
+        data.put_i32(0); // partition leader epoch
+        data.put_i8(2); // magic
+        data.put_u32(0); // crc
+        data.put_i16(0); // attributes
+        data.put_i32(records - 1); // last offset delta
+        data.put_i64(timestamp); // base timestamp
+        data.put_i64(timestamp); // max timestamp
+        data.put_i64(-1); // producer id
+        data.put_i16(-1); // producer epoch
+        data.put_i32(-1); // base sequence
+        data.put_i32(records); // records count
+        data.resize(size, 0);
+        data
+    }
+
+    #[test]
+    fn rebuilds_and_looks_up_entries() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("index-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir)?;
+        let segment_path = dir.join("00000000000000000100.log");
+
+        let mut log = Vec::new();
+        let mut positions = Vec::new();
+        for n in 0..10 {
+            positions.push(log.len());
+            log.extend(batch_bytes(100 + n * 2, 2, 1000 + n, 3000));
+        }
+        std::fs::write(&segment_path, &log)?;
+
+        assert!(open(&segment_path, 100)?.is_none());
+        let (offset_index, time_index) = rebuild(&segment_path, 100)?;
+        assert!(open_checked(&segment_path, 100, log.len())?.is_some());
+        assert!(!offset_index.is_empty());
+        assert_eq!(offset_index.len(), time_index.len());
+
+        // every batch lookup lands on or before the batch holding the offset
+        for (n, batch_position) in positions.iter().enumerate() {
+            let position = offset_index.lookup(100 + n as i64 * 2 + 1);
+            assert!(position <= *batch_position);
+        }
+        assert_eq!(offset_index.lookup(100), 0);
+        assert!(time_index.lookup(1005) <= 110);
+        assert_eq!(time_index.lookup(0), 100);
+
+        // a truncated index is detected and rebuilt
+        std::fs::write(offset_index_path(&segment_path), [0u8; 5])?;
+        assert!(open_checked(&segment_path, 100, log.len())?.is_none());
+        let (rebuilt, _) = rebuild(&segment_path, 100)?;
+        assert_eq!(rebuilt.len(), offset_index.len());
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
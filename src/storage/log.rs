@@ -0,0 +1,437 @@
+use std::path::{Path, PathBuf};
+
+use tokio::fs::File;
+
+use crate::{config, metadata::image::MetadataImage};
+
+use super::{
+    batch::BatchHeader,
+    index::{self, AbortedTxn, OffsetIndex, TimeIndex},
+    open_indexes,
+    segment::{LogSegment, SegmentBounds},
+    AppendInfo, PartitionRead,
+};
+
+/// When the active segment of a log rolls. Defaults to the broker's
+/// `log.segment.bytes` and `log.roll.ms`.
+#[derive(Clone, Debug)]
+pub struct LogConfig {
+    pub segment_bytes: usize,
+    pub segment_ms: i64,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        let config = config::get();
+        LogConfig {
+            segment_bytes: config.log_segment_bytes,
+            segment_ms: config.log_roll_ms,
+        }
+    }
+}
+
+impl LogConfig {
+    /// The topic's `segment.bytes` and `segment.ms` configs, falling back to
+    /// the broker's for those it does not set.
+    pub fn for_topic(image: &MetadataImage, topic_name: &str) -> LogConfig {
+        let mut config = LogConfig::default();
+        let topic_config = |name| image.topic_config(topic_name, name);
+        if let Some(segment_bytes) = topic_config("segment.bytes").and_then(|v| v.parse().ok()) {
+            config.segment_bytes = segment_bytes;
+        }
+        if let Some(segment_ms) = topic_config("segment.ms").and_then(|v| v.parse().ok()) {
+            config.segment_ms = segment_ms;
+        }
+        config
+    }
+}
+
+/// A partition log made of `<base_offset>.log` segments in one directory.
+/// The segment with the highest base offset is the active one that
+/// receives appends.
+#[derive(Clone, Debug)]
+pub struct Log {
+    pub dir: PathBuf,
+    pub segments: Vec<LogSegment>,
+    pub config: LogConfig,
+}
+
+impl Log {
+    pub async fn open(dir: &Path, config: LogConfig) -> std::io::Result<Log> {
+        let mut segments = Vec::new();
+        match tokio::fs::read_dir(dir).await {
+            Ok(mut entries) => {
+                while let Some(entry) = entries.next_entry().await? {
+                    if let Some(segment) = LogSegment::from_path(&entry.path()) {
+                        segments.push(segment);
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        segments.sort_by_key(|segment| segment.base_offset);
+        if segments.is_empty() {
+            // not created on disk until the first append
+            segments.push(LogSegment::new(dir, 0));
+        }
+        Ok(Log {
+            dir: dir.to_path_buf(),
+            segments,
+            config,
+        })
+    }
+
+    pub fn active_segment(&self) -> &LogSegment {
+        self.segments
+            .last()
+            .expect("a log has at least one segment")
+    }
+
+    // index of the segment that holds `offset`
+    fn segment_index(&self, offset: i64) -> usize {
+        self.segments
+            .partition_point(|segment| segment.base_offset <= offset)
+            .saturating_sub(1)
+    }
+
+    pub async fn log_start_offset(&self) -> std::io::Result<i64> {
+        let first = &self.segments[0];
+        let file_size = first.size().await?;
+        match File::open(&first.path).await {
+            Ok(mut file) => Ok(super::segment::read_header_at(&mut file, 0, file_size)
+                .await?
+                .map_or(first.base_offset, |header| header.base_offset)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(first.base_offset),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn segment_bounds(&self, segment: &LogSegment) -> anyhow::Result<Option<SegmentBounds>> {
+        Ok(self
+            .open_segment(segment)
+            .await?
+            .map(|(_, bounds, _)| bounds))
+    }
+
+    pub async fn high_watermark(&self) -> anyhow::Result<i64> {
+        let active = self.active_segment();
+        Ok(self
+            .segment_bounds(active)
+            .await?
+            .map_or(active.base_offset, |bounds| bounds.next_offset))
+    }
+
+    /// Reads whole batches starting with the one that contains
+    /// `fetch_offset`, stopping before `max_bytes` is exceeded. With
+    /// `min_one_batch` the first batch is returned even when it is larger
+    /// than `max_bytes`, so consumers can always make progress.
+    pub async fn read(
+        &self,
+        fetch_offset: i64,
+        max_bytes: usize,
+        min_one_batch: bool,
+    ) -> anyhow::Result<PartitionRead> {
+        let log_start_offset = self.log_start_offset().await?;
+        let high_watermark = self.high_watermark().await?;
+        if fetch_offset < log_start_offset || fetch_offset > high_watermark {
+            return Ok(PartitionRead {
+                high_watermark,
+                last_stable_offset: high_watermark,
+                log_start_offset,
+                records: None,
+                aborted_transactions: Vec::new(),
+            });
+        }
+
+        let mut records = Vec::new();
+        // an offset past the last batch of a segment continues in the next one
+        for segment in &self.segments[self.segment_index(fetch_offset)..] {
+            if fetch_offset >= high_watermark {
+                break;
+            }
+            let Ok(mut file) = File::open(&segment.path).await else {
+                continue;
+            };
+            let file_size = file.metadata().await?.len() as usize;
+            let (offset_index, _) = open_indexes(segment, file_size).await?;
+            let bounds = segment.bounds(&mut file, file_size, &offset_index).await?;
+            records = segment
+                .read(
+                    &mut file,
+                    bounds.end,
+                    &offset_index,
+                    fetch_offset,
+                    max_bytes,
+                    min_one_batch,
+                )
+                .await?;
+            if !records.is_empty() || fetch_offset < bounds.next_offset {
+                break;
+            }
+        }
+
+        Ok(PartitionRead {
+            high_watermark,
+            last_stable_offset: high_watermark,
+            log_start_offset,
+            records: Some(records),
+            aborted_transactions: Vec::new(),
+        })
+    }
+
+    /// Aborted transactions with records between `start_offset` and
+    /// `end_offset`, exclusive.
+    pub fn aborted_txns(
+        &self,
+        start_offset: i64,
+        end_offset: i64,
+    ) -> std::io::Result<Vec<AbortedTxn>> {
+        let mut aborted = Vec::new();
+        // a transaction aborted before the segment of `start_offset` ended
+        // before `start_offset` too
+        for segment in &self.segments[self.segment_index(start_offset)..] {
+            aborted.extend(
+                index::read_aborted_txns(&segment.path)?
+                    .into_iter()
+                    .filter(|txn| txn.last_offset >= start_offset && txn.first_offset < end_offset),
+            );
+        }
+        Ok(aborted)
+    }
+
+    /// Adds aborted transactions to the index of the segment holding their
+    /// abort marker, skipping the ones already there. Callers must hold the
+    /// append lock.
+    pub fn append_aborted_txns(&self, aborted: &[AbortedTxn]) -> std::io::Result<()> {
+        for txn in aborted {
+            let segment = &self.segments[self.segment_index(txn.last_offset)];
+            // markers are indexed in offset order
+            let indexed = index::read_aborted_txns(&segment.path)?;
+            if indexed
+                .last()
+                .is_some_and(|last| last.last_offset >= txn.last_offset)
+            {
+                continue;
+            }
+            index::append_aborted_txns(&segment.path, std::slice::from_ref(txn))?;
+        }
+        Ok(())
+    }
+
+    /// Returns `(timestamp, offset)` of the first record whose timestamp is
+    /// at or after `timestamp`, `None` when every record is older.
+    pub async fn offset_for_timestamp(&self, timestamp: i64) -> anyhow::Result<Option<(i64, i64)>> {
+        for segment in &self.segments {
+            let Some((mut file, bounds, indexes)) = self.open_segment(segment).await? else {
+                continue;
+            };
+            let found = segment
+                .find_timestamp(&mut file, &bounds, &indexes, timestamp)
+                .await?;
+            if found.is_some() {
+                return Ok(found);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the largest timestamp in the log and the offset of the first
+    /// record that carries it.
+    pub async fn max_timestamp(&self) -> anyhow::Result<Option<(i64, i64)>> {
+        let mut max: Option<(i64, i64)> = None;
+        for segment in &self.segments {
+            let Some((mut file, bounds, indexes)) = self.open_segment(segment).await? else {
+                continue;
+            };
+            let found = segment.max_timestamp(&mut file, &bounds, &indexes).await?;
+            if let Some((timestamp, offset)) = found {
+                if max.map_or(true, |(max_timestamp, _)| timestamp > max_timestamp) {
+                    max = Some((timestamp, offset));
+                }
+            }
+        }
+        Ok(max)
+    }
+
+    async fn open_segment(
+        &self,
+        segment: &LogSegment,
+    ) -> anyhow::Result<Option<(File, SegmentBounds, (OffsetIndex, TimeIndex))>> {
+        let mut file = match File::open(&segment.path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let file_size = file.metadata().await?.len() as usize;
+        let indexes = open_indexes(segment, file_size).await?;
+        let bounds = segment.bounds(&mut file, file_size, &indexes.0).await?;
+        Ok(Some((file, bounds, indexes)))
+    }
+
+    /// Assigns offsets to validated batches and appends them to the active
+    /// segment, rolling a new segment first when it is full or too old.
+    /// Callers must hold the append lock.
+    pub async fn append(
+        &mut self,
+        leader_epoch: i32,
+        records: &mut [u8],
+        headers: &[(usize, BatchHeader)],
+    ) -> anyhow::Result<AppendInfo> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let active = self.active_segment().clone();
+        if File::open(&active.path).await.is_err() {
+            File::create(&active.path).await?;
+        }
+        let file_size = active.size().await?;
+        let mut indexes = active.open_indexes(file_size)?;
+        let mut file = File::open(&active.path).await?;
+        let mut bounds = active.bounds(&mut file, file_size, &indexes.0).await?;
+
+        let mut segment = active;
+        if self.should_roll(&segment, &bounds, records.len(), headers) {
+            segment = LogSegment::new(&self.dir, bounds.next_offset);
+            File::create(&segment.path).await?;
+            indexes = segment.open_indexes(0)?;
+            bounds = SegmentBounds {
+                next_offset: bounds.next_offset,
+                end: 0,
+                first_timestamp: None,
+                tail_max_timestamp: None,
+            };
+            self.segments.push(segment.clone());
+        }
+
+        let base_offset = bounds.next_offset;
+        segment
+            .append(&bounds, &indexes, leader_epoch, records, headers)
+            .await?;
+        Ok(AppendInfo {
+            base_offset,
+            log_start_offset: self.log_start_offset().await?,
+        })
+    }
+
+    fn should_roll(
+        &self,
+        segment: &LogSegment,
+        bounds: &SegmentBounds,
+        append_size: usize,
+        headers: &[(usize, BatchHeader)],
+    ) -> bool {
+        if bounds.end == 0 {
+            return false;
+        }
+        let records_count: i64 = headers
+            .iter()
+            .map(|(_, header)| header.last_offset_delta as i64 + 1)
+            .sum();
+        let max_timestamp = headers
+            .iter()
+            .map(|(_, header)| header.max_timestamp)
+            .max()
+            .unwrap_or_default();
+        bounds.end + append_size > self.config.segment_bytes
+            || bounds
+                .first_timestamp
+                .is_some_and(|first| max_timestamp - first > self.config.segment_ms)
+            // index entries store offsets relative to the segment as i32
+            || bounds.next_offset + records_count - segment.base_offset > i32::MAX as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::batch;
+    use bytes::BufMut;
+
+    fn batch_bytes(records: i32, timestamp: i64) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.put_i64(0); // base offset
+        data.put_i32(100 - batch::BATCH_LENGTH_OFFSET as i32); // batch length
+        data.put_i32(0); // partition leader epoch
+        data.put_i8(2); // magic
+        data.put_u32(0); // crc
+        data.put_i16(0); // attributes
+        data.put_i32(records - 1); // last offset delta
+        data.put_i64(timestamp); // base timestamp
+        data.put_i64(timestamp); // max timestamp
+        data.put_i64(-1); // producer id
+        data.put_i16(-1); // producer epoch
+        data.put_i32(-1); // base sequence
+        data.put_i32(records); // records count
+        data.resize(100, 0);
+        batch::set_crc(&mut data);
+        data
+    }
+
+    #[tokio::test]
+    async fn rolls_segments_and_routes_reads() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("log-test-{}", uuid::Uuid::new_v4()));
+        let config = LogConfig {
+            segment_bytes: 350,
+            segment_ms: 1000,
+        };
+
+        for timestamp in [0, 10, 20, 30, 5000] {
+            let mut log = Log::open(&dir, config.clone()).await?;
+            let mut records = batch_bytes(2, timestamp);
+            let headers = batch::validate_batches(&records)?;
+            log.append(0, &mut records, &headers).await?;
+        }
+
+        let log = Log::open(&dir, config).await?;
+        // three batches fit in the first segment, the last one rolls by time
+        let base_offsets: Vec<i64> = log.segments.iter().map(|s| s.base_offset).collect();
+        assert_eq!(base_offsets, vec![0, 6, 8]);
+        assert_eq!(log.high_watermark().await?, 10);
+
+        let read = log.read(5, 1000, true).await?;
+        let records = read.records.unwrap();
+        assert_eq!(batch::BatchHeader::parse(&records)?.base_offset, 4);
+        assert_eq!(records.len(), 100); // reads stop at the segment end
+
+        assert!(log.read(11, 1000, true).await?.records.is_none());
+        let mut size = 0;
+        for segment in &log.segments {
+            size += segment.size().await?;
+        }
+        assert_eq!(size, 500);
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checks_segment_indexes_once() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!("log-test-{}", uuid::Uuid::new_v4()));
+        let config = LogConfig {
+            segment_bytes: usize::MAX,
+            segment_ms: i64::MAX,
+        };
+        let mut log = Log::open(&dir, config.clone()).await?;
+        for _ in 0..50 {
+            let mut records = batch_bytes(1, 0);
+            let headers = batch::validate_batches(&records)?;
+            log.append(0, &mut records, &headers).await?;
+        }
+
+        let log = Log::open(&dir, config.clone()).await?;
+        let segment = log.active_segment();
+        let size = segment.size().await?;
+        assert!(segment.try_open_indexes(size)?.is_some());
+        // an entry pointing past the end of the log is only caught by the
+        // first open of a segment's indexes
+        let mut corrupt = Vec::new();
+        corrupt.put_i32(1);
+        corrupt.put_u32(u32::MAX);
+        std::fs::write(index::offset_index_path(&segment.path), corrupt)?;
+        assert!(segment.try_open_indexes(size)?.is_some());
+        let reopened = Log::open(&dir, config).await?;
+        assert!(reopened.active_segment().try_open_indexes(size)?.is_none());
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,429 @@
+pub mod batch;
+pub mod compression;
+pub mod index;
+pub mod log;
+pub mod producer_state;
+pub mod purgatory;
+pub mod segment;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+};
+
+use crate::config;
+
+use tokio::sync::Mutex;
+
+use log::{Log, LogConfig};
+use producer_state::ProducerStateManager;
+use segment::LogSegment;
+
+pub static METADATA_TOPIC: &str = "__cluster_metadata";
+
+// Suffix of partition directories waiting to be removed
+static DELETE_DIR_SUFFIX: &str = "-delete";
+
+// Appends read the current end of the log to assign offsets, so they must
+// not interleave.
+static APPEND_LOCK: Mutex<()> = Mutex::const_new(());
+
+// Producer state of the partitions read or appended to since startup, by
+// directory. Appends take a partition's state out while they run, so it is
+// only put in or taken out while holding `APPEND_LOCK`.
+static PRODUCER_STATES: std::sync::Mutex<BTreeMap<PathBuf, ProducerStateManager>> =
+    std::sync::Mutex::new(BTreeMap::new());
+
+// Logs opened since startup, by directory, so reads and appends do not list
+// the directory again. Segments only change when an append rolls one,
+// which puts the log back, or when the partition is deleted, so logs are
+// only put in or taken out while holding `APPEND_LOCK`.
+static LOGS: std::sync::Mutex<BTreeMap<PathBuf, Log>> = std::sync::Mutex::new(BTreeMap::new());
+
+/// The directory of a partition, in whichever of `log.dirs` holds it.
+pub fn partition_dir(topic_name: &str, partition_index: i32) -> PathBuf {
+    find_partition_dir(
+        &config::get().log_dirs,
+        &format!("{}-{}", topic_name, partition_index),
+    )
+}
+
+// A partition stays in the log directory it was created in, even when
+// `log.dirs` is reordered. New partitions are spread over the directories
+// by name, so where a partition will be created is known before it is.
+fn find_partition_dir(log_dirs: &[PathBuf], name: &str) -> PathBuf {
+    if let Some(dir) = log_dirs
+        .iter()
+        .map(|log_dir| log_dir.join(name))
+        .find(|dir| dir.exists())
+    {
+        return dir;
+    }
+    let index = crc32c::crc32c(name.as_bytes()) as usize % log_dirs.len();
+    log_dirs[index].join(name)
+}
+
+fn metadata_log_dir() -> PathBuf {
+    config::get()
+        .metadata_log_dir()
+        .join(format!("{}-0", METADATA_TOPIC))
+}
+
+/// The metadata log lives in `metadata.log.dir`, apart from the topics.
+pub async fn open_metadata_log() -> std::io::Result<Log> {
+    open_log(&metadata_log_dir()).await
+}
+
+pub async fn open_partition_log(topic_name: &str, partition_index: i32) -> std::io::Result<Log> {
+    open_log(&partition_dir(topic_name, partition_index)).await
+}
+
+async fn open_log(dir: &Path) -> std::io::Result<Log> {
+    if let Some(log) = cached_log(dir) {
+        return Ok(log);
+    }
+    // a log opened while its partition is deleted must not be cached
+    let _guard = APPEND_LOCK.lock().await;
+    open_log_locked(dir, LogConfig::default()).await
+}
+
+// Like `open_log` with `config`, for callers holding `APPEND_LOCK`
+async fn open_log_locked(dir: &Path, config: LogConfig) -> std::io::Result<Log> {
+    if let Some(log) = cached_log(dir) {
+        return Ok(Log { config, ..log });
+    }
+    let log = Log::open(dir, config).await?;
+    put_log(log.clone());
+    Ok(log)
+}
+
+fn cached_log(dir: &Path) -> Option<Log> {
+    LOGS.lock().expect("logs lock poisoned").get(dir).cloned()
+}
+
+fn put_log(log: Log) {
+    LOGS.lock()
+        .expect("logs lock poisoned")
+        .insert(log.dir.clone(), log);
+}
+
+/// Renames the partition directory to `<topic>-<partition>.<id>-delete`
+/// and removes it in the background, like Kafka does for deleted topics.
+pub async fn delete_partition(topic_name: &str, partition_index: i32) -> std::io::Result<()> {
+    let dir = partition_dir(topic_name, partition_index);
+    let deleted = dir.with_file_name(format!(
+        "{}-{}.{}{}",
+        topic_name,
+        partition_index,
+        uuid::Uuid::new_v4().simple(),
+        DELETE_DIR_SUFFIX
+    ));
+    {
+        // no append may recreate the directory halfway
+        let _guard = APPEND_LOCK.lock().await;
+        take_producer_state(&dir);
+        LOGS.lock().expect("logs lock poisoned").remove(&dir);
+        match tokio::fs::rename(&dir, &deleted).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            renamed => renamed?,
+        }
+    }
+    tokio::spawn(remove_deleted_dir(deleted));
+    Ok(())
+}
+
+/// Removes the directories of deleted partitions a previous run did not
+/// get to.
+pub async fn remove_deleted_partitions() -> std::io::Result<()> {
+    for log_dir in &config::get().log_dirs {
+        let mut entries = match tokio::fs::read_dir(log_dir).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            entries => entries?,
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if entry
+                .file_name()
+                .to_string_lossy()
+                .ends_with(DELETE_DIR_SUFFIX)
+            {
+                tokio::spawn(remove_deleted_dir(entry.path()));
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn remove_deleted_dir(dir: PathBuf) {
+    if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+        eprintln!("failed to remove {}: {}", dir.display(), e);
+    }
+}
+
+/// Reads `meta.properties`, which `kafka-storage format` writes into the
+/// metadata log directory with the cluster id and node id.
+pub async fn read_meta_properties() -> HashMap<String, String> {
+    let path = config::get().metadata_log_dir().join("meta.properties");
+    let content = tokio::fs::read_to_string(path).await.unwrap_or_default();
+    config::parse_properties(&content)
+}
+
+/// Result of appending record batches to a partition log.
+#[derive(Debug)]
+pub struct AppendInfo {
+    pub base_offset: i64,
+    pub log_start_offset: i64,
+}
+
+/// Records read from a partition log for a fetch.
+#[derive(Debug)]
+pub struct PartitionRead {
+    pub high_watermark: i64,
+    // the high watermark as far as [`Log::read`] knows
+    pub last_stable_offset: i64,
+    pub log_start_offset: i64,
+    // None when the fetch offset is outside of the log
+    pub records: Option<Vec<u8>>,
+    // of the records read, only filled for read_committed fetches
+    pub aborted_transactions: Vec<index::AbortedTxn>,
+}
+
+/// Assigns offsets to already validated record batches and appends them to
+/// the partition log. A retry of a batch an idempotent producer already
+/// appended is not appended again, the earlier offsets are returned
+/// instead. Sequence errors are [`producer_state::ProducerStateError`]s.
+pub async fn append_partition_records(
+    topic_name: &str,
+    partition_index: i32,
+    leader_epoch: i32,
+    config: LogConfig,
+    records: &mut [u8],
+    headers: &[(usize, batch::BatchHeader)],
+) -> anyhow::Result<AppendInfo> {
+    let _guard = APPEND_LOCK.lock().await;
+    let dir = partition_dir(topic_name, partition_index);
+    let mut log = open_log_locked(&dir, config).await?;
+    // taken out while appending, a failed append reloads it from disk
+    let mut producers = match take_producer_state(&dir) {
+        Some(producers) => producers,
+        None => ProducerStateManager::load(&log).await?,
+    };
+    let duplicate = match producers.check_append(headers) {
+        Ok(duplicate) => duplicate,
+        Err(e) => {
+            put_producer_state(dir, producers);
+            return Err(e.into());
+        }
+    };
+    if let Some(duplicate) = duplicate {
+        put_producer_state(dir, producers);
+        return Ok(AppendInfo {
+            base_offset: duplicate.first_offset,
+            log_start_offset: log.log_start_offset().await?,
+        });
+    }
+    let segments = log.segments.len();
+    let appended = log.append(leader_epoch, records, headers).await;
+    let rolled = log.segments.len() > segments;
+    if rolled {
+        // even when the append then failed, the new segment is there
+        put_log(log.clone());
+    }
+    let info = appended?;
+    if rolled {
+        // like Kafka, at the base offset of the new segment, so a load only
+        // replays the active segment
+        producers.take_snapshot().await?;
+    }
+    // the batches now carry the offsets they were given
+    let mut aborted = Vec::new();
+    for (position, header) in batch::read_batch_headers(records) {
+        let marker = batch::read_control_marker(&records[position..position + header.size()]);
+        aborted.extend(producers.apply(&header, marker));
+    }
+    // a replay of the log indexes them again if this fails
+    log.append_aborted_txns(&aborted)?;
+    put_producer_state(dir.clone(), producers);
+    purgatory::wake_fetches(&dir);
+    Ok(info)
+}
+
+fn take_producer_state(dir: &Path) -> Option<ProducerStateManager> {
+    PRODUCER_STATES
+        .lock()
+        .expect("producer states lock poisoned")
+        .remove(dir)
+}
+
+fn put_producer_state(dir: PathBuf, producers: ProducerStateManager) {
+    PRODUCER_STATES
+        .lock()
+        .expect("producer states lock poisoned")
+        .insert(dir, producers);
+}
+
+/// Appends record batches the broker built itself to the metadata log.
+pub async fn append_metadata_records(
+    leader_epoch: i32,
+    records: &mut [u8],
+    headers: &[(usize, batch::BatchHeader)],
+) -> anyhow::Result<AppendInfo> {
+    let _guard = APPEND_LOCK.lock().await;
+    let mut log = open_log_locked(&metadata_log_dir(), LogConfig::default()).await?;
+    let segments = log.segments.len();
+    let appended = log.append(leader_epoch, records, headers).await;
+    if log.segments.len() > segments {
+        put_log(log);
+    }
+    appended
+}
+
+/// See [`Log::read`]. With `read_committed` the records stop at the last
+/// stable offset and come with the aborted transactions among them.
+pub async fn read_partition_records(
+    topic_name: &str,
+    partition_index: i32,
+    fetch_offset: i64,
+    max_bytes: usize,
+    min_one_batch: bool,
+    read_committed: bool,
+) -> anyhow::Result<PartitionRead> {
+    let log = open_partition_log(topic_name, partition_index).await?;
+    let mut read = log.read(fetch_offset, max_bytes, min_one_batch).await?;
+    // the log may have grown since the high watermark was read
+    read.last_stable_offset = last_stable_offset(topic_name, partition_index)
+        .await?
+        .min(read.high_watermark);
+    if let Some(records) = read.records.as_mut().filter(|_| read_committed) {
+        let headers = batch::read_batch_headers(records);
+        let stable: Vec<_> = headers
+            .iter()
+            .take_while(|(_, header)| header.base_offset < read.last_stable_offset)
+            .collect();
+        records.truncate(
+            stable
+                .last()
+                .map_or(0, |(position, header)| position + header.size()),
+        );
+        if let Some((_, last)) = stable.last() {
+            read.aborted_transactions = log.aborted_txns(fetch_offset, last.next_offset())?;
+        }
+    }
+    Ok(read)
+}
+
+/// Returns the offset of the first record of the oldest open transaction
+/// of a partition, the high watermark when none is open.
+pub async fn last_stable_offset(topic_name: &str, partition_index: i32) -> anyhow::Result<i64> {
+    let dir = partition_dir(topic_name, partition_index);
+    let cached = PRODUCER_STATES
+        .lock()
+        .expect("producer states lock poisoned")
+        .get(&dir)
+        .map(ProducerStateManager::last_stable_offset);
+    if let Some(last_stable_offset) = cached {
+        return Ok(last_stable_offset);
+    }
+    // loading replays the end of the log, which must not race with appends
+    let _guard = APPEND_LOCK.lock().await;
+    let producers = match take_producer_state(&dir) {
+        Some(producers) => producers,
+        None => {
+            ProducerStateManager::load(&open_log_locked(&dir, LogConfig::default()).await?).await?
+        }
+    };
+    let last_stable_offset = producers.last_stable_offset();
+    put_producer_state(dir, producers);
+    Ok(last_stable_offset)
+}
+
+/// Opens the indexes of a segment, rebuilding them if needed.
+async fn open_indexes(
+    segment: &LogSegment,
+    file_size: usize,
+) -> anyhow::Result<(index::OffsetIndex, index::TimeIndex)> {
+    if let Some(indexes) = segment.try_open_indexes(file_size)? {
+        return Ok(indexes);
+    }
+    // rebuilding must not race with an append to the same segment
+    let _guard = APPEND_LOCK.lock().await;
+    Ok(segment.open_indexes(segment.size().await?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BufMut;
+
+    fn batch_bytes() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.put_i64(0); // base offset
+        data.put_i32(100 - batch::BATCH_LENGTH_OFFSET as i32); // batch length
+        data.put_i32(0); // partition leader epoch
+        data.put_i8(2); // magic
+        data.put_u32(0); // crc
+        data.put_i16(0); // attributes
+        data.put_i32(0); // last offset delta
+        data.put_i64(0); // base timestamp
+        data.put_i64(0); // max timestamp
+        data.put_i64(-1); // producer id
+        data.put_i16(-1); // producer epoch
+        data.put_i32(-1); // base sequence
+        data.put_i32(1); // records count
+        data.resize(100, 0);
+        batch::set_crc(&mut data);
+        data
+    }
+
+    #[tokio::test]
+    async fn caches_logs_until_their_partition_is_deleted() -> anyhow::Result<()> {
+        let topic = "cached-log";
+        let dir = partition_dir(topic, 0);
+        let config = LogConfig {
+            segment_bytes: 150,
+            segment_ms: i64::MAX,
+        };
+        for _ in 0..2 {
+            let mut records = batch_bytes();
+            let headers = batch::validate_batches(&records)?;
+            append_partition_records(topic, 0, 0, config.clone(), &mut records, &headers).await?;
+        }
+
+        // the second append rolled a segment, the cached log has it
+        assert_eq!(cached_log(&dir).unwrap().segments.len(), 2);
+        let log = open_partition_log(topic, 0).await?;
+        assert_eq!(log.high_watermark().await?, 2);
+
+        delete_partition(topic, 0).await?;
+        assert!(cached_log(&dir).is_none());
+        let log = open_partition_log(topic, 0).await?;
+        assert_eq!(log.high_watermark().await?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn finds_partitions_in_any_log_dir() -> std::io::Result<()> {
+        let root = std::env::temp_dir().join(format!("kafka-log-dirs-{}", std::process::id()));
+        let log_dirs: Vec<PathBuf> = (0..3).map(|i| root.join(i.to_string())).collect();
+
+        // a new partition always goes to the same directory
+        let new_dir = find_partition_dir(&log_dirs, "spread-0");
+        assert!(log_dirs
+            .iter()
+            .any(|log_dir| new_dir.parent() == Some(log_dir)));
+        assert_eq!(find_partition_dir(&log_dirs, "spread-0"), new_dir);
+
+        // an existing one is found wherever it is
+        let existing = log_dirs
+            .iter()
+            .map(|log_dir| log_dir.join("spread-1"))
+            .find(|dir| *dir != find_partition_dir(&log_dirs, "spread-1"))
+            .unwrap();
+        std::fs::create_dir_all(&existing)?;
+        assert_eq!(find_partition_dir(&log_dirs, "spread-1"), existing);
+        let mut reordered = log_dirs.clone();
+        reordered.reverse();
+        assert_eq!(find_partition_dir(&reordered, "spread-1"), existing);
+
+        std::fs::remove_dir_all(&root)
+    }
+}
@@ -0,0 +1,498 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use bytes::{Buf, BufMut};
+
+use super::{
+    batch::{self, BatchHeader, ControlMarker},
+    index::AbortedTxn,
+    log::Log,
+};
+
+/// Kafka error codes of a produce the producer state rejects.
+pub const OUT_OF_ORDER_SEQUENCE_NUMBER: i16 = 45;
+pub const INVALID_PRODUCER_EPOCH: i16 = 47;
+
+// Batches remembered per producer, the most a producer may have in flight
+const DEDUP_WINDOW: usize = 5;
+
+static SNAPSHOT_VERSION: i16 = 1;
+static SNAPSHOT_SUFFIX: &str = ".snapshot";
+// producer_id, producer_epoch, last_sequence, last_offset, offset_delta,
+// timestamp, coordinator_epoch and current_txn_first_offset
+static SNAPSHOT_ENTRY_SIZE: usize = 46;
+
+// How much of the log is read at once while replaying it
+static REPLAY_READ_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProducerStateError {
+    #[error("out of order sequence number for producer {producer_id}: expected {expected}, got {received}")]
+    OutOfOrderSequence {
+        producer_id: i64,
+        expected: i32,
+        received: i32,
+    },
+    #[error("producer {producer_id} epoch {received} is older than the current epoch {current}")]
+    InvalidProducerEpoch {
+        producer_id: i64,
+        current: i16,
+        received: i16,
+    },
+}
+
+impl ProducerStateError {
+    pub fn error_code(&self) -> i16 {
+        match self {
+            ProducerStateError::OutOfOrderSequence { .. } => OUT_OF_ORDER_SEQUENCE_NUMBER,
+            ProducerStateError::InvalidProducerEpoch { .. } => INVALID_PRODUCER_EPOCH,
+        }
+    }
+}
+
+/// A batch an idempotent producer appended, kept to recognize retries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchMetadata {
+    pub first_sequence: i32,
+    pub last_sequence: i32,
+    pub first_offset: i64,
+    pub last_offset: i64,
+    pub timestamp: i64,
+}
+
+impl BatchMetadata {
+    fn from_header(header: &BatchHeader) -> BatchMetadata {
+        BatchMetadata {
+            first_sequence: header.base_sequence,
+            last_sequence: last_sequence(header),
+            first_offset: header.base_offset,
+            last_offset: header.last_offset(),
+            timestamp: header.max_timestamp,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProducerStateEntry {
+    pub producer_epoch: i16,
+    // newest last, at most `DEDUP_WINDOW`
+    pub batches: VecDeque<BatchMetadata>,
+    // offset of the first batch of the producer's open transaction
+    pub current_txn_first_offset: Option<i64>,
+}
+
+impl ProducerStateEntry {
+    fn last_sequence(&self) -> Option<i32> {
+        self.batches.back().map(|batch| batch.last_sequence)
+    }
+}
+
+/// The idempotent and transactional producers of one partition. It is saved as
+/// `<next_offset>.snapshot` next to the segments when a segment rolls, and
+/// batches appended after the snapshot are replayed when it is loaded.
+#[derive(Debug)]
+pub struct ProducerStateManager {
+    dir: PathBuf,
+    producers: HashMap<i64, ProducerStateEntry>,
+    // offset after the last batch applied
+    next_offset: i64,
+}
+
+impl ProducerStateManager {
+    /// Loads the latest snapshot of the log's partition and replays the
+    /// batches that came after it. Callers must hold the append lock.
+    pub async fn load(log: &Log) -> anyhow::Result<ProducerStateManager> {
+        let mut state = ProducerStateManager {
+            dir: log.dir.clone(),
+            producers: HashMap::new(),
+            next_offset: 0,
+        };
+        if let Some((next_offset, path)) = latest_snapshot(&log.dir).await? {
+            match decode_snapshot(&tokio::fs::read(&path).await?) {
+                Some(producers) => {
+                    state.producers = producers;
+                    state.next_offset = next_offset;
+                }
+                None => eprintln!("ignoring corrupt producer snapshot {}", path.display()),
+            }
+        }
+
+        // reads go through the segment indexes, and rebuilding one would
+        // wait for the append lock the caller holds
+        let replayed = log
+            .segments
+            .partition_point(|segment| segment.base_offset <= state.next_offset)
+            .saturating_sub(1);
+        for segment in &log.segments[replayed..] {
+            if tokio::fs::try_exists(&segment.path).await? {
+                segment.open_indexes(segment.size().await?)?;
+            }
+        }
+
+        let high_watermark = log.high_watermark().await?;
+        let mut aborted = Vec::new();
+        while state.next_offset < high_watermark {
+            let read = log.read(state.next_offset, REPLAY_READ_BYTES, true).await?;
+            let records = read.records.unwrap_or_default();
+            let headers = batch::read_batch_headers(&records);
+            if headers.is_empty() {
+                break;
+            }
+            for (position, header) in &headers {
+                if header.last_offset() >= state.next_offset {
+                    let batch = &records[*position..position + header.size()];
+                    aborted.extend(state.apply(header, batch::read_control_marker(batch)));
+                }
+            }
+        }
+        // an append may have stopped before indexing its aborts
+        log.append_aborted_txns(&aborted)?;
+        Ok(state)
+    }
+
+    /// Checks the sequence numbers of batches about to be appended. Returns
+    /// the earlier append of a batch that is a retry, which must not be
+    /// appended again.
+    pub fn check_append(
+        &self,
+        headers: &[(usize, BatchHeader)],
+    ) -> Result<Option<BatchMetadata>, ProducerStateError> {
+        // (epoch, last sequence) of producers with more than one batch here
+        let mut pending: HashMap<i64, (i16, i32)> = HashMap::new();
+        for (_, header) in headers {
+            if header.producer_id < 0 {
+                continue;
+            }
+            let entry = self.producers.get(&header.producer_id);
+            if let Some(entry) = entry.filter(|e| e.producer_epoch == header.producer_epoch) {
+                let duplicate = entry.batches.iter().find(|batch| {
+                    batch.first_sequence == header.base_sequence
+                        && batch.last_sequence == last_sequence(header)
+                });
+                if let Some(duplicate) = duplicate {
+                    return Ok(Some(duplicate.clone()));
+                }
+            }
+
+            let current = pending
+                .get(&header.producer_id)
+                .copied()
+                .or_else(|| entry.map(|e| (e.producer_epoch, e.last_sequence().unwrap_or(-1))));
+            if let Some((epoch, last_sequence)) = current {
+                if header.producer_epoch < epoch {
+                    return Err(ProducerStateError::InvalidProducerEpoch {
+                        producer_id: header.producer_id,
+                        current: epoch,
+                        received: header.producer_epoch,
+                    });
+                }
+                // batches the broker writes for a producer, like transaction
+                // markers, carry no sequence
+                if header.base_sequence < 0 {
+                    continue;
+                }
+                // a new epoch starts its sequence over
+                let expected = if header.producer_epoch > epoch {
+                    0
+                } else {
+                    next_sequence(last_sequence)
+                };
+                if header.base_sequence != expected {
+                    return Err(ProducerStateError::OutOfOrderSequence {
+                        producer_id: header.producer_id,
+                        expected,
+                        received: header.base_sequence,
+                    });
+                }
+            }
+            pending.insert(
+                header.producer_id,
+                (header.producer_epoch, last_sequence(header)),
+            );
+        }
+        Ok(None)
+    }
+
+    /// Records an appended batch, with its offsets already assigned.
+    /// `marker` is the outcome a control batch carries. Returns the
+    /// producer's transaction when the batch aborts it.
+    pub fn apply(
+        &mut self,
+        header: &BatchHeader,
+        marker: Option<ControlMarker>,
+    ) -> Option<AbortedTxn> {
+        self.next_offset = header.next_offset();
+        if header.producer_id < 0 {
+            return None;
+        }
+        let entry = self.producers.entry(header.producer_id).or_default();
+        if header.producer_epoch != entry.producer_epoch {
+            entry.producer_epoch = header.producer_epoch;
+            entry.batches.clear();
+        }
+
+        let mut aborted = None;
+        if header.is_control() {
+            let first_offset = entry.current_txn_first_offset.take();
+            if let Some(first_offset) =
+                first_offset.filter(|_| marker == Some(ControlMarker::Abort))
+            {
+                aborted = Some(AbortedTxn {
+                    producer_id: header.producer_id,
+                    first_offset,
+                    last_offset: header.base_offset,
+                    last_stable_offset: 0,
+                });
+            }
+        } else if header.is_transactional() && entry.current_txn_first_offset.is_none() {
+            entry.current_txn_first_offset = Some(header.base_offset);
+        }
+
+        if header.base_sequence >= 0 {
+            if entry.batches.len() == DEDUP_WINDOW {
+                entry.batches.pop_front();
+            }
+            entry.batches.push_back(BatchMetadata::from_header(header));
+        }
+        // the offset stable once this transaction is complete
+        aborted.map(|txn| AbortedTxn {
+            last_stable_offset: self.last_stable_offset(),
+            ..txn
+        })
+    }
+
+    /// Returns the first offset of the oldest open transaction, or the end
+    /// of the log when every transaction is complete.
+    pub fn last_stable_offset(&self) -> i64 {
+        self.producers
+            .values()
+            .filter_map(|entry| entry.current_txn_first_offset)
+            .min()
+            .unwrap_or(self.next_offset)
+    }
+
+    /// Writes the state as `<next_offset>.snapshot` and removes the older
+    /// snapshots.
+    pub async fn take_snapshot(&self) -> std::io::Result<()> {
+        let path = self.dir.join(snapshot_file_name(self.next_offset));
+        let temporary = path.with_extension("snapshot.tmp");
+        tokio::fs::write(&temporary, encode_snapshot(&self.producers)).await?;
+        tokio::fs::rename(&temporary, &path).await?;
+
+        let mut entries = tokio::fs::read_dir(&self.dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if snapshot_offset(&entry.path()).is_some_and(|offset| offset < self.next_offset) {
+                tokio::fs::remove_file(entry.path()).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn last_sequence(header: &BatchHeader) -> i32 {
+    // sequence numbers wrap around to 0 after i32::MAX
+    ((header.base_sequence as i64 + header.last_offset_delta as i64) % (i32::MAX as i64 + 1)) as i32
+}
+
+fn next_sequence(sequence: i32) -> i32 {
+    if sequence == i32::MAX {
+        0
+    } else {
+        sequence + 1
+    }
+}
+
+pub fn snapshot_file_name(next_offset: i64) -> String {
+    format!("{:020}{}", next_offset, SNAPSHOT_SUFFIX)
+}
+
+// Recognizes `<next_offset>.snapshot` file names
+fn snapshot_offset(path: &Path) -> Option<i64> {
+    path.file_name()?
+        .to_str()?
+        .strip_suffix(SNAPSHOT_SUFFIX)?
+        .parse()
+        .ok()
+}
+
+async fn latest_snapshot(dir: &Path) -> std::io::Result<Option<(i64, PathBuf)>> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        entries => entries?,
+    };
+    let mut latest: Option<(i64, PathBuf)> = None;
+    while let Some(entry) = entries.next_entry().await? {
+        if let Some(offset) = snapshot_offset(&entry.path()) {
+            if latest.as_ref().map_or(true, |(latest, _)| offset > *latest) {
+                latest = Some((offset, entry.path()));
+            }
+        }
+    }
+    Ok(latest)
+}
+
+// Kafka's snapshot layout: version, the CRC32C of the rest, then the last
+// batch of each producer. Only that batch survives a restart.
+fn encode_snapshot(producers: &HashMap<i64, ProducerStateEntry>) -> Vec<u8> {
+    let mut entries = Vec::new();
+    let last_batches: Vec<_> = producers
+        .iter()
+        .filter_map(|(producer_id, entry)| Some((producer_id, entry, entry.batches.back()?)))
+        .collect();
+    entries.put_i32(last_batches.len() as i32);
+    for (producer_id, entry, batch) in last_batches {
+        entries.put_i64(*producer_id); // producer_id
+        entries.put_i16(entry.producer_epoch); // producer_epoch
+        entries.put_i32(batch.last_sequence); // last_sequence
+        entries.put_i64(batch.last_offset); // last_offset
+        entries.put_i32((batch.last_offset - batch.first_offset) as i32); // offset_delta
+        entries.put_i64(batch.timestamp); // timestamp
+        entries.put_i32(-1); // coordinator_epoch
+        entries.put_i64(entry.current_txn_first_offset.unwrap_or(-1)); // current_txn_first_offset
+    }
+
+    let mut data = Vec::new();
+    data.put_i16(SNAPSHOT_VERSION); // version
+    data.put_u32(crc32c::crc32c(&entries)); // crc
+    data.extend(entries);
+    data
+}
+
+fn decode_snapshot(mut data: &[u8]) -> Option<HashMap<i64, ProducerStateEntry>> {
+    if data.remaining() < 10 || data.get_i16() != SNAPSHOT_VERSION {
+        return None;
+    }
+    if data.get_u32() != crc32c::crc32c(data) {
+        return None;
+    }
+    let count = data.get_i32();
+    if count < 0 || data.remaining() != count as usize * SNAPSHOT_ENTRY_SIZE {
+        return None;
+    }
+    let mut producers = HashMap::new();
+    for _ in 0..count {
+        let producer_id = data.get_i64();
+        let producer_epoch = data.get_i16();
+        let last_sequence = data.get_i32();
+        let last_offset = data.get_i64();
+        let offset_delta = data.get_i32();
+        let timestamp = data.get_i64();
+        let _coordinator_epoch = data.get_i32();
+        let current_txn_first_offset = data.get_i64();
+        let batch = BatchMetadata {
+            first_sequence: (last_sequence as i64 - offset_delta as i64).max(0) as i32,
+            last_sequence,
+            first_offset: last_offset - offset_delta as i64,
+            last_offset,
+            timestamp,
+        };
+        producers.insert(
+            producer_id,
+            ProducerStateEntry {
+                producer_epoch,
+                batches: VecDeque::from([batch]),
+                current_txn_first_offset: (current_txn_first_offset >= 0)
+                    .then_some(current_txn_first_offset),
+            },
+        );
+    }
+    Some(producers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(producer_epoch: i16, base_sequence: i32, base_offset: i64) -> (usize, BatchHeader) {
+        let mut data = batch::build_batch(&[(None, Some(b"a")), (None, Some(b"b"))], 0);
+        batch::set_base_offset(&mut data, base_offset);
+        let mut header = BatchHeader::parse(&data).unwrap();
+        header.producer_id = 7;
+        header.producer_epoch = producer_epoch;
+        header.base_sequence = base_sequence;
+        (0, header)
+    }
+
+    #[test]
+    fn deduplicates_retries_and_rejects_gaps() {
+        let mut state = ProducerStateManager {
+            dir: PathBuf::new(),
+            producers: HashMap::new(),
+            next_offset: 0,
+        };
+        for (sequence, offset) in [(0, 0), (2, 2)] {
+            let batch = header(0, sequence, offset);
+            assert_eq!(
+                state.check_append(std::slice::from_ref(&batch)).unwrap(),
+                None
+            );
+            state.apply(&batch.1, None);
+        }
+
+        let retry = state.check_append(&[header(0, 0, 4)]).unwrap().unwrap();
+        assert_eq!((retry.first_offset, retry.last_offset), (0, 1));
+        assert!(matches!(
+            state.check_append(&[header(0, 6, 4)]),
+            Err(ProducerStateError::OutOfOrderSequence { expected: 4, .. })
+        ));
+        // two batches in one request follow each other
+        assert_eq!(
+            state
+                .check_append(&[header(0, 4, 4), header(0, 6, 6)])
+                .unwrap(),
+            None
+        );
+        assert!(state.check_append(&[header(1, 0, 4)]).unwrap().is_none());
+        assert!(state.check_append(&[header(1, 3, 4)]).is_err());
+
+        state.apply(&header(1, 0, 4).1, None);
+        assert!(matches!(
+            state.check_append(&[header(0, 4, 6)]),
+            Err(ProducerStateError::InvalidProducerEpoch { current: 1, .. })
+        ));
+        let decoded = decode_snapshot(&encode_snapshot(&state.producers)).unwrap();
+        assert_eq!(
+            decoded[&7].batches,
+            [BatchMetadata::from_header(&header(1, 0, 4).1)]
+        );
+    }
+
+    #[test]
+    fn tracks_the_last_stable_offset() {
+        let mut state = ProducerStateManager {
+            dir: PathBuf::new(),
+            producers: HashMap::new(),
+            next_offset: 0,
+        };
+        let apply = |state: &mut ProducerStateManager, mut data: Vec<u8>, offset| {
+            batch::set_base_offset(&mut data, offset);
+            let header = BatchHeader::parse(&data).unwrap();
+            state.apply(&header, batch::read_control_marker(&data))
+        };
+        let transactional = |producer_id| {
+            batch::build_transactional_batch(&[(None, Some(b"a"))], 0, producer_id, 0)
+        };
+        let marker = |marker, producer_id| batch::build_control_batch(marker, producer_id, 0, 0, 0);
+
+        apply(&mut state, transactional(1), 0);
+        apply(&mut state, transactional(2), 1);
+        apply(&mut state, transactional(1), 2);
+        assert_eq!(state.last_stable_offset(), 0);
+
+        let aborted = apply(&mut state, marker(ControlMarker::Abort, 1), 3).unwrap();
+        // producer 2 still holds the log back
+        assert_eq!(
+            aborted,
+            AbortedTxn {
+                producer_id: 1,
+                first_offset: 0,
+                last_offset: 3,
+                last_stable_offset: 1,
+            }
+        );
+        assert!(apply(&mut state, marker(ControlMarker::Commit, 2), 4).is_none());
+        assert_eq!(state.last_stable_offset(), 5);
+    }
+}
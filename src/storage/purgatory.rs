@@ -0,0 +1,99 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use tokio::sync::Notify;
+
+use super::partition_dir;
+
+// Fetches waiting for records, by partition directory
+static WATCHERS: Mutex<BTreeMap<PathBuf, Vec<Arc<Notify>>>> = Mutex::new(BTreeMap::new());
+
+/// A fetch parked until records are appended to one of its partitions,
+/// like the delayed fetches of Kafka's fetch purgatory. It stops watching
+/// when dropped.
+pub struct FetchWatch {
+    dirs: Vec<PathBuf>,
+    notify: Arc<Notify>,
+}
+
+impl FetchWatch {
+    /// Starts watching before the partitions are read, so that an append
+    /// in between still wakes [`FetchWatch::appended`].
+    pub fn new<'a>(partitions: impl IntoIterator<Item = (&'a str, i32)>) -> FetchWatch {
+        let notify = Arc::new(Notify::new());
+        let dirs: Vec<PathBuf> = partitions
+            .into_iter()
+            .map(|(topic_name, partition_index)| partition_dir(topic_name, partition_index))
+            .collect();
+        let mut watchers = lock();
+        for dir in &dirs {
+            watchers
+                .entry(dir.clone())
+                .or_default()
+                .push(notify.clone());
+        }
+        FetchWatch { dirs, notify }
+    }
+
+    /// Completes once records were appended to a watched partition since
+    /// the watch started or the last call.
+    pub async fn appended(&self) {
+        self.notify.notified().await
+    }
+}
+
+impl Drop for FetchWatch {
+    fn drop(&mut self) {
+        let mut watchers = lock();
+        for dir in &self.dirs {
+            if let Some(notifies) = watchers.get_mut(dir) {
+                notifies.retain(|notify| !Arc::ptr_eq(notify, &self.notify));
+                if notifies.is_empty() {
+                    watchers.remove(dir);
+                }
+            }
+        }
+    }
+}
+
+/// Wakes the fetches watching a partition after an append.
+pub fn wake_fetches(dir: &Path) {
+    if let Some(notifies) = lock().get(dir) {
+        for notify in notifies {
+            notify.notify_one();
+        }
+    }
+}
+
+fn lock() -> MutexGuard<'static, BTreeMap<PathBuf, Vec<Arc<Notify>>>> {
+    WATCHERS.lock().expect("fetch watchers lock poisoned")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn wakes_watches_of_the_appended_partition() {
+        let watch = FetchWatch::new([("purgatory-a", 0), ("purgatory-b", 1)]);
+        let other = FetchWatch::new([("purgatory-a", 1)]);
+
+        // a wake before waiting is not lost
+        wake_fetches(&partition_dir("purgatory-b", 1));
+        let woken = tokio::time::timeout(Duration::from_secs(5), watch.appended()).await;
+        assert!(woken.is_ok());
+        let woken = tokio::time::timeout(Duration::from_millis(50), other.appended()).await;
+        assert!(woken.is_err());
+
+        drop(watch);
+        drop(other);
+        let watchers = lock();
+        assert!(!watchers.contains_key(&partition_dir("purgatory-a", 0)));
+        assert!(!watchers.contains_key(&partition_dir("purgatory-a", 1)));
+    }
+}
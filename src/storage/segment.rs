@@ -0,0 +1,306 @@
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+
+use super::{
+    batch::{self, BatchHeader},
+    index::{self, IndexWriter, OffsetIndex, TimeIndex},
+};
+
+/// One `<base_offset>.log` file of a partition together with its indexes.
+#[derive(Clone, Debug)]
+pub struct LogSegment {
+    pub base_offset: i64,
+    pub path: PathBuf,
+    // whether the index entries were checked against the log or rebuilt,
+    // shared by the clones of a cached log
+    indexes_checked: Arc<AtomicBool>,
+}
+
+/// Where a segment's valid batches start and end.
+#[derive(Debug)]
+pub struct SegmentBounds {
+    pub next_offset: i64,
+    // position after the last complete batch
+    pub end: usize,
+    // base timestamp of the first batch, used for time based rolling
+    pub first_timestamp: Option<i64>,
+    // largest timestamp after the last offset index entry, and its offset
+    pub tail_max_timestamp: Option<(i64, i64)>,
+}
+
+impl LogSegment {
+    pub fn new(dir: &Path, base_offset: i64) -> LogSegment {
+        LogSegment {
+            base_offset,
+            path: dir.join(segment_file_name(base_offset)),
+            indexes_checked: Arc::default(),
+        }
+    }
+
+    /// Recognizes `<base_offset>.log` file names.
+    pub fn from_path(path: &Path) -> Option<LogSegment> {
+        if path.extension()? != "log" {
+            return None;
+        }
+        let base_offset = path.file_stem()?.to_str()?.parse().ok()?;
+        Some(LogSegment {
+            base_offset,
+            path: path.to_path_buf(),
+            indexes_checked: Arc::default(),
+        })
+    }
+
+    pub async fn size(&self) -> std::io::Result<usize> {
+        match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => Ok(metadata.len() as usize),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Opens the indexes, rebuilding them when missing or corrupt. Callers
+    /// must hold the append lock.
+    pub fn open_indexes(&self, file_size: usize) -> std::io::Result<(OffsetIndex, TimeIndex)> {
+        if let Some(indexes) = self.try_open_indexes(file_size)? {
+            return Ok(indexes);
+        }
+        let indexes = index::rebuild(&self.path, self.base_offset)?;
+        self.indexes_checked.store(true, Ordering::Relaxed);
+        Ok(indexes)
+    }
+
+    /// Opens the indexes without rebuilding, `None` if they need a rebuild.
+    /// Only the first open checks every entry: appends only add valid
+    /// entries and rebuilds replace whole files, so later opens trust them.
+    pub fn try_open_indexes(
+        &self,
+        file_size: usize,
+    ) -> std::io::Result<Option<(OffsetIndex, TimeIndex)>> {
+        if self.indexes_checked.load(Ordering::Relaxed) {
+            return index::open(&self.path, self.base_offset);
+        }
+        let indexes = index::open_checked(&self.path, self.base_offset, file_size)?;
+        if indexes.is_some() {
+            self.indexes_checked.store(true, Ordering::Relaxed);
+        }
+        Ok(indexes)
+    }
+
+    pub async fn bounds(
+        &self,
+        file: &mut File,
+        file_size: usize,
+        offset_index: &OffsetIndex,
+    ) -> std::io::Result<SegmentBounds> {
+        let first = read_header_at(file, 0, file_size).await?;
+        let log_start_offset = first.as_ref().map_or(self.base_offset, |h| h.base_offset);
+        let mut next_offset = log_start_offset;
+        let mut tail_max_timestamp: Option<(i64, i64)> = None;
+        let mut position = offset_index
+            .last_entry()
+            .map_or(0, |(_, position)| position);
+        while let Some(header) = read_header_at(file, position, file_size).await? {
+            if tail_max_timestamp.map_or(true, |(max, _)| header.max_timestamp > max) {
+                tail_max_timestamp = Some((header.max_timestamp, header.last_offset()));
+            }
+            next_offset = header.next_offset();
+            position += header.size();
+        }
+        Ok(SegmentBounds {
+            next_offset,
+            end: position,
+            first_timestamp: first.map(|h| h.base_timestamp),
+            tail_max_timestamp,
+        })
+    }
+
+    /// Reads whole batches starting with the one that contains
+    /// `fetch_offset`, stopping before `max_bytes` is exceeded.
+    pub async fn read(
+        &self,
+        file: &mut File,
+        end: usize,
+        offset_index: &OffsetIndex,
+        fetch_offset: i64,
+        max_bytes: usize,
+        min_one_batch: bool,
+    ) -> std::io::Result<Vec<u8>> {
+        // seek to the closest indexed batch, then skip to the one holding fetch_offset
+        let mut start = None;
+        let mut read_end = 0;
+        let mut position = offset_index.lookup(fetch_offset);
+        while let Some(header) = read_header_at(file, position, end).await? {
+            if header.last_offset() >= fetch_offset {
+                let start = *start.get_or_insert(position);
+                let size = position + header.size() - start;
+                if size > max_bytes && !(min_one_batch && read_end == 0) {
+                    break;
+                }
+                read_end = position + header.size();
+            }
+            position += header.size();
+        }
+
+        let mut records = Vec::new();
+        if let Some(start) = start.filter(|start| read_end > *start) {
+            records.resize(read_end - start, 0);
+            file.seek(SeekFrom::Start(start as u64)).await?;
+            file.read_exact(&mut records).await?;
+        }
+        Ok(records)
+    }
+
+    /// Returns the largest timestamp in the segment and the offset of the
+    /// record that carries it.
+    pub async fn max_timestamp(
+        &self,
+        file: &mut File,
+        bounds: &SegmentBounds,
+        indexes: &(OffsetIndex, TimeIndex),
+    ) -> std::io::Result<Option<(i64, i64)>> {
+        let Some((timestamp, offset)) = indexes.1.last_entry().max(bounds.tail_max_timestamp)
+        else {
+            return Ok(None);
+        };
+        self.find_record(file, bounds.end, &indexes.0, offset, timestamp)
+            .await
+    }
+
+    /// Returns the first record with a timestamp at or after `timestamp`
+    /// as `(timestamp, offset)`.
+    pub async fn find_timestamp(
+        &self,
+        file: &mut File,
+        bounds: &SegmentBounds,
+        indexes: &(OffsetIndex, TimeIndex),
+        timestamp: i64,
+    ) -> std::io::Result<Option<(i64, i64)>> {
+        let start_offset = indexes.1.lookup(timestamp);
+        let mut position = indexes.0.lookup(start_offset);
+        while let Some(header) = read_header_at(file, position, bounds.end).await? {
+            if header.last_offset() >= start_offset && header.max_timestamp >= timestamp {
+                let batch = read_batch_at(file, position, &header).await?;
+                return Ok(batch::record_timestamps(&batch)
+                    .and_then(|records| records.into_iter().find(|(ts, _)| *ts >= timestamp))
+                    // fall back to the whole batch when its records cannot be read
+                    .or(Some((header.max_timestamp, header.base_offset))));
+            }
+            position += header.size();
+        }
+        Ok(None)
+    }
+
+    // finds the record with `timestamp` in the batch holding `offset`
+    async fn find_record(
+        &self,
+        file: &mut File,
+        end: usize,
+        offset_index: &OffsetIndex,
+        offset: i64,
+        timestamp: i64,
+    ) -> std::io::Result<Option<(i64, i64)>> {
+        let mut position = offset_index.lookup(offset);
+        while let Some(header) = read_header_at(file, position, end).await? {
+            if header.last_offset() >= offset {
+                let batch = read_batch_at(file, position, &header).await?;
+                let record_offset = batch::record_timestamps(&batch)
+                    .and_then(|records| records.into_iter().find(|(ts, _)| *ts == timestamp))
+                    .map_or(offset, |(_, record_offset)| record_offset);
+                return Ok(Some((timestamp, record_offset)));
+            }
+            position += header.size();
+        }
+        Ok(None)
+    }
+
+    /// Assigns offsets from `next_offset` on to validated batches and writes
+    /// them at `end`, dropping anything after it. Returns the next offset.
+    pub async fn append(
+        &self,
+        bounds: &SegmentBounds,
+        indexes: &(OffsetIndex, TimeIndex),
+        leader_epoch: i32,
+        records: &mut [u8],
+        headers: &[(usize, BatchHeader)],
+    ) -> std::io::Result<i64> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&self.path)
+            .await?;
+        if (file.metadata().await?.len() as usize) > bounds.end {
+            // drop a batch that was only partially written before a crash
+            file.set_len(bounds.end as u64).await?;
+        }
+
+        let mut index_writer = IndexWriter::resume(
+            &indexes.0,
+            &indexes.1,
+            bounds.end,
+            bounds.tail_max_timestamp,
+        );
+        let mut next_offset = bounds.next_offset;
+        for (position, header) in headers {
+            let batch_bytes = &mut records[*position..*position + header.size()];
+            batch::set_base_offset(batch_bytes, next_offset);
+            batch::set_partition_leader_epoch(batch_bytes, leader_epoch);
+            let appended_header = BatchHeader {
+                base_offset: next_offset,
+                ..header.clone()
+            };
+            index_writer.append(self.base_offset, bounds.end + position, &appended_header);
+            next_offset = appended_header.next_offset();
+        }
+
+        file.seek(SeekFrom::Start(bounds.end as u64)).await?;
+        file.write_all(records).await?;
+        file.flush().await?;
+        index::append_entries(&self.path, &index_writer)?;
+        Ok(next_offset)
+    }
+}
+
+pub fn segment_file_name(base_offset: i64) -> String {
+    format!("{:020}.log", base_offset)
+}
+
+/// Reads the header of the batch at `position`, or `None` at the end of
+/// the segment or when the batch there is incomplete.
+pub async fn read_header_at(
+    file: &mut File,
+    position: usize,
+    file_size: usize,
+) -> std::io::Result<Option<BatchHeader>> {
+    if position + batch::BATCH_HEADER_SIZE > file_size {
+        return Ok(None);
+    }
+    let mut buf = [0u8; batch::BATCH_HEADER_SIZE];
+    file.seek(SeekFrom::Start(position as u64)).await?;
+    file.read_exact(&mut buf).await?;
+    Ok(BatchHeader::parse(&buf)
+        .ok()
+        .filter(|header| header.batch_length >= 0 && position + header.size() <= file_size))
+}
+
+async fn read_batch_at(
+    file: &mut File,
+    position: usize,
+    header: &BatchHeader,
+) -> std::io::Result<Vec<u8>> {
+    let mut batch = vec![0u8; header.size()];
+    file.seek(SeekFrom::Start(position as u64)).await?;
+    file.read_exact(&mut batch).await?;
+    Ok(batch)
+}